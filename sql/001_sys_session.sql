@@ -0,0 +1,12 @@
+-- 服务端会话, session.store = true 时使用
+CREATE TABLE IF NOT EXISTS sys_session (
+    session_id     varchar(64)  NOT NULL PRIMARY KEY,
+    username       varchar(64)  NOT NULL,
+    create_time    datetime     NOT NULL,
+    last_seen_time datetime     NOT NULL,
+    expire_time    datetime     NOT NULL,
+    client_ip      varchar(64)  NULL,
+    user_agent     varchar(255) NULL,
+    revoked        char(1)      NULL,
+    KEY idx_sys_session_username (username)
+) DEFAULT CHARSET = utf8mb4;
@@ -0,0 +1,48 @@
+-- 角色和权限, page 对应 /jpm/{page}/..., op 为 query/update/delete/admin, * 为通配
+CREATE TABLE IF NOT EXISTS sys_role (
+    id     bigint       NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    code   varchar(32)  NOT NULL,
+    name   varchar(64)  NOT NULL,
+    remark varchar(255) NULL,
+    UNIQUE KEY uk_sys_role_code (code)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE IF NOT EXISTS sys_role_permission (
+    id      bigint      NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    role_id bigint      NOT NULL,
+    page    varchar(64) NOT NULL,
+    op      varchar(16) NOT NULL,
+    UNIQUE KEY uk_sys_role_permission (role_id, page, op)
+) DEFAULT CHARSET = utf8mb4;
+
+-- project_id 为空时是全局角色, 否则只对该项目生效
+CREATE TABLE IF NOT EXISTS sys_user_role (
+    id          bigint      NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    username    varchar(64) NOT NULL,
+    role_id     bigint      NOT NULL,
+    project_id  bigint      NULL,
+    create_user varchar(64) NULL,
+    create_time datetime    NULL,
+    KEY idx_sys_user_role_username (username)
+) DEFAULT CHARSET = utf8mb4;
+
+INSERT IGNORE INTO sys_role (code, name) VALUES
+    ('viewer', '访客'),
+    ('developer', '开发'),
+    ('release_manager', '发布管理员'),
+    ('admin', '管理员');
+
+INSERT IGNORE INTO sys_role_permission (role_id, page, op)
+SELECT id, '*', 'query' FROM sys_role WHERE code IN ('viewer', 'developer', 'release_manager');
+
+INSERT IGNORE INTO sys_role_permission (role_id, page, op)
+SELECT r.id, p.page, 'update'
+FROM sys_role r
+JOIN (SELECT 'project' AS page UNION SELECT 'mdm45' UNION SELECT 'versionconfigmdm45') p
+WHERE r.code IN ('developer', 'release_manager');
+
+INSERT IGNORE INTO sys_role_permission (role_id, page, op)
+SELECT id, '*', 'delete' FROM sys_role WHERE code = 'release_manager';
+
+INSERT IGNORE INTO sys_role_permission (role_id, page, op)
+SELECT id, '*', '*' FROM sys_role WHERE code = 'admin';
@@ -0,0 +1,7 @@
+-- 用户管理: 禁用账号和强制修改密码
+ALTER TABLE sys_user
+    ADD COLUMN is_disabled          char(1) NULL,
+    ADD COLUMN must_change_password char(1) NULL;
+
+-- Argon2id 的 PHC 字符串比旧的 64 位 hex 长
+ALTER TABLE sys_user MODIFY COLUMN password varchar(255) NOT NULL;
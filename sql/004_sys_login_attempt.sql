@@ -0,0 +1,12 @@
+-- 登录记录, login_guard.persist = true 时写入, 启动时据此恢复锁定状态
+-- result: success / failure / blocked (锁定或等待期内被拒绝, 没有校验密码) / unlock (管理员解除锁定)
+CREATE TABLE IF NOT EXISTS sys_login_attempt (
+    id          bigint       NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    username    varchar(64)  NOT NULL,
+    client_ip   varchar(64)  NULL,
+    result      varchar(16)  NOT NULL,
+    reason      varchar(255) NULL,
+    create_time datetime     NOT NULL,
+    KEY idx_sys_login_attempt_username (username),
+    KEY idx_sys_login_attempt_time (create_time)
+) DEFAULT CHARSET = utf8mb4;
@@ -0,0 +1,16 @@
+-- 所有页面的新建/修改/删除记录, before_data/after_data 是修改前后的记录, diff 只包含变化的字段
+CREATE TABLE IF NOT EXISTS sys_audit_log (
+    id          bigint      NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    username    varchar(64) NOT NULL,
+    page        varchar(64) NOT NULL,
+    entity_id   varchar(64) NULL,
+    action      varchar(16) NOT NULL,
+    client_ip   varchar(64) NULL,
+    diff        text        NULL,
+    before_data text        NULL,
+    after_data  text        NULL,
+    create_time datetime    NOT NULL,
+    KEY idx_sys_audit_log_username (username),
+    KEY idx_sys_audit_log_entity (page, entity_id),
+    KEY idx_sys_audit_log_time (create_time)
+) DEFAULT CHARSET = utf8mb4;
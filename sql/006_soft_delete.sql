@@ -0,0 +1,12 @@
+-- 回收站: 记录删除人和删除时间, 之前删除的记录用 update_time 代替
+ALTER TABLE tb_project
+    ADD COLUMN delete_user varchar(64) NULL,
+    ADD COLUMN delete_time datetime    NULL;
+
+ALTER TABLE tb_version_mdm45
+    ADD COLUMN delete_user varchar(64) NULL,
+    ADD COLUMN delete_time datetime    NULL;
+
+ALTER TABLE tb_version_config_mdm45
+    ADD COLUMN delete_user varchar(64) NULL,
+    ADD COLUMN delete_time datetime    NULL;
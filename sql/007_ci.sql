@@ -0,0 +1,16 @@
+-- CI 上报构建记录使用的 token, 只保存 sha256, project_id 不为空时只能上报该项目
+CREATE TABLE IF NOT EXISTS sys_ci_token (
+    id             bigint      NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    name           varchar(64) NOT NULL,
+    token_hash     char(64)    NOT NULL,
+    project_id     bigint      NULL,
+    create_user    varchar(64) NULL,
+    create_time    datetime    NOT NULL,
+    last_used_time datetime    NULL,
+    revoked        char(1)     NULL,
+    UNIQUE KEY uk_sys_ci_token_hash (token_hash)
+) DEFAULT CHARSET = utf8mb4;
+
+-- build_status: queued / running / success / failed
+ALTER TABLE tb_version_build_record
+    ADD INDEX idx_build_record_uuid (build_uuid);
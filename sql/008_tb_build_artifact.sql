@@ -0,0 +1,15 @@
+-- 构建产物, 文件保存在 storage 后端的 storage_key 下
+CREATE TABLE IF NOT EXISTS tb_build_artifact (
+    id           bigint       NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    build_id     bigint       NOT NULL,
+    build_uuid   varchar(64)  NOT NULL,
+    file_name    varchar(128) NOT NULL,
+    content_type varchar(128) NULL,
+    size         bigint       NOT NULL,
+    sha256       char(64)     NOT NULL,
+    storage      varchar(16)  NOT NULL,
+    storage_key  varchar(255) NOT NULL,
+    create_user  varchar(64)  NULL,
+    create_time  datetime     NOT NULL,
+    UNIQUE KEY uk_build_artifact_file (build_uuid, file_name)
+) DEFAULT CHARSET = utf8mb4;
@@ -0,0 +1,15 @@
+-- 发布流程, release_state: pending (等待审批) / released
+-- release_request_user 为发起发布的人, release_user 为使发布生效的人 (需要审批时为审批人)
+ALTER TABLE tb_version_build_record
+    ADD COLUMN release_state        varchar(16) NULL,
+    ADD COLUMN release_request_user varchar(64) NULL,
+    ADD COLUMN release_request_time datetime    NULL,
+    ADD COLUMN release_user         varchar(64) NULL,
+    ADD COLUMN release_time         datetime    NULL,
+    ADD INDEX idx_build_record_release (project_id, is_release, version_code);
+
+-- 已有的发布记录
+UPDATE tb_version_build_record SET release_state = 'released' WHERE is_release = 1 AND release_state IS NULL;
+
+INSERT IGNORE INTO sys_role_permission (role_id, page, op)
+SELECT id, 'release', 'update' FROM sys_role WHERE code = 'release_manager';
@@ -0,0 +1,10 @@
+-- OTA 更新检查, 发布时设置, release_channel: stable / beta
+-- 只推送给 version_code 在 [min_version_code, max_version_code] 内的客户端, 为空表示不限制
+ALTER TABLE tb_version_build_record
+    ADD COLUMN release_channel  varchar(16)   NULL,
+    ADD COLUMN release_note     varchar(2000) NULL,
+    ADD COLUMN force_update     tinyint       NULL,
+    ADD COLUMN min_version_code bigint        NULL,
+    ADD COLUMN max_version_code bigint        NULL;
+
+UPDATE tb_version_build_record SET release_channel = 'stable' WHERE is_release = 1 AND release_channel IS NULL;
@@ -0,0 +1,27 @@
+-- 发布的灰度规则, 没有记录时全量发布, state: active / paused / halted
+-- allow_devices / deny_devices 为设备 id 的 json 数组
+CREATE TABLE IF NOT EXISTS tb_release_rollout (
+    id            bigint      NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    build_id      bigint      NOT NULL,
+    percentage    int         NOT NULL,
+    state         varchar(16) NOT NULL,
+    allow_devices text        NULL,
+    deny_devices  text        NULL,
+    update_user   varchar(64) NULL,
+    update_time   datetime    NULL,
+    UNIQUE KEY uk_release_rollout_build (build_id)
+) DEFAULT CHARSET = utf8mb4;
+
+-- 每次修改后的规则
+CREATE TABLE IF NOT EXISTS tb_release_rollout_history (
+    id            bigint      NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    build_id      bigint      NOT NULL,
+    action        varchar(16) NOT NULL,
+    percentage    int         NOT NULL,
+    state         varchar(16) NOT NULL,
+    allow_devices text        NULL,
+    deny_devices  text        NULL,
+    username      varchar(64) NOT NULL,
+    create_time   datetime    NOT NULL,
+    KEY idx_release_rollout_history_build (build_id)
+) DEFAULT CHARSET = utf8mb4;
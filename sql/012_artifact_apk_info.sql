@@ -0,0 +1,3 @@
+-- 上传 apk 时解析出的包名, 版本, sdk, 权限, abi 和签名证书指纹 (json)
+ALTER TABLE tb_build_artifact
+    ADD COLUMN apk_info text NULL;
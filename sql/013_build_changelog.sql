@@ -0,0 +1,12 @@
+-- 构建相对于上一次发布的提交记录, 发布时生成
+-- entries 为 [{revision, author, date, message}] 的 json, 新的在前
+CREATE TABLE IF NOT EXISTS tb_build_changelog (
+    id            bigint      NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    build_id      bigint      NOT NULL,
+    from_build_id bigint      NULL,
+    from_revision varchar(64) NULL,
+    to_revision   varchar(64) NOT NULL,
+    entries       mediumtext  NOT NULL,
+    create_time   datetime    NOT NULL,
+    UNIQUE KEY uk_build_changelog_build (build_id)
+) DEFAULT CHARSET = utf8mb4;
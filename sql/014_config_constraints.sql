@@ -0,0 +1,3 @@
+-- 配置项的约束 (json): required, min/max, pattern, max_length, values, schemes
+ALTER TABLE tb_version_config_mdm45
+    ADD COLUMN constraints text NULL;
@@ -0,0 +1,28 @@
+-- 配置值集: project_id 和 version_id 都为 0 时是全局默认值集,
+-- version_id 为 0 时是项目的值集, 都不为 0 时是项目中某个 mdm45 版本的值集
+CREATE TABLE IF NOT EXISTS tb_config_value_set (
+    id          bigint       NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    name        varchar(128) NOT NULL,
+    project_id  bigint       NOT NULL DEFAULT 0,
+    version_id  bigint       NOT NULL DEFAULT 0,
+    create_user varchar(64)  NOT NULL,
+    create_time datetime     NOT NULL,
+    update_user varchar(64)  NULL,
+    update_time datetime     NULL,
+    UNIQUE KEY uk_config_value_set_scope (project_id, version_id),
+    KEY idx_config_value_set_version (version_id)
+) DEFAULT CHARSET = utf8mb4;
+
+-- 值集中覆盖的值, 没有记录的 key 继承上一级 (版本 -> 项目 -> 默认)
+CREATE TABLE IF NOT EXISTS tb_config_value (
+    id          bigint       NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    set_id      bigint       NOT NULL,
+    config_key  varchar(128) NOT NULL,
+    value       text         NOT NULL,
+    update_user varchar(64)  NOT NULL,
+    update_time datetime     NOT NULL,
+    UNIQUE KEY uk_config_value_key (set_id, config_key)
+) DEFAULT CHARSET = utf8mb4;
+
+INSERT IGNORE INTO tb_config_value_set (name, project_id, version_id, create_user, create_time)
+VALUES ('默认', 0, 0, 'system', NOW());
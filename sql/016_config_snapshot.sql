@@ -0,0 +1,22 @@
+-- 冻结的配置: 项目 (或项目中某个版本) 当时的最终配置值, content 为 {config_key: value} 的 json,
+-- content_hash 为 content 的 sha256, 内容不能修改
+CREATE TABLE IF NOT EXISTS tb_config_snapshot (
+    id           bigint       NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    project_id   bigint       NOT NULL,
+    version_id   bigint       NOT NULL DEFAULT 0,
+    tag          varchar(128) NOT NULL,
+    content_hash char(64)     NOT NULL,
+    content      mediumtext   NOT NULL,
+    remark       varchar(255) NULL,
+    create_user  varchar(64)  NOT NULL,
+    create_time  datetime     NOT NULL,
+    update_user  varchar(64)  NULL,
+    update_time  datetime     NULL,
+    UNIQUE KEY uk_config_snapshot_tag (project_id, tag),
+    KEY idx_config_snapshot_hash (content_hash)
+) DEFAULT CHARSET = utf8mb4;
+
+-- 构建使用的配置快照
+ALTER TABLE tb_version_build_record
+    ADD COLUMN config_snapshot_id bigint NULL,
+    ADD INDEX idx_build_record_config_snapshot (config_snapshot_id);
@@ -0,0 +1,3 @@
+-- 上传前先占用 (build_uuid, file_name), 写完文件后置为 NULL; 'uploading' 的记录不对外显示
+ALTER TABLE tb_build_artifact
+    ADD COLUMN upload_state varchar(16) NULL;
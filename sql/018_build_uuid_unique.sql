@@ -0,0 +1,6 @@
+-- build_uuid 唯一, 并发上报同一个 build_uuid 时由数据库拒绝
+-- 执行前先确认没有重复的记录:
+--   select build_uuid, count(*) from tb_version_build_record group by build_uuid having count(*) > 1;
+ALTER TABLE tb_version_build_record
+    DROP INDEX idx_build_record_uuid,
+    ADD UNIQUE KEY uk_build_record_uuid (build_uuid);
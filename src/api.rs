@@ -1,26 +1,46 @@
+pub mod artifact;
+pub mod audit;
+pub mod build_diff;
 pub mod build_record;
+pub mod changelog;
+pub mod ci;
+pub mod config_io;
+pub mod config_snapshot;
+pub mod config_value;
 pub mod mdm45;
 pub mod mdm45_config;
+pub mod ota;
 pub mod page_base;
 pub mod project;
+pub mod rbac;
+pub mod release;
+pub mod render;
+pub mod rollout;
+pub mod session;
+pub mod stats;
+pub mod trash;
+pub mod users;
 
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
-use crate::mysql_find_one;
 use crate::{
-    http_response::{response_error, response_error2, response_ok, response_success},
-    response_auth_err,
+    config::Config,
+    error::{AppError, AppResult},
+    http_response::{response_ok, response_success},
+    rbac::{require, Op},
 };
+use crate::{mysql_query, sql_args};
 use actix_identity::Identity;
-use actix_web::{delete, get, post, web, HttpResponse, Result};
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
 use build_record::BuildRecordPage;
-use log::info;
+use log::{error, info};
 use mdm45_config::Mdm45ConfigPage;
 use once_cell::sync::OnceCell;
 use page_base::{NotFoundPage, PageBase};
 use serde_json::Value;
 
 use self::project::ProjectPage;
+use self::users::UserPage;
 use self::{mdm45::Mdm45Page, page_base::QueryInfo};
 
 static PAGES: OnceCell<HashMap<String, Arc<dyn PageBase + Send + Sync>>> = OnceCell::new();
@@ -30,6 +50,7 @@ pub fn init() {
     map.insert("mdm45".to_string(), Arc::new(Mdm45Page));
     map.insert("versionbuildrecord".to_string(), Arc::new(BuildRecordPage));
     map.insert("versionconfigmdm45".to_string(), Arc::new(Mdm45ConfigPage));
+    map.insert("users".to_string(), Arc::new(UserPage));
 
     let _ = PAGES.set(map);
 }
@@ -38,24 +59,85 @@ pub fn get_page() -> &'static HashMap<String, Arc<dyn PageBase + Send + Sync>> {
     PAGES.get().unwrap()
 }
 
-pub async fn check_user(id: Identity) -> Result<String, String> {
-    let user = id.identity();
-    if user.is_none() {
-        return Err("请先登录".to_string());
-    }
+/// 当前登录用户, name 用于记录 create_user/update_user
+#[derive(Debug, Clone)]
+pub struct CurrentUser {
+    pub username: String,
+    pub name: String,
+    pub session_id: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+struct UserState {
+    name: String,
+    is_disabled: Option<String>,
+    must_change_password: Option<String>,
+}
 
-    let username = user.unwrap();
+/// 登录且没有被禁用, 需要修改密码时也通过, 只用于修改密码
+pub async fn check_login(id: Identity) -> AppResult<(CurrentUser, bool)> {
+    let identity = id
+        .identity()
+        .ok_or_else(|| AppError::Auth("请先登录".to_string()))?;
+    let username = crate::session::resolve(&identity).await?;
 
-    let result = mysql_find_one!(
-        String,
-        &format!("select name from sys_user where username = '{}'", username)
+    let mut data: Vec<UserState> = Vec::new();
+    mysql_query!(
+        UserState,
+        data,
+        "select name, is_disabled, must_change_password from sys_user where username = ?",
+        &sql_args![&username]
     )?;
+    let state = data
+        .pop()
+        .ok_or_else(|| AppError::Auth("请先登录".to_string()))?;
+    if state.is_disabled.as_deref() == Some("Y") {
+        return Err(AppError::Auth("用户已被禁用".to_string()));
+    }
+
+    let user = CurrentUser {
+        session_id: if crate::session::is_store_enabled() {
+            Some(identity)
+        } else {
+            None
+        },
+        username,
+        name: state.name,
+    };
+    Ok((user, state.must_change_password.as_deref() == Some("Y")))
+}
+
+/// 需要修改密码的用户只能修改密码和退出
+pub async fn check_user(id: Identity) -> AppResult<CurrentUser> {
+    let (user, must_change_password) = check_login(id).await?;
+    if must_change_password {
+        return Err(AppError::Forbidden("请先修改密码".to_string()));
+    }
+    Ok(user)
+}
+
+/// 对端是 server.trusted_proxies 中的代理时才使用代理头里的地址
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    let peer = req.peer_addr().map(|addr| addr.ip());
+    let trusted = &Config::get().server.trusted_proxies;
+    if peer.map_or(false, |ip| trusted.contains(&ip)) {
+        real_ip(req.connection_info().realip_remote_addr())
+    } else {
+        peer.map(|ip| ip.to_string())
+    }
+}
 
-    Ok(result)
+/// 代理头里的地址可能带端口
+fn real_ip(addr: Option<&str>) -> Option<String> {
+    addr.map(|s| {
+        s.parse::<SocketAddr>()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_else(|_| s.to_string())
+    })
 }
 
 #[inline]
-async fn _query(mode: &str, info: &QueryInfo) -> Result<Value, String> {
+async fn _query(mode: &str, info: &QueryInfo) -> AppResult<Value> {
     let page = get_page();
     match page.get(mode).clone() {
         Some(p) => p.query(info).await,
@@ -63,19 +145,115 @@ async fn _query(mode: &str, info: &QueryInfo) -> Result<Value, String> {
     }
 }
 
-pub async fn _update(mode: &str, user: &str, body: &str) -> Result<(), String> {
-    let page = get_page();
-    match page.get(mode).clone() {
-        Some(p) => p.update(user, body).await,
-        None => NotFoundPage.update(user, body).await,
+fn page_of(mode: &str) -> &'static (dyn PageBase + Send + Sync) {
+    match get_page().get(mode) {
+        Some(p) => p.as_ref(),
+        None => &NotFoundPage,
+    }
+}
+
+/// 修改已经成功, 审计写入失败只记录日志
+async fn _audit(entry: crate::audit::Entry<'_>) {
+    if let Err(err) = crate::audit::record(&entry).await {
+        error!("audit log err = {}, entry = {:?}", err, entry);
+    }
+}
+
+pub async fn _update(
+    mode: &str,
+    user: &CurrentUser,
+    client_ip: Option<&str>,
+    body: &str,
+) -> AppResult<()> {
+    let p = page_of(mode);
+    let before = match p.id_of_params(body) {
+        Some(id) => p.find(&id).await?,
+        None => None,
+    };
+
+    let id = p.update(&user.name, body).await?;
+    let after = p.find(&id).await?;
+
+    _audit(crate::audit::Entry {
+        username: &user.username,
+        page: mode,
+        entity_id: Some(&id),
+        action: if before.is_some() {
+            crate::audit::ACTION_UPDATE
+        } else {
+            crate::audit::ACTION_CREATE
+        },
+        client_ip,
+        before,
+        after,
+    })
+    .await;
+    Ok(())
+}
+
+pub async fn _delete(
+    mode: &str,
+    user: &CurrentUser,
+    client_ip: Option<&str>,
+    id: u32,
+) -> AppResult<()> {
+    let p = page_of(mode);
+    let entity_id = id.to_string();
+    let before = p.find(&entity_id).await?;
+
+    p.delete(&user.name, id).await?;
+
+    _audit(crate::audit::Entry {
+        username: &user.username,
+        page: mode,
+        entity_id: Some(&entity_id),
+        action: crate::audit::ACTION_DELETE,
+        client_ip,
+        before,
+        after: None,
+    })
+    .await;
+    Ok(())
+}
+
+/// update 要检查的项目: 修改已有记录时是记录原来的项目, 以及请求中的新项目
+async fn _projects_of_update(mode: &str, body: &str) -> AppResult<Vec<Option<i64>>> {
+    let p = page_of(mode);
+    let new_project = p.project_of_params(body);
+    let old_project = match p.id_of_params(body).and_then(|id| id.parse::<u32>().ok()) {
+        Some(id) => p.project_of_id(id).await?,
+        None => None,
+    };
+
+    Ok(match (old_project, new_project) {
+        (Some(old), Some(new)) if old != new => vec![Some(old), Some(new)],
+        (old, new) => vec![old.or(new)],
+    })
+}
+
+/// 通用页面的操作需要的权限
+fn op_of(p: &(dyn PageBase + Send + Sync), op: Op) -> Op {
+    if p.admin_only() {
+        Op::Admin
+    } else {
+        op
     }
 }
 
-pub async fn _delete(mode: &str, user: &str, id: u32) -> Result<(), String> {
+/// 列表查询授权的项目, 页面不按 s_project 过滤时需要全局权限
+fn project_of_query(p: &(dyn PageBase + Send + Sync), info: &QueryInfo) -> Option<i64> {
+    if p.filters_by_project() {
+        info.project.map(i64::from)
+    } else {
+        None
+    }
+}
+
+async fn _project_of_id(mode: &str, id: u32) -> AppResult<Option<i64>> {
     let page = get_page();
     match page.get(mode).clone() {
-        Some(p) => p.delete(user, id).await,
-        None => NotFoundPage.delete(user, id).await,
+        Some(p) => p.project_of_id(id).await,
+        None => NotFoundPage.project_of_id(id).await,
     }
 }
 
@@ -84,37 +262,115 @@ pub async fn query(
     id: Identity,
     page: web::Path<(String,)>,
     info: web::Query<QueryInfo>,
-) -> HttpResponse {
+) -> AppResult<HttpResponse> {
     info!("query info {:?}!", info);
 
-    match check_user(id).await {
-        Ok(_) => match _query(&page.into_inner().0, &info).await {
-            Ok(d) => response_ok(d),
-            Err(err) => response_error(&err),
-        },
-        Err(err) => response_auth_err!(err),
-    }
+    let user = check_user(id).await?;
+    let mode = page.into_inner().0;
+    let p = page_of(&mode);
+    require(
+        &user,
+        &mode,
+        op_of(p, Op::Query),
+        project_of_query(p, &info),
+    )
+    .await?;
+
+    let d = _query(&mode, &info).await?;
+    Ok(response_ok(d))
 }
 
 #[post("/{page}/update")]
-pub async fn update(id: Identity, page: web::Path<(String,)>, req_body: String) -> HttpResponse {
-    match check_user(id).await {
-        Ok(user) => match _update(&page.into_inner().0, &user, &req_body).await {
-            Ok(_) => response_success("成功"),
-            Err(err) => response_error(&err),
-        },
-        Err(err) => response_auth_err!(err),
+pub async fn update(
+    id: Identity,
+    req: HttpRequest,
+    page: web::Path<(String,)>,
+    req_body: String,
+) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    let mode = page.into_inner().0;
+    for project in _projects_of_update(&mode, &req_body).await? {
+        require(&user, &mode, op_of(page_of(&mode), Op::Update), project).await?;
     }
+
+    _update(&mode, &user, client_ip(&req).as_deref(), &req_body).await?;
+    Ok(response_success("成功"))
 }
 
 #[delete("/{page}/delete/{id}")]
-pub async fn delete(id: Identity, path: web::Path<(String, u32)>) -> HttpResponse {
+pub async fn delete(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(String, u32)>,
+) -> AppResult<HttpResponse> {
     let p = path.into_inner();
-    match check_user(id).await {
-        Ok(user) => match _delete(&p.0, &user, p.1).await {
-            Ok(_) => response_success("成功"),
-            Err(err) => response_error(&err),
-        },
-        Err(err) => response_auth_err!(err),
+    let user = check_user(id).await?;
+    require(
+        &user,
+        &p.0,
+        op_of(page_of(&p.0), Op::Delete),
+        _project_of_id(&p.0, p.1).await?,
+    )
+    .await?;
+
+    _delete(&p.0, &user, client_ip(&req).as_deref(), p.1).await?;
+    Ok(response_success("成功"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        build_record::BuildRecordPage, op_of, project::ProjectPage, project_of_query,
+        users::UserPage,
+    };
+    use crate::{
+        api::page_base::QueryInfo,
+        rbac::{Grant, Op, Permissions},
+    };
+
+    #[test]
+    fn test_project_of_query() {
+        let p = Permissions {
+            superuser: false,
+            grants: vec![Grant {
+                role_code: "developer".to_string(),
+                project_id: Some(3),
+                page: "*".to_string(),
+                op: "query".to_string(),
+            }],
+        };
+        let info = QueryInfo {
+            project: Some(3),
+            ..Default::default()
+        };
+
+        // 项目列表不按 s_project 过滤, 只有项目授权时不能查询
+        assert_eq!(None, project_of_query(&ProjectPage, &info));
+        assert!(!p.allows("project", Op::Query, project_of_query(&ProjectPage, &info)));
+
+        let project = project_of_query(&BuildRecordPage, &info);
+        assert_eq!(Some(3), project);
+        assert!(p.allows("versionbuildrecord", Op::Query, project));
+        assert!(!p.allows(
+            "versionbuildrecord",
+            Op::Query,
+            project_of_query(&BuildRecordPage, &QueryInfo::default())
+        ));
+    }
+
+    #[test]
+    fn test_admin_only() {
+        let viewer = Permissions {
+            superuser: false,
+            grants: vec![Grant {
+                role_code: "viewer".to_string(),
+                project_id: None,
+                page: "*".to_string(),
+                op: "query".to_string(),
+            }],
+        };
+        assert!(viewer.allows("project", op_of(&ProjectPage, Op::Query), None));
+        assert_eq!(Op::Admin, op_of(&UserPage, Op::Query));
+        assert!(!viewer.allows("users", op_of(&UserPage, Op::Query), None));
     }
 }
@@ -0,0 +1,375 @@
+use actix_identity::Identity;
+use actix_multipart::{Field, Multipart};
+use actix_web::{
+    delete, get,
+    http::{header, StatusCode},
+    post, web, HttpRequest, HttpResponse,
+};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    apk::{self, ApkInfo},
+    artifact::{self, parse_range, ArtifactWriter, ByteRange, NewArtifact, StoredFile},
+    audit::{ACTION_CREATE, ACTION_DELETE},
+    ci_token,
+    config::Config,
+    error::{AppError, AppResult},
+    http_response::{response_ok, response_success},
+    rbac::{require, Op},
+};
+
+use super::{
+    _audit,
+    build_record::{self, BuildRecord},
+    check_user, client_ip, CurrentUser,
+};
+
+/// 权限和构建记录一致
+const PAGE: &str = "versionbuildrecord";
+const AUDIT_PAGE: &str = "artifact";
+
+#[derive(Deserialize, Debug)]
+pub struct UploadQuery {
+    /// 上传单个文件时可以带上期望的 sha256, 不一致时拒绝
+    pub sha256: Option<String>,
+}
+
+/// 带 Authorization 头时按 CI token 校验, 否则按登录用户校验
+async fn authorize(
+    id: Identity,
+    req: &HttpRequest,
+    project_id: i64,
+    op: Op,
+) -> AppResult<CurrentUser> {
+    if req.headers().contains_key(header::AUTHORIZATION) {
+        let ci = ci_token::authenticate(req).await?;
+        ci.check_project(project_id)?;
+        return Ok(CurrentUser {
+            username: ci.actor(),
+            name: ci.name,
+            session_id: None,
+        });
+    }
+
+    let user = check_user(id).await?;
+    require(&user, PAGE, op, Some(project_id)).await?;
+    Ok(user)
+}
+
+pub fn content_type_of(file_name: &str) -> &'static str {
+    let ext = file_name
+        .rsplit('.')
+        .next()
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    match ext.as_str() {
+        "apk" => "application/vnd.android.package-archive",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "txt" | "properties" | "gradle" | "log" => "text/plain; charset=utf-8",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+pub fn is_apk(file_name: &str) -> bool {
+    file_name.to_ascii_lowercase().ends_with(".apk")
+}
+
+/// 解析上传的 apk, 版本与构建记录不一致, 或包名与同一构建中的其他 apk 不同时拒绝
+async fn inspect_apk(record: &BuildRecord, key: &str) -> AppResult<ApkInfo> {
+    let path = artifact::store().local_path(key).await?;
+    let info = tokio::task::spawn_blocking(move || apk::parse_file(&path))
+        .await
+        .map_err(AppError::internal)??;
+    info.check(record.version_code, &record.version_name)?;
+
+    for other in artifact::list(&[record.build_uuid.clone()]).await? {
+        if let Some(o) = other.apk() {
+            if o.package != info.package {
+                return Err(AppError::Validation(format!(
+                    "包名 {} 与 {} 的 {} 不一致",
+                    info.package, other.file_name, o.package
+                )));
+            }
+        }
+    }
+    Ok(info)
+}
+
+/// 边写边计算大小和 sha256, 超过上限时返回错误
+async fn copy_field(
+    field: &mut Field,
+    writer: &mut dyn ArtifactWriter,
+    max_size: u64,
+) -> AppResult<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut size = 0u64;
+
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(|e| AppError::Validation(format!("上传失败: {}", e)))?;
+        size += chunk.len() as u64;
+        if size > max_size {
+            return Err(AppError::Validation(format!(
+                "文件不能超过 {} MB",
+                max_size / 1024 / 1024
+            )));
+        }
+        hasher.update(&chunk);
+        writer.write(&chunk).await?;
+    }
+
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
+/// 写入 key 并校验 sha256, apk 还要解析检查. 失败时未完成的写入已丢弃
+async fn store_field(
+    record: &BuildRecord,
+    file_name: &str,
+    key: &str,
+    field: &mut Field,
+    max_size: u64,
+    expected: Option<&str>,
+) -> AppResult<(u64, String, Option<ApkInfo>)> {
+    let mut writer = artifact::store().create(key).await?;
+    let (size, sha256) = match copy_field(field, writer.as_mut(), max_size).await {
+        Ok(v) => v,
+        Err(err) => {
+            writer.abort().await;
+            return Err(err);
+        }
+    };
+
+    if let Some(expected) = expected {
+        if !expected.eq_ignore_ascii_case(&sha256) {
+            writer.abort().await;
+            return Err(AppError::Validation(format!(
+                "文件 {} 的 sha256 {} 与期望的 {} 不一致",
+                file_name, sha256, expected
+            )));
+        }
+    }
+    writer.finish().await?;
+
+    let apk_info = if is_apk(file_name) {
+        Some(inspect_apk(record, key).await?)
+    } else {
+        None
+    };
+    Ok((size, sha256, apk_info))
+}
+
+#[post("/builds/{build_uuid}/artifacts")]
+pub async fn upload(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    info: web::Query<UploadQuery>,
+    mut payload: Multipart,
+) -> AppResult<HttpResponse> {
+    let build_uuid = path.into_inner().0;
+    let record = build_record::find_by_uuid(&build_uuid).await?;
+    let user = authorize(id, &req, record.project_id, Op::Update).await?;
+    let max_size = Config::get().artifact.max_size_mb * 1024 * 1024;
+
+    let mut saved = Vec::new();
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(|e| AppError::Validation(format!("上传失败: {}", e)))?;
+        let file_name = match field
+            .content_disposition()
+            .and_then(|cd| cd.get_filename().map(|s| s.to_string()))
+        {
+            Some(name) => name,
+            None => continue,
+        };
+
+        artifact::check_segment("文件名", &file_name)?;
+        // 先占用记录, 同名文件只有一个上传能写入 key
+        let reserved = artifact::reserve(&NewArtifact {
+            build_id: record.id.unwrap_or_default(),
+            build_uuid: &build_uuid,
+            file_name: &file_name,
+            create_user: &user.name,
+        })
+        .await?;
+
+        let key = artifact::key(&build_uuid, &file_name);
+        let stored = store_field(
+            &record,
+            &file_name,
+            &key,
+            &mut field,
+            max_size,
+            info.sha256.as_deref(),
+        )
+        .await;
+        let completed = match &stored {
+            Ok((size, sha256, apk_info)) => {
+                artifact::complete(
+                    reserved,
+                    &StoredFile {
+                        content_type: Some(content_type_of(&file_name).to_string()),
+                        size: *size,
+                        sha256,
+                        apk_info: apk_info.as_ref(),
+                    },
+                )
+                .await
+            }
+            Err(_) => Ok(()),
+        };
+        if let Err(err) = stored.and(completed) {
+            // 记录还是自己占用的才删除文件, 否则 key 可能已经属于其他上传
+            if artifact::release(reserved).await? {
+                artifact::store().delete(&key).await?;
+            }
+            return Err(err);
+        }
+
+        let a = artifact::find(&build_uuid, &file_name).await?;
+        _audit(crate::audit::Entry {
+            username: &user.username,
+            page: AUDIT_PAGE,
+            entity_id: Some(&key),
+            action: ACTION_CREATE,
+            client_ip: client_ip(&req).as_deref(),
+            before: None,
+            after: serde_json::to_value(&a).ok(),
+        })
+        .await;
+        saved.push(a);
+    }
+
+    if saved.is_empty() {
+        return Err(AppError::Validation("没有上传文件".to_string()));
+    }
+    Ok(response_ok(
+        serde_json::to_value(saved).map_err(AppError::internal)?,
+    ))
+}
+
+#[get("/builds/{build_uuid}/artifacts")]
+pub async fn list(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+) -> AppResult<HttpResponse> {
+    let build_uuid = path.into_inner().0;
+    let record = build_record::find_by_uuid(&build_uuid).await?;
+    authorize(id, &req, record.project_id, Op::Query).await?;
+
+    let data = artifact::list(&[build_uuid]).await?;
+    Ok(response_ok(
+        serde_json::to_value(data).map_err(AppError::internal)?,
+    ))
+}
+
+/// 下载, 支持 Range 断点续传
+#[get("/builds/{build_uuid}/artifacts/{file_name}")]
+pub async fn download(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> AppResult<HttpResponse> {
+    let (build_uuid, file_name) = path.into_inner();
+    let record = build_record::find_by_uuid(&build_uuid).await?;
+    authorize(id, &req, record.project_id, Op::Query).await?;
+
+    let a = artifact::find(&build_uuid, &file_name).await?;
+    serve(&req, &a).await
+}
+
+/// 按 Range 头返回文件内容, OTA 下载也使用
+pub async fn serve(req: &HttpRequest, a: &artifact::Artifact) -> AppResult<HttpResponse> {
+    let size = a.size as u64;
+    let range = parse_range(
+        req.headers()
+            .get(header::RANGE)
+            .and_then(|v| v.to_str().ok()),
+        size,
+    );
+
+    let (status, start, len) = match range {
+        ByteRange::Full => (StatusCode::OK, 0, size),
+        ByteRange::Partial(start, end) => (StatusCode::PARTIAL_CONTENT, start, end - start + 1),
+        ByteRange::Unsatisfiable => {
+            return Ok(HttpResponse::build(StatusCode::RANGE_NOT_SATISFIABLE)
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+                .finish());
+        }
+    };
+
+    let body = artifact::store()
+        .open(&artifact::key(&a.build_uuid, &a.file_name), start, len)
+        .await?;
+
+    let mut res = HttpResponse::build(status);
+    res.insert_header((
+        header::CONTENT_TYPE,
+        a.content_type
+            .clone()
+            .unwrap_or_else(|| content_type_of(&a.file_name).to_string()),
+    ))
+    .insert_header((header::CONTENT_LENGTH, len))
+    .insert_header((header::ACCEPT_RANGES, "bytes"))
+    .insert_header((header::ETAG, format!("\"{}\"", a.sha256)))
+    .insert_header((
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{}\"", a.file_name),
+    ))
+    .insert_header(("X-Checksum-Sha256", a.sha256.clone()));
+    if status == StatusCode::PARTIAL_CONTENT {
+        res.insert_header((
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, start + len - 1, size),
+        ));
+    }
+
+    Ok(res.streaming(body))
+}
+
+#[delete("/builds/{build_uuid}/artifacts/{file_name}")]
+pub async fn remove(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> AppResult<HttpResponse> {
+    let (build_uuid, file_name) = path.into_inner();
+    let record = build_record::find_by_uuid(&build_uuid).await?;
+    let user = check_user(id).await?;
+    require(&user, PAGE, Op::Delete, Some(record.project_id)).await?;
+
+    let a = artifact::find(&build_uuid, &file_name).await?;
+    artifact::remove(&build_uuid, &file_name).await?;
+
+    _audit(crate::audit::Entry {
+        username: &user.username,
+        page: AUDIT_PAGE,
+        entity_id: Some(&artifact::key(&build_uuid, &file_name)),
+        action: ACTION_DELETE,
+        client_ip: client_ip(&req).as_deref(),
+        before: serde_json::to_value(&a).ok(),
+        after: None,
+    })
+    .await;
+    Ok(response_success("成功"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{content_type_of, is_apk};
+
+    #[test]
+    fn test_content_type_of() {
+        assert_eq!(
+            "application/vnd.android.package-archive",
+            content_type_of("app-release.APK")
+        );
+        assert_eq!("application/json", content_type_of("config.json"));
+        assert_eq!("application/octet-stream", content_type_of("noext"));
+        assert!(is_apk("app-release.APK"));
+        assert!(!is_apk("apk.txt"));
+    }
+}
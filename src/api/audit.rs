@@ -0,0 +1,19 @@
+use actix_identity::Identity;
+use actix_web::{get, web, HttpResponse};
+
+use crate::{
+    audit::{self, AuditQuery},
+    error::AppResult,
+    http_response::response_ok,
+    rbac::{require, Op},
+};
+
+use super::check_user;
+
+#[get("/audit/list")]
+pub async fn list(id: Identity, info: web::Query<AuditQuery>) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    require(&user, "audit", Op::Admin, None).await?;
+
+    Ok(response_ok(audit::list(&info).await?))
+}
@@ -0,0 +1,161 @@
+use actix_identity::Identity;
+use actix_web::{get, web, HttpResponse};
+use serde_json::{json, Value};
+
+use crate::{
+    artifact,
+    error::{AppError, AppResult},
+    http_response::response_ok,
+    rbac::{require, Op},
+    snapshot::{self, ConfigValues},
+};
+
+use super::{
+    build_record::{self, BuildRecord},
+    check_user, config_snapshot,
+};
+
+const PAGE: &str = "versionbuildrecord";
+/// 配置文件大小上限
+const MAX_SNAPSHOT_SIZE: u64 = 4 * 1024 * 1024;
+
+/// 参与比较的构建字段
+const FIELDS: &[&str] = &[
+    "project_id",
+    "project_name",
+    "app_name",
+    "version_code",
+    "version_name",
+    "revision",
+    "svn_url",
+    "release_file_arch",
+    "config_tag",
+    "config_snapshot_id",
+    "config_detail_file",
+    "build_result",
+    "is_release",
+];
+
+/// 有变化的字段, 顺序同 FIELDS
+fn field_changes(from: &BuildRecord, to: &BuildRecord) -> AppResult<Vec<Value>> {
+    let a = serde_json::to_value(from).map_err(AppError::internal)?;
+    let b = serde_json::to_value(to).map_err(AppError::internal)?;
+    Ok(FIELDS
+        .iter()
+        .filter(|f| a[**f] != b[**f])
+        .map(|f| json!({ "field": f, "from": a[*f], "to": b[*f] }))
+        .collect())
+}
+
+/// config_detail_file 可能带有构建机上的路径, 产物中只保存文件名
+fn snapshot_file(record: &BuildRecord) -> Option<&str> {
+    let path = record.config_detail_file.trim();
+    path.rsplit(['/', '\\']).next().filter(|s| !s.is_empty())
+}
+
+/// 优先使用关联的配置快照, 没有快照也没有上传配置文件时返回 None
+async fn load_snapshot(record: &BuildRecord) -> AppResult<Option<ConfigValues>> {
+    if let Some(id) = record.config_snapshot_id {
+        match config_snapshot::find(id).await {
+            Ok(s) => return Ok(Some(s.values())),
+            Err(AppError::NotFound(_)) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    let file = match snapshot_file(record) {
+        Some(f) => f,
+        None => return Ok(None),
+    };
+    let a = match artifact::find(&record.build_uuid, file).await {
+        Ok(a) => a,
+        Err(AppError::NotFound(_)) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let data = artifact::read_all(&a, MAX_SNAPSHOT_SIZE).await?;
+    Ok(Some(snapshot::parse(&String::from_utf8_lossy(&data))))
+}
+
+fn snapshot_info(record: &BuildRecord, values: &Option<ConfigValues>) -> Value {
+    json!({
+        "snapshot_id": record.config_snapshot_id,
+        "file": snapshot_file(record),
+        "available": values.is_some(),
+        "keys": values.as_ref().map(|v| v.len()),
+    })
+}
+
+/// 比较两个构建的版本信息和配置文件
+#[get("/builds/diff/{from}/{to}")]
+pub async fn diff(id: Identity, path: web::Path<(u32, u32)>) -> AppResult<HttpResponse> {
+    let (from_id, to_id) = path.into_inner();
+    let user = check_user(id).await?;
+    let from = build_record::find_by_id(from_id).await?;
+    let to = build_record::find_by_id(to_id).await?;
+    require(&user, PAGE, Op::Query, Some(from.project_id)).await?;
+    if to.project_id != from.project_id {
+        require(&user, PAGE, Op::Query, Some(to.project_id)).await?;
+    }
+
+    let from_values = load_snapshot(&from).await?;
+    let to_values = load_snapshot(&to).await?;
+    let empty = ConfigValues::new();
+    let config = snapshot::diff(
+        from_values.as_ref().unwrap_or(&empty),
+        to_values.as_ref().unwrap_or(&empty),
+    );
+
+    Ok(response_ok(json!({
+        "from": from,
+        "to": to,
+        "fields": field_changes(&from, &to)?,
+        "config": {
+            "from": snapshot_info(&from, &from_values),
+            "to": snapshot_info(&to, &to_values),
+            "diff": config,
+        },
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{field_changes, snapshot_file};
+    use crate::api::build_record::{test_record, BuildRecord};
+
+    fn record(version_code: i64, config_detail_file: &str) -> BuildRecord {
+        BuildRecord {
+            config_detail_file: config_detail_file.to_string(),
+            ..test_record(version_code)
+        }
+    }
+
+    #[test]
+    fn test_field_changes() {
+        let a = record(1, "config.json");
+        let mut b = record(2, "config.json");
+        b.release_file_arch = Some("arm64-v8a".to_string());
+
+        let changes = field_changes(&a, &b).unwrap();
+        let fields: Vec<&str> = changes
+            .iter()
+            .map(|c| c["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            vec!["version_code", "version_name", "release_file_arch"],
+            fields
+        );
+        assert_eq!(1, changes[0]["from"]);
+        assert_eq!(2, changes[0]["to"]);
+        assert!(field_changes(&a, &a).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_file() {
+        assert_eq!(
+            Some("c.json"),
+            snapshot_file(&record(1, "/build/out/c.json"))
+        );
+        assert_eq!(Some("c.json"), snapshot_file(&record(1, "out\\c.json")));
+        assert_eq!(None, snapshot_file(&record(1, "")));
+        assert_eq!(None, snapshot_file(&record(1, "out/")));
+    }
+}
@@ -1,14 +1,21 @@
 use crate::{
-    mysql::{count, sql_page_str},
-    mysql_query, result_err,
+    artifact::{self, Artifact},
+    error::{AppError, AppResult},
+    mysql::{
+        count_args, execute_affected, insert_args, like_pattern, order_by, sql_page_str, SqlBuilder,
+    },
+    mysql_find_one, mysql_query, sql_args,
 };
 
-use super::page_base::{ListData, PageBase};
+use super::{
+    changelog, config_snapshot,
+    page_base::{ListData, PageBase, QueryInfo},
+};
 use async_trait::async_trait;
 
 use chrono::{DateTime, Utc};
-use log::info;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 #[derive(sqlx::FromRow, Debug, Serialize, Deserialize, Clone)]
 pub struct BuildRecord {
@@ -29,38 +36,439 @@ pub struct BuildRecord {
     pub is_release: Option<i64>,
     pub release_file_arch: Option<String>,
     pub config_tag: String,
+    pub build_status: Option<String>,
+    pub release_state: Option<String>,
+    pub release_request_user: Option<String>,
+    pub release_request_time: Option<DateTime<Utc>>,
+    pub release_user: Option<String>,
+    pub release_time: Option<DateTime<Utc>>,
+    pub release_channel: Option<String>,
+    pub release_note: Option<String>,
+    pub force_update: Option<i64>,
+    pub min_version_code: Option<i64>,
+    pub max_version_code: Option<i64>,
+    /// 使用的配置快照
+    pub config_snapshot_id: Option<i64>,
+}
+
+impl BuildRecord {
+    /// 外部工具写入的旧记录没有状态, 按 build_result 判断成功或失败
+    pub fn status(&self) -> BuildStatus {
+        self.build_status
+            .as_deref()
+            .and_then(BuildStatus::parse)
+            .unwrap_or_else(|| BuildStatus::of_result(&self.build_result))
+    }
+}
+
+/// 测试用的构建记录, 构建成功, 还没有发布
+#[cfg(test)]
+pub fn test_record(version_code: i64) -> BuildRecord {
+    BuildRecord {
+        id: Some(version_code),
+        project_id: 1,
+        revision: "1".to_string(),
+        project_name: "p".to_string(),
+        project_no: "p1".to_string(),
+        app_name: None,
+        svn_url: String::new(),
+        build_user: "ci".to_string(),
+        build_time: Utc::now(),
+        build_result: "success".to_string(),
+        version_code,
+        version_name: format!("1.0.{}", version_code),
+        build_uuid: format!("b{}", version_code),
+        config_detail_file: String::new(),
+        is_release: None,
+        release_file_arch: None,
+        config_tag: String::new(),
+        build_status: None,
+        release_state: None,
+        release_request_user: None,
+        release_request_time: None,
+        release_user: None,
+        release_time: None,
+        release_channel: None,
+        release_note: None,
+        force_update: None,
+        min_version_code: None,
+        max_version_code: None,
+        config_snapshot_id: None,
+    }
+}
+
+pub const SELECT_BUILD: &str = r#"
+select id, project_id, project_no, project_name, svn_url, revision, app_name, build_result, build_user, build_status, build_time, build_uuid, version_code, version_name,
+is_release, release_file_arch, config_detail_file, config_tag,
+release_state, release_request_user, release_request_time, release_user, release_time,
+release_channel, release_note, force_update, min_version_code, max_version_code, config_snapshot_id
+from tb_version_build_record"#;
+
+/// CI 上报的构建状态, 同时写入 build_status 和 build_result
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildStatus {
+    Queued,
+    Running,
+    Success,
+    Failed,
+}
+
+impl BuildStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BuildStatus::Queued => "queued",
+            BuildStatus::Running => "running",
+            BuildStatus::Success => "success",
+            BuildStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<BuildStatus> {
+        match s {
+            "queued" => Some(BuildStatus::Queued),
+            "running" => Some(BuildStatus::Running),
+            "success" => Some(BuildStatus::Success),
+            "failed" => Some(BuildStatus::Failed),
+            _ => None,
+        }
+    }
+
+    /// 旧记录的 build_result, 不是成功的都视为失败
+    pub fn of_result(build_result: &str) -> BuildStatus {
+        match build_result.trim().to_ascii_lowercase().as_str() {
+            "success" | "succeeded" | "successful" | "ok" | "成功" => BuildStatus::Success,
+            _ => BuildStatus::Failed,
+        }
+    }
+
+    pub fn is_final(&self) -> bool {
+        matches!(self, BuildStatus::Success | BuildStatus::Failed)
+    }
+
+    /// 只能往前走, 结束后不能再修改
+    pub fn can_transition_to(&self, next: BuildStatus) -> bool {
+        match self {
+            BuildStatus::Queued => next != BuildStatus::Queued,
+            BuildStatus::Running => next.is_final(),
+            BuildStatus::Success | BuildStatus::Failed => false,
+        }
+    }
+}
+
+/// CI 新建构建记录, 项目编号和名称从 tb_project 读取
+#[derive(Deserialize, Debug)]
+pub struct CreateBuildParams {
+    pub project_id: i64,
+    pub revision: String,
+    pub version_code: i64,
+    pub version_name: String,
+    pub build_uuid: String,
+    pub build_user: Option<String>,
+    pub app_name: Option<String>,
+    /// 默认使用项目的 version_svn_url
+    pub svn_url: Option<String>,
+    /// 有同名的配置快照时关联到构建
+    pub config_tag: Option<String>,
+    /// 指定使用的配置快照, 不传时按 config_tag 查找
+    pub config_snapshot_id: Option<i64>,
+    pub config_detail_file: Option<String>,
+    /// 默认 queued
+    pub status: Option<BuildStatus>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BuildStatusParams {
+    pub status: BuildStatus,
+}
+
+fn check_len(field: &str, value: &str, max: usize) -> AppResult<()> {
+    if value.trim().is_empty() {
+        return Err(AppError::Validation(format!("{} 不能为空", field)));
+    }
+    if value.chars().count() > max {
+        return Err(AppError::Validation(format!(
+            "{} 不能超过 {} 个字符",
+            field, max
+        )));
+    }
+    Ok(())
+}
+
+impl CreateBuildParams {
+    pub fn validate(&self) -> AppResult<()> {
+        if self.project_id <= 0 {
+            return Err(AppError::Validation("project_id 无效".to_string()));
+        }
+        if self.version_code <= 0 {
+            return Err(AppError::Validation("version_code 必须大于0".to_string()));
+        }
+        check_len("version_name", &self.version_name, 64)?;
+        if self.version_name.chars().any(char::is_whitespace) {
+            return Err(AppError::Validation(
+                "version_name 不能包含空白字符".to_string(),
+            ));
+        }
+        check_len("revision", &self.revision, 64)?;
+        check_len("build_uuid", &self.build_uuid, 64)?;
+        if !self
+            .build_uuid
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        {
+            return Err(AppError::Validation(
+                "build_uuid 只能包含字母, 数字, '-', '_' 和 '.'".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+struct ProjectInfo {
+    no: String,
+    name: String,
+    version_svn_url: Option<String>,
+}
+
+pub async fn find_by_uuid(build_uuid: &str) -> AppResult<BuildRecord> {
+    let mut data: Vec<BuildRecord> = Vec::new();
+    mysql_query!(
+        BuildRecord,
+        data,
+        &format!("{} where build_uuid = ?", SELECT_BUILD),
+        &sql_args![build_uuid]
+    )?;
+    data.pop()
+        .ok_or_else(|| AppError::NotFound(format!("构建 {} 不存在", build_uuid)))
+}
+
+pub async fn find_by_id(id: u32) -> AppResult<BuildRecord> {
+    let mut data: Vec<BuildRecord> = Vec::new();
+    mysql_query!(
+        BuildRecord,
+        data,
+        &format!("{} where id = ?", SELECT_BUILD),
+        &sql_args![id]
+    )?;
+    data.pop()
+        .ok_or_else(|| AppError::NotFound(format!("构建 {} 不存在", id)))
+}
+
+/// 新建构建记录, 返回 id
+pub async fn create(params: &CreateBuildParams, build_user: &str) -> AppResult<i64> {
+    params.validate()?;
+
+    let mut projects: Vec<ProjectInfo> = Vec::new();
+    mysql_query!(
+        ProjectInfo,
+        projects,
+        "select no, name, version_svn_url from tb_project where project_id = ? and is_delete is null",
+        &sql_args![params.project_id]
+    )?;
+    let project = projects
+        .pop()
+        .ok_or_else(|| AppError::Validation(format!("项目 {} 不存在", params.project_id)))?;
+
+    let snapshot = config_snapshot::for_build(
+        params.project_id,
+        params.config_snapshot_id,
+        params.config_tag.as_deref(),
+    )
+    .await?;
+    let config_tag = params
+        .config_tag
+        .clone()
+        .or_else(|| snapshot.as_ref().map(|s| s.tag.clone()));
+
+    let status = params.status.unwrap_or(BuildStatus::Queued);
+    let id = insert_args(
+        r#"insert into tb_version_build_record (project_id, project_no, project_name, svn_url, revision, app_name, build_result, build_status, build_user, build_time, build_uuid, version_code, version_name, config_detail_file, config_tag, config_snapshot_id)
+values (?, ?, ?, ?, ?, ?, ?, ?, ?, NOW(), ?, ?, ?, ?, ?, ?)"#,
+        &sql_args![
+            params.project_id,
+            project.no,
+            project.name,
+            params
+                .svn_url
+                .clone()
+                .or(project.version_svn_url)
+                .unwrap_or_default(),
+            &params.revision,
+            params.app_name.clone(),
+            status.as_str(),
+            status.as_str(),
+            params.build_user.as_deref().unwrap_or(build_user),
+            &params.build_uuid,
+            params.version_code,
+            &params.version_name,
+            params.config_detail_file.clone().unwrap_or_default(),
+            config_tag.unwrap_or_default(),
+            snapshot.map(|s| s.id)
+        ],
+    )
+    .await
+    .map_err(|err| match err {
+        // uk_build_record_uuid
+        AppError::Conflict(_) => {
+            AppError::Conflict(format!("build_uuid {} 已存在", params.build_uuid))
+        }
+        e => e,
+    })?;
+
+    Ok(id as i64)
+}
+
+/// 修改构建状态, 状态只能往前走, 并发修改时以先到的为准
+pub async fn update_status(record: &BuildRecord, next: BuildStatus) -> AppResult<()> {
+    let current = record.status();
+
+    if !current.can_transition_to(next) {
+        return Err(AppError::Conflict(format!(
+            "构建状态不能从 {} 改为 {}",
+            current.as_str(),
+            next.as_str()
+        )));
+    }
+
+    let n = execute_affected(
+        "UPDATE tb_version_build_record SET build_status = ?, build_result = ? where id = ? and build_status = ?",
+        &sql_args![next.as_str(), next.as_str(), record.id, current.as_str()],
+    )
+    .await?;
+
+    if n == 0 {
+        return Err(AppError::Conflict("构建状态已被修改, 请重试".to_string()));
+    }
+    Ok(())
+}
+
+/// 列表中每条记录带上它的构建产物和已生成的提交记录
+pub async fn with_details(data: Vec<BuildRecord>) -> AppResult<Vec<Value>> {
+    let uuids: Vec<String> = data.iter().map(|r| r.build_uuid.clone()).collect();
+    let artifacts = artifact::list(&uuids).await?;
+    let ids: Vec<i64> = data.iter().filter_map(|r| r.id).collect();
+    let mut changelogs = changelog::stored(&ids).await?;
+
+    data.into_iter()
+        .map(|r| {
+            let files: Vec<&Artifact> = artifacts
+                .iter()
+                .filter(|a| a.build_uuid == r.build_uuid)
+                .collect();
+            let mut v = serde_json::to_value(&r)?;
+            v["artifacts"] = serde_json::to_value(files)?;
+            v["changelog"] = serde_json::to_value(r.id.and_then(|id| changelogs.remove(&id)))?;
+            Ok(v)
+        })
+        .collect::<Result<Vec<Value>, serde_json::Error>>()
+        .map_err(AppError::internal)
+}
+
+/// 可以排序的字段, 第一个是默认值
+const SORT_FIELDS: [&str; 7] = [
+    "id",
+    "build_time",
+    "version_code",
+    "version_name",
+    "project_id",
+    "build_result",
+    "build_user",
+];
+
+/// query 关键字搜索的字段, 多个关键字用空格分开, 每个关键字都要匹配其中一个字段
+const SEARCH_FIELDS: [&str; 8] = [
+    "project_name",
+    "project_no",
+    "app_name",
+    "version_name",
+    "revision",
+    "build_user",
+    "config_tag",
+    "build_uuid",
+];
+const MAX_SEARCH_TERMS: usize = 5;
+
+fn filter(info: &QueryInfo) -> SqlBuilder {
+    let mut w = SqlBuilder::new("config_tag is not null and build_result is not null");
+
+    if let Some(project) = info.project {
+        w.push(" and project_id = ?").bind(project);
+    }
+
+    if let Some(version) = &info.version {
+        let c = like_pattern(version);
+        w.push(" and (version_code like ? or version_name like ? )")
+            .bind(&c)
+            .bind(&c);
+    }
+
+    let exact = [
+        ("build_result", &info.build_result),
+        ("build_user", &info.build_user),
+        ("config_tag", &info.config_tag),
+        ("revision", &info.revision),
+    ];
+    for (field, value) in exact.iter() {
+        if let Some(v) = value {
+            w.push(&format!(" and {} = ?", field)).bind(v);
+        }
+    }
+
+    match info.is_release {
+        Some(1) => {
+            w.push(" and is_release = 1");
+        }
+        Some(_) => {
+            w.push(" and (is_release is null or is_release <> 1)");
+        }
+        None => {}
+    }
+    if let Some(start) = info.start {
+        w.push(" and build_time >= ?").bind(start);
+    }
+    if let Some(end) = info.end {
+        w.push(" and build_time < ?").bind(end);
+    }
+
+    if let Some(q) = &info.query {
+        for term in q.split_whitespace().take(MAX_SEARCH_TERMS) {
+            let c = like_pattern(term);
+            let fields: Vec<String> = SEARCH_FIELDS
+                .iter()
+                .map(|f| format!("{} like ?", f))
+                .collect();
+            w.push(&format!(" and ({})", fields.join(" or ")));
+            for _ in SEARCH_FIELDS.iter() {
+                w.bind(&c);
+            }
+        }
+    }
+    w
 }
 
 pub struct BuildRecordPage;
 
 #[async_trait]
 impl PageBase for BuildRecordPage {
+    fn filters_by_project(&self) -> bool {
+        true
+    }
+
     #[inline]
-    async fn query(&self, info: &super::page_base::QueryInfo) -> Result<serde_json::Value, String> {
-        let mut w = r#"config_tag is not null and build_result is not null"#.to_string();
+    async fn query(&self, info: &QueryInfo) -> AppResult<serde_json::Value> {
+        let w = filter(info);
+        let order = order_by(info.sort.as_deref(), info.order.as_deref(), &SORT_FIELDS)?;
 
         let limit = info.limit.or(Some(20)).unwrap();
         let page = info.page.or(Some(1)).unwrap();
 
-        if info.project.is_some() {
-            w = format!("{} and project_id={}", w, info.project.unwrap());
-        }
-
-        if info.version.is_some() {
-            let c = info.version.clone().unwrap();
-            w = format!(
-                "{} and (version_code like concat('%{}%') or version_name like concat('%{}%') )",
-                w, &c, &c
-            );
-        }
         let sql = sql_page_str(
             &format!(
-                r#"
-select id, project_id, project_no, project_name, svn_url, revision, app_name, build_result, build_user, build_status, build_time, build_uuid, version_code, version_name,
-is_release, release_file_arch, config_detail_file, config_tag
-from tb_version_build_record where  {}
-                order by id desc"#,
-                &w
+                "{} where {} order by {}, id desc",
+                SELECT_BUILD,
+                w.sql(),
+                order
             ),
             limit,
             page,
@@ -68,29 +476,161 @@ from tb_version_build_record where  {}
 
         let mut data: Vec<BuildRecord> = Vec::new();
 
-        let count = count(&format!(
-            "SELECT COUNT(id) FROM tb_version_build_record 
+        let count = count_args(
+            &format!(
+                "SELECT COUNT(id) FROM tb_version_build_record 
             where {}",
-            &w
-        ))
+                w.sql()
+            ),
+            w.args(),
+        )
         .await?;
 
-        mysql_query!(BuildRecord, data, &sql)?;
+        mysql_query!(BuildRecord, data, &sql, w.args())?;
 
-        Ok(serde_json::to_value(ListData::<BuildRecord> {
+        Ok(serde_json::to_value(ListData::<Value> {
             current_page: page,
             page_size: limit,
             total: count,
-            page_list: data,
+            page_list: with_details(data).await?,
         })
-        .map_err(result_err!())?)
+        .map_err(AppError::internal)?)
+    }
+
+    async fn update(&self, _user: &str, _params: &str) -> AppResult<String> {
+        Err(AppError::NotFound("not found".to_string()))
+    }
+
+    async fn delete(&self, _user: &str, _id: u32) -> AppResult<()> {
+        Err(AppError::NotFound("not found".to_string()))
+    }
+
+    async fn project_of_id(&self, id: u32) -> AppResult<Option<i64>> {
+        let project_id = mysql_find_one!(
+            i64,
+            "select project_id from tb_version_build_record where id = ?",
+            &sql_args![id]
+        )?;
+        Ok(Some(project_id))
+    }
+
+    async fn find(&self, id: &str) -> AppResult<Option<Value>> {
+        let mut data: Vec<BuildRecord> = Vec::new();
+        mysql_query!(
+            BuildRecord,
+            data,
+            &format!("{} where id = ?", SELECT_BUILD),
+            &sql_args![id]
+        )?;
+
+        data.pop()
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(AppError::internal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{filter, test_record, BuildRecord, BuildStatus, CreateBuildParams, QueryInfo};
+    use crate::mysql::Arg;
+
+    fn legacy(build_result: &str) -> BuildRecord {
+        BuildRecord {
+            build_result: build_result.to_string(),
+            ..test_record(10)
+        }
+    }
+
+    #[test]
+    fn test_legacy_status() {
+        assert_eq!(BuildStatus::Success, legacy("success").status());
+        assert_eq!(BuildStatus::Success, legacy("SUCCESS").status());
+        assert_eq!(BuildStatus::Failed, legacy("failed").status());
+        assert_eq!(BuildStatus::Failed, legacy("FAILURE").status());
+        assert_eq!(BuildStatus::Failed, legacy("").status());
+
+        let mut r = legacy("failed");
+        r.build_status = Some("running".to_string());
+        assert_eq!(BuildStatus::Running, r.status());
+    }
+
+    fn params() -> CreateBuildParams {
+        CreateBuildParams {
+            project_id: 1,
+            revision: "1234".to_string(),
+            version_code: 10,
+            version_name: "1.0.10".to_string(),
+            build_uuid: "3f0c9a2e-1b7d-4c1e-9a3b-2d5e6f7a8b9c".to_string(),
+            build_user: None,
+            app_name: None,
+            svn_url: None,
+            config_tag: None,
+            config_snapshot_id: None,
+            config_detail_file: None,
+            status: None,
+        }
     }
 
-    async fn update(&self, _user: &str, _params: &str) -> Result<(), String> {
-        Err("not found".to_string())
+    #[test]
+    fn test_validate() {
+        assert!(params().validate().is_ok());
+
+        let checks: Vec<fn(&mut CreateBuildParams)> = vec![
+            |p| p.project_id = 0,
+            |p| p.version_code = 0,
+            |p| p.version_name = " ".to_string(),
+            |p| p.version_name = "1.0 beta".to_string(),
+            |p| p.revision = String::new(),
+            |p| p.build_uuid = "a/b".to_string(),
+            |p| p.build_uuid = "x".repeat(65),
+        ];
+        for f in checks {
+            let mut p = params();
+            f(&mut p);
+            assert!(p.validate().is_err(), "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let w = filter(&QueryInfo::default());
+        assert_eq!(
+            "config_tag is not null and build_result is not null",
+            w.sql()
+        );
+
+        let info = QueryInfo {
+            project: Some(2),
+            build_user: Some("jenkins".to_string()),
+            is_release: Some(0),
+            start: Some("2021-01-01T00:00:00Z".parse().unwrap()),
+            query: Some("app  1.0".to_string()),
+            ..Default::default()
+        };
+        let w = filter(&info);
+        assert!(w
+            .sql()
+            .contains(" and project_id = ? and build_user = ? and (is_release is null or is_release <> 1) and build_time >= ?"));
+        assert_eq!(2, w.sql().matches("project_name like ?").count());
+        assert_eq!(3 + 2 * 8, w.args().len());
+        assert_eq!(Arg::Str("%1.0%".to_string()), w.args()[w.args().len() - 1]);
     }
 
-    async fn delete(&self, _user: &str, _id: u32) -> Result<(), String> {
-        Err("not found".to_string())
+    #[test]
+    fn test_status_transition() {
+        use BuildStatus::*;
+
+        assert!(Queued.can_transition_to(Running));
+        assert!(Queued.can_transition_to(Failed));
+        assert!(Running.can_transition_to(Success));
+        assert!(!Running.can_transition_to(Queued));
+        assert!(!Success.can_transition_to(Failed));
+        assert!(!Failed.can_transition_to(Running));
+
+        for s in [Queued, Running, Success, Failed].iter() {
+            assert_eq!(Some(*s), BuildStatus::parse(s.as_str()));
+        }
+        assert_eq!(None, BuildStatus::parse("done"));
     }
 }
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+
+use actix_identity::Identity;
+use actix_web::{get, web, HttpResponse};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{AppError, AppResult},
+    http_response::response_ok,
+    mysql::{execute_args, Arg},
+    mysql_query,
+    rbac::{require, Op},
+    sql_args,
+    vcs::{self, LogEntry},
+};
+
+use super::{
+    build_record::{self, BuildRecord, SELECT_BUILD},
+    check_user,
+};
+
+const PAGE: &str = "versionbuildrecord";
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Changelog {
+    pub build_id: i64,
+    pub from_build_id: Option<i64>,
+    pub from_revision: Option<String>,
+    pub to_revision: String,
+    pub entries: Vec<LogEntry>,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+struct ChangelogRow {
+    build_id: i64,
+    from_build_id: Option<i64>,
+    from_revision: Option<String>,
+    to_revision: String,
+    entries: String,
+}
+
+impl From<ChangelogRow> for Changelog {
+    fn from(row: ChangelogRow) -> Self {
+        Changelog {
+            build_id: row.build_id,
+            from_build_id: row.from_build_id,
+            from_revision: row.from_revision,
+            to_revision: row.to_revision,
+            entries: serde_json::from_str(&row.entries).unwrap_or_default(),
+        }
+    }
+}
+
+/// 已生成的提交记录, 没有生成的构建不在结果中
+pub async fn stored(build_ids: &[i64]) -> AppResult<HashMap<i64, Changelog>> {
+    if build_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let marks = vec!["?"; build_ids.len()].join(", ");
+    let args: Vec<Arg> = build_ids.iter().map(|id| Arg::from(*id)).collect();
+
+    let mut data: Vec<ChangelogRow> = Vec::new();
+    mysql_query!(
+        ChangelogRow,
+        data,
+        &format!(
+            "select build_id, from_build_id, from_revision, to_revision, entries from tb_build_changelog where build_id in ({})",
+            marks
+        ),
+        &args
+    )?;
+    Ok(data
+        .into_iter()
+        .map(|r| (r.build_id, Changelog::from(r)))
+        .collect())
+}
+
+async fn save(c: &Changelog) -> AppResult<()> {
+    let entries = serde_json::to_string(&c.entries).map_err(AppError::internal)?;
+    execute_args(
+        r#"insert into tb_build_changelog (build_id, from_build_id, from_revision, to_revision, entries, create_time)
+values (?, ?, ?, ?, ?, NOW())
+on duplicate key update from_build_id = values(from_build_id), from_revision = values(from_revision),
+to_revision = values(to_revision), entries = values(entries), create_time = values(create_time)"#,
+        &sql_args![
+            c.build_id,
+            c.from_build_id,
+            c.from_revision.clone(),
+            &c.to_revision,
+            &entries
+        ],
+    )
+    .await
+}
+
+/// 同一项目中 version_code 更小的最近一次发布, 没有发布过时为上一次构建
+async fn previous(record: &BuildRecord) -> AppResult<Option<BuildRecord>> {
+    for cond in ["and is_release = 1", ""].iter() {
+        let mut data: Vec<BuildRecord> = Vec::new();
+        mysql_query!(
+            BuildRecord,
+            data,
+            &format!(
+                "{} where project_id = ? and version_code < ? and id <> ? {} order by version_code desc, id desc limit 1",
+                SELECT_BUILD, cond
+            ),
+            &sql_args![record.project_id, record.version_code, record.id]
+        )?;
+        if let Some(r) = data.pop() {
+            return Ok(Some(r));
+        }
+    }
+    Ok(None)
+}
+
+/// from 为空时没有提交记录
+pub async fn between(from: Option<&BuildRecord>, to: &BuildRecord) -> AppResult<Changelog> {
+    let entries = match from {
+        Some(from) if from.revision != to.revision => {
+            if to.svn_url.trim().is_empty() {
+                return Err(AppError::Validation(format!(
+                    "构建 {} 没有版本库地址",
+                    to.build_uuid
+                )));
+            }
+            vcs::log(to.svn_url.trim(), from.revision.trim(), to.revision.trim()).await?
+        }
+        _ => Vec::new(),
+    };
+
+    Ok(Changelog {
+        build_id: to.id.unwrap_or_default(),
+        from_build_id: from.and_then(|r| r.id),
+        from_revision: from.map(|r| r.revision.clone()),
+        to_revision: to.revision.clone(),
+        entries,
+    })
+}
+
+/// 生成相对于上一次发布的提交记录并保存
+pub async fn generate(record: &BuildRecord) -> AppResult<Changelog> {
+    let from = previous(record).await?;
+    let c = between(from.as_ref(), record).await?;
+    save(&c).await?;
+    Ok(c)
+}
+
+/// 发布时在后台生成, 失败只记录日志, 之后可以通过接口重新生成
+pub fn spawn_generate(record: BuildRecord) {
+    actix_web::rt::spawn(async move {
+        if let Err(err) = generate(&record).await {
+            info!("changelog of {} err = {}", record.build_uuid, err);
+        }
+    });
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChangelogQuery {
+    /// 和指定的构建比较, 不保存
+    pub from: Option<u32>,
+    /// 重新生成已保存的记录
+    pub refresh: Option<bool>,
+}
+
+/// 构建的提交记录, 默认相对于上一次发布
+#[get("/builds/changelog/{id}")]
+pub async fn detail(
+    id: Identity,
+    path: web::Path<(u32,)>,
+    info: web::Query<ChangelogQuery>,
+) -> AppResult<HttpResponse> {
+    let build_id = path.into_inner().0;
+    let record = build_record::find_by_id(build_id).await?;
+    let user = check_user(id).await?;
+    require(&user, PAGE, Op::Query, Some(record.project_id)).await?;
+
+    let c = match info.from {
+        Some(from) => {
+            let from = build_record::find_by_id(from).await?;
+            if from.project_id != record.project_id {
+                return Err(AppError::Validation("只能比较同一项目的构建".to_string()));
+            }
+            between(Some(&from), &record).await?
+        }
+        None => {
+            let saved = if info.refresh == Some(true) {
+                None
+            } else {
+                stored(&[build_id as i64]).await?.remove(&(build_id as i64))
+            };
+            match saved {
+                Some(c) => c,
+                None => generate(&record).await?,
+            }
+        }
+    };
+    Ok(response_ok(
+        serde_json::to_value(c).map_err(AppError::internal)?,
+    ))
+}
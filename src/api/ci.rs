@@ -0,0 +1,125 @@
+use actix_identity::Identity;
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    audit::{ACTION_CREATE, ACTION_UPDATE},
+    ci_token,
+    error::{AppError, AppResult},
+    http_response::{response_ok, response_success},
+    mysql_find_one,
+    rbac::{require, Op},
+    sql_args,
+};
+
+use super::{
+    _audit,
+    build_record::{self, BuildStatusParams, CreateBuildParams},
+    check_user, client_ip, page_of,
+};
+
+const PAGE: &str = "versionbuildrecord";
+
+#[derive(Deserialize, Debug)]
+pub struct CreateTokenParams {
+    pub name: String,
+    pub project_id: Option<i64>,
+}
+
+/// 新建 token, 明文只在这里返回一次
+#[post("/ci/tokens")]
+pub async fn create_token(
+    id: Identity,
+    params: web::Json<CreateTokenParams>,
+) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    require(&user, "ci", Op::Admin, None).await?;
+
+    if params.name.trim().is_empty() {
+        return Err(AppError::Validation("name 不能为空".to_string()));
+    }
+    if let Some(project_id) = params.project_id {
+        mysql_find_one!(
+            i64,
+            "select project_id from tb_project where project_id = ? and is_delete is null",
+            &sql_args![project_id]
+        )
+        .map_err(|_| AppError::Validation(format!("项目 {} 不存在", project_id)))?;
+    }
+
+    let (token_id, token) =
+        ci_token::create(params.name.trim(), params.project_id, &user.name).await?;
+    Ok(response_ok(json!({ "id": token_id, "token": token })))
+}
+
+#[get("/ci/tokens")]
+pub async fn tokens(id: Identity) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    require(&user, "ci", Op::Admin, None).await?;
+
+    Ok(response_ok(
+        serde_json::to_value(ci_token::list().await?).map_err(AppError::internal)?,
+    ))
+}
+
+#[delete("/ci/tokens/{id}")]
+pub async fn revoke_token(id: Identity, path: web::Path<(u32,)>) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    require(&user, "ci", Op::Admin, None).await?;
+
+    ci_token::revoke(path.into_inner().0).await?;
+    Ok(response_success("成功"))
+}
+
+/// CI 新建构建记录, 使用 `Authorization: Bearer <token>`
+#[post("/ci/builds")]
+pub async fn create_build(
+    req: HttpRequest,
+    params: web::Json<CreateBuildParams>,
+) -> AppResult<HttpResponse> {
+    let ci = ci_token::authenticate(&req).await?;
+    ci.check_project(params.project_id)?;
+
+    let build_id = build_record::create(&params, &ci.name).await?;
+    let entity_id = build_id.to_string();
+
+    _audit(crate::audit::Entry {
+        username: &ci.actor(),
+        page: PAGE,
+        entity_id: Some(&entity_id),
+        action: ACTION_CREATE,
+        client_ip: client_ip(&req).as_deref(),
+        before: None,
+        after: page_of(PAGE).find(&entity_id).await?,
+    })
+    .await;
+    Ok(response_ok(json!({ "id": build_id })))
+}
+
+#[post("/ci/builds/{build_uuid}/status")]
+pub async fn update_build_status(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    params: web::Json<BuildStatusParams>,
+) -> AppResult<HttpResponse> {
+    let ci = ci_token::authenticate(&req).await?;
+    let record = build_record::find_by_uuid(&path.into_inner().0).await?;
+    ci.check_project(record.project_id)?;
+
+    let build_id = record.id.unwrap_or_default().to_string();
+    let before = page_of(PAGE).find(&build_id).await?;
+    build_record::update_status(&record, params.status).await?;
+
+    _audit(crate::audit::Entry {
+        username: &ci.actor(),
+        page: PAGE,
+        entity_id: Some(&build_id),
+        action: ACTION_UPDATE,
+        client_ip: client_ip(&req).as_deref(),
+        before,
+        after: page_of(PAGE).find(&build_id).await?,
+    })
+    .await;
+    Ok(response_success("成功"))
+}
@@ -0,0 +1,219 @@
+use actix_identity::Identity;
+use actix_web::{get, http::header, post, web, HttpRequest, HttpResponse};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    config_io::{self, ConfigDef, Format, ImportPlan},
+    error::{AppError, AppResult},
+    http_response::response_ok,
+    mysql::Tx,
+    rbac::{require, Op},
+    sql_args,
+};
+
+use super::{
+    _audit, check_user, client_ip,
+    mdm45_config::{self, MdmConfig},
+};
+
+/// 配置项是全局的, 需要全局权限
+const PAGE: &str = "versionconfigmdm45";
+/// 导入内容的大小上限
+const MAX_IMPORT_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Deserialize, Debug)]
+pub struct ExportQuery {
+    pub format: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ImportQuery {
+    pub format: String,
+    /// 默认只返回差异, 为 false 时才写入
+    pub dry_run: Option<bool>,
+    /// 删除导入内容中没有的配置项
+    #[serde(default)]
+    pub prune: bool,
+}
+
+impl From<&MdmConfig> for ConfigDef {
+    fn from(c: &MdmConfig) -> Self {
+        ConfigDef {
+            config_key: c.config_key.clone(),
+            config_name: c.config_name.clone(),
+            config_type: c.config_type.clone(),
+            category: c.category.clone(),
+            module: c.module.clone(),
+            sort: c.sort,
+            remark: c.remark.clone(),
+            constraints: c.constraints.clone(),
+        }
+        .normalize()
+    }
+}
+
+/// 导出所有未删除的配置项
+#[get("/config/definitions/export")]
+pub async fn export(id: Identity, query: web::Query<ExportQuery>) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    require(&user, PAGE, Op::Query, None).await?;
+    let format = Format::parse(&query.format)?;
+
+    let defs: Vec<ConfigDef> = mdm45_config::all()
+        .await?
+        .iter()
+        .map(ConfigDef::from)
+        .collect();
+    let content = config_io::export(format, &defs)?;
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, format.content_type()))
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", format.file_name()),
+        ))
+        .body(content))
+}
+
+async fn read_body(mut payload: web::Payload) -> AppResult<String> {
+    let mut data = Vec::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| AppError::Validation(format!("读取失败: {}", e)))?;
+        if data.len() + chunk.len() > MAX_IMPORT_SIZE {
+            return Err(AppError::Validation(format!(
+                "导入内容超过 {} 字节",
+                MAX_IMPORT_SIZE
+            )));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    String::from_utf8(data).map_err(|_| AppError::Validation("导入内容不是 utf-8".to_string()))
+}
+
+fn id_of(current: &[MdmConfig], key: &str) -> AppResult<i64> {
+    current
+        .iter()
+        .find(|c| c.config_key == key)
+        .and_then(|c| c.id)
+        .ok_or_else(|| AppError::Conflict(format!("配置项 {} 已被修改, 请重试", key)))
+}
+
+/// 在一个事务中写入, 任何一条失败都回滚
+async fn apply(user: &str, current: &[MdmConfig], plan: &ImportPlan) -> AppResult<()> {
+    let mut tx = Tx::begin().await?;
+    for d in &plan.added {
+        tx.execute(
+            "insert into tb_version_config_mdm45 (create_time, config_key, config_name, category, create_user, remark, module, sort, config_type, constraints)
+values (NOW(), ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            &sql_args![
+                &d.config_key,
+                d.config_name.clone(),
+                &d.category,
+                user,
+                d.remark.clone(),
+                &d.module,
+                d.sort,
+                &d.config_type,
+                d.constraints.clone()
+            ],
+        )
+        .await?;
+    }
+    for c in &plan.changed {
+        let d = &c.to;
+        let n = tx
+            .execute(
+                r#"UPDATE tb_version_config_mdm45
+SET config_name = ?, category = ?, update_user = ?, remark = ?, module = ?, sort = ?, config_type = ?, constraints = ?, update_time = NOW()
+where id = ? and is_delete is null"#,
+                &sql_args![
+                    d.config_name.clone(),
+                    &d.category,
+                    user,
+                    d.remark.clone(),
+                    &d.module,
+                    d.sort,
+                    &d.config_type,
+                    d.constraints.clone(),
+                    id_of(current, &c.key)?
+                ],
+            )
+            .await?;
+        if n == 0 {
+            return Err(AppError::Conflict(format!(
+                "配置项 {} 已被删除, 请重试",
+                c.key
+            )));
+        }
+    }
+    for d in &plan.removed {
+        tx.execute(
+            "UPDATE tb_version_config_mdm45 SET is_delete = 'Y', update_user = ?, update_time = NOW(), delete_user = ?, delete_time = NOW() where id = ? and is_delete is null",
+            &sql_args![user, user, id_of(current, &d.config_key)?],
+        )
+        .await?;
+    }
+    tx.commit().await
+}
+
+/// 导入 json / yaml / csv, 默认只返回差异
+#[post("/config/definitions/import")]
+pub async fn import(
+    id: Identity,
+    req: HttpRequest,
+    query: web::Query<ImportQuery>,
+    payload: web::Payload,
+) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    let dry_run = query.dry_run.unwrap_or(true);
+    require(&user, PAGE, Op::Query, None).await?;
+    if !dry_run {
+        require(&user, PAGE, Op::Update, None).await?;
+    }
+    let format = Format::parse(&query.format)?;
+
+    let incoming = config_io::parse(format, &read_body(payload).await?)?;
+    let current = mdm45_config::all().await?;
+    let defs: Vec<ConfigDef> = current.iter().map(ConfigDef::from).collect();
+    let plan = config_io::plan(&defs, &incoming, query.prune);
+
+    let applied = !dry_run && !plan.is_empty();
+    if applied {
+        if !plan.removed.is_empty() {
+            require(&user, PAGE, Op::Delete, None).await?;
+        }
+        apply(&user.name, &current, &plan).await?;
+
+        let before: Vec<&ConfigDef> = plan
+            .changed
+            .iter()
+            .map(|c| &c.from)
+            .chain(plan.removed.iter())
+            .collect();
+        let after: Vec<&ConfigDef> = plan
+            .added
+            .iter()
+            .chain(plan.changed.iter().map(|c| &c.to))
+            .collect();
+        _audit(crate::audit::Entry {
+            username: &user.username,
+            page: PAGE,
+            entity_id: None,
+            action: "import",
+            client_ip: client_ip(&req).as_deref(),
+            before: Some(json!(before)),
+            after: Some(json!(after)),
+        })
+        .await;
+    }
+
+    Ok(response_ok(json!({
+        "dry_run": dry_run,
+        "applied": applied,
+        "added": plan.added,
+        "changed": plan.changed,
+        "removed": plan.removed,
+        "unchanged": plan.unchanged,
+    })))
+}
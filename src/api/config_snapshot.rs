@@ -0,0 +1,482 @@
+use std::collections::HashMap;
+
+use actix_identity::Identity;
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    config_value::Scope,
+    error::{AppError, AppResult, FieldError},
+    http_response::{response_ok, response_success},
+    mysql::{count_args, execute_affected, insert_args, sql_page_str, Arg},
+    mysql_query,
+    rbac::{require, Op},
+    sha::sha256_hex,
+    snapshot::ConfigValues,
+    sql_args,
+};
+
+use super::{
+    _audit,
+    build_record::{BuildRecord, SELECT_BUILD},
+    check_user, client_ip, config_value, mdm45_config,
+    page_base::ListData,
+    CurrentUser,
+};
+
+/// 和配置项使用同一个权限
+const PAGE: &str = "versionconfigmdm45";
+const AUDIT_PAGE: &str = "configsnapshot";
+
+#[derive(sqlx::FromRow, Debug, Serialize, Clone)]
+pub struct ConfigSnapshot {
+    pub id: i64,
+    pub project_id: i64,
+    pub version_id: i64,
+    pub tag: String,
+    pub content_hash: String,
+    /// 列表中不返回, 详情中为 values
+    #[serde(skip)]
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remark: Option<String>,
+    pub create_user: String,
+    pub create_time: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update_user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update_time: Option<DateTime<Utc>>,
+}
+
+impl ConfigSnapshot {
+    pub fn values(&self) -> ConfigValues {
+        serde_json::from_str(&self.content).unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FreezeParams {
+    pub tag: String,
+    /// 冻结项目中该版本的值集
+    pub version: Option<i64>,
+    pub remark: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SnapshotParams {
+    pub tag: Option<String>,
+    pub remark: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListQuery {
+    pub limit: Option<u32>,
+    pub page: Option<u32>,
+}
+
+/// 引用快照的构建数
+#[derive(sqlx::FromRow, Debug, Serialize, Clone, Default, PartialEq)]
+pub struct References {
+    #[serde(skip)]
+    pub id: i64,
+    pub builds: i64,
+    pub released: i64,
+}
+
+/// 按 key 排序的 json 和它的 sha256, 相同的配置得到相同的 hash
+pub fn content(values: &ConfigValues) -> AppResult<(String, String)> {
+    let text = serde_json::to_string(values).map_err(AppError::internal)?;
+    let hash = sha256_hex(&text);
+    Ok((text, hash))
+}
+
+fn check_tag(tag: &str) -> AppResult<()> {
+    if tag.is_empty() || tag.chars().count() > 128 || tag.chars().any(char::is_whitespace) {
+        return Err(AppError::Fields(vec![FieldError::new(
+            "tag",
+            "不能为空, 不能包含空白字符且不超过 128 个字符",
+        )]));
+    }
+    Ok(())
+}
+
+/// 已经被发布的构建引用的快照不能修改
+fn check_editable(snapshot: &ConfigSnapshot, refs: &References) -> AppResult<()> {
+    if refs.released > 0 {
+        return Err(AppError::Conflict(format!(
+            "快照 {} 已被 {} 个发布的构建使用, 不能修改",
+            snapshot.tag, refs.released
+        )));
+    }
+    Ok(())
+}
+
+const SELECT_SNAPSHOT: &str = "select id, project_id, version_id, tag, content_hash, content, remark, create_user, create_time, update_user, update_time from tb_config_snapshot";
+
+pub async fn find(id: i64) -> AppResult<ConfigSnapshot> {
+    let mut data: Vec<ConfigSnapshot> = Vec::new();
+    mysql_query!(
+        ConfigSnapshot,
+        data,
+        &format!("{} where id = ?", SELECT_SNAPSHOT),
+        &sql_args![id]
+    )?;
+    data.pop()
+        .ok_or_else(|| AppError::NotFound(format!("配置快照 {} 不存在", id)))
+}
+
+pub async fn find_by_tag(project_id: i64, tag: &str) -> AppResult<Option<ConfigSnapshot>> {
+    let mut data: Vec<ConfigSnapshot> = Vec::new();
+    mysql_query!(
+        ConfigSnapshot,
+        data,
+        &format!("{} where project_id = ? and tag = ?", SELECT_SNAPSHOT),
+        &sql_args![project_id, tag]
+    )?;
+    Ok(data.pop())
+}
+
+/// 新建构建时使用的快照: 指定了 id 时必须属于该项目, 否则按 config_tag 查找, 没有时为 None
+pub async fn for_build(
+    project_id: i64,
+    snapshot_id: Option<i64>,
+    tag: Option<&str>,
+) -> AppResult<Option<ConfigSnapshot>> {
+    match (snapshot_id, tag) {
+        (Some(id), _) => {
+            let s = find(id).await?;
+            if s.project_id != project_id {
+                return Err(AppError::Validation(format!(
+                    "配置快照 {} 不属于项目 {}",
+                    id, project_id
+                )));
+            }
+            Ok(Some(s))
+        }
+        (None, Some(tag)) if !tag.trim().is_empty() => find_by_tag(project_id, tag.trim()).await,
+        _ => Ok(None),
+    }
+}
+
+/// 没有被引用的快照不在结果中
+pub async fn references(ids: &[i64]) -> AppResult<HashMap<i64, References>> {
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let marks = vec!["?"; ids.len()].join(", ");
+    let args: Vec<Arg> = ids.iter().map(|id| Arg::from(*id)).collect();
+
+    let mut data: Vec<References> = Vec::new();
+    mysql_query!(
+        References,
+        data,
+        &format!(
+            r#"select config_snapshot_id as id, count(id) as builds,
+cast(sum(case when is_release = 1 or release_state = 'released' then 1 else 0 end) as signed) as released
+from tb_version_build_record where config_snapshot_id in ({}) group by config_snapshot_id"#,
+            marks
+        ),
+        &args
+    )?;
+    Ok(data.into_iter().map(|r| (r.id, r)).collect())
+}
+
+async fn references_of(id: i64) -> AppResult<References> {
+    Ok(references(&[id]).await?.remove(&id).unwrap_or_default())
+}
+
+async fn authorize(id: Identity, project_id: i64, op: Op) -> AppResult<CurrentUser> {
+    let user = check_user(id).await?;
+    require(&user, PAGE, op, Some(project_id)).await?;
+    Ok(user)
+}
+
+async fn audit(
+    req: &HttpRequest,
+    user: &CurrentUser,
+    action: &str,
+    id: i64,
+    before: Option<&ConfigSnapshot>,
+    after: Option<&ConfigSnapshot>,
+) {
+    _audit(crate::audit::Entry {
+        username: &user.username,
+        page: AUDIT_PAGE,
+        entity_id: Some(&id.to_string()),
+        action,
+        client_ip: client_ip(req).as_deref(),
+        before: before.and_then(|s| serde_json::to_value(s).ok()),
+        after: after.and_then(|s| serde_json::to_value(s).ok()),
+    })
+    .await;
+}
+
+/// 冻结项目当前的最终配置; 同一个 tag 内容相同时返回已有的快照
+#[post("/config/snapshots/{project}")]
+pub async fn freeze(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(i64,)>,
+    params: web::Json<FreezeParams>,
+) -> AppResult<HttpResponse> {
+    let scope = Scope::new(path.into_inner().0, params.version)?;
+    if scope.project_id == 0 {
+        return Err(AppError::Validation("需要指定项目".to_string()));
+    }
+    let user = authorize(id, scope.project_id, Op::Update).await?;
+    config_value::check_scope(scope).await?;
+    let tag = params.tag.trim();
+    check_tag(tag)?;
+
+    let keys: Vec<String> = mdm45_config::all()
+        .await?
+        .into_iter()
+        .map(|d| d.config_key)
+        .collect();
+    let mut values = crate::config_value::effective_values(scope).await?;
+    values.retain(|k, _| keys.contains(k));
+    let (text, hash) = content(&values)?;
+
+    if let Some(s) = find_by_tag(scope.project_id, tag).await? {
+        if s.content_hash != hash {
+            return Err(AppError::Conflict(format!(
+                "快照 {} 已存在且内容不同, 请使用新的 tag",
+                tag
+            )));
+        }
+        return Ok(response_ok(json!({ "snapshot": s, "created": false })));
+    }
+
+    let id = insert_args(
+        r#"insert into tb_config_snapshot (project_id, version_id, tag, content_hash, content, remark, create_user, create_time)
+values (?, ?, ?, ?, ?, ?, ?, NOW())"#,
+        &sql_args![
+            scope.project_id,
+            scope.version_id,
+            tag,
+            &hash,
+            text,
+            params.remark.clone(),
+            &user.username
+        ],
+    )
+    .await? as i64;
+
+    let s = find(id).await?;
+    audit(&req, &user, "freeze", id, None, Some(&s)).await;
+    Ok(response_ok(json!({ "snapshot": s, "created": true })))
+}
+
+/// 项目的快照, 新的在前
+#[get("/config/snapshots/{project}")]
+pub async fn by_project(
+    id: Identity,
+    path: web::Path<(i64,)>,
+    query: web::Query<ListQuery>,
+) -> AppResult<HttpResponse> {
+    let project_id = path.into_inner().0;
+    authorize(id, project_id, Op::Query).await?;
+    let limit = query.limit.unwrap_or(20);
+    let page = query.page.unwrap_or(1);
+
+    let total = count_args(
+        "SELECT COUNT(id) FROM tb_config_snapshot where project_id = ?",
+        &sql_args![project_id],
+    )
+    .await?;
+    let mut data: Vec<ConfigSnapshot> = Vec::new();
+    mysql_query!(
+        ConfigSnapshot,
+        data,
+        &sql_page_str(
+            &format!("{} where project_id = ? order by id desc", SELECT_SNAPSHOT),
+            limit,
+            page
+        )?,
+        &sql_args![project_id]
+    )?;
+
+    let ids: Vec<i64> = data.iter().map(|s| s.id).collect();
+    let mut refs = references(&ids).await?;
+    let list: Vec<Value> = data
+        .iter()
+        .map(|s| {
+            let mut v = serde_json::to_value(s).unwrap_or_default();
+            v["keys"] = json!(s.values().len());
+            v["references"] = json!(refs.remove(&s.id).unwrap_or_default());
+            v
+        })
+        .collect();
+    Ok(response_ok(json!(ListData::<Value> {
+        current_page: page,
+        page_size: limit,
+        total,
+        page_list: list,
+    })))
+}
+
+/// 快照的内容和使用它的构建
+#[get("/config/snapshots/detail/{id}")]
+pub async fn detail(id: Identity, path: web::Path<(i64,)>) -> AppResult<HttpResponse> {
+    let s = find(path.into_inner().0).await?;
+    authorize(id, s.project_id, Op::Query).await?;
+
+    let mut builds: Vec<BuildRecord> = Vec::new();
+    mysql_query!(
+        BuildRecord,
+        builds,
+        &format!(
+            "{} where config_snapshot_id = ? order by id desc limit 100",
+            SELECT_BUILD
+        ),
+        &sql_args![s.id]
+    )?;
+    Ok(response_ok(json!({
+        "snapshot": s,
+        "values": s.values(),
+        "references": references_of(s.id).await?,
+        "builds": builds,
+    })))
+}
+
+/// 只能修改 tag 和备注, 内容不能修改
+#[post("/config/snapshots/update/{id}")]
+pub async fn update(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(i64,)>,
+    params: web::Json<SnapshotParams>,
+) -> AppResult<HttpResponse> {
+    let before = find(path.into_inner().0).await?;
+    let user = authorize(id, before.project_id, Op::Update).await?;
+    check_editable(&before, &references_of(before.id).await?)?;
+
+    let tag = params.tag.as_deref().map(str::trim);
+    if let Some(tag) = tag {
+        check_tag(tag)?;
+        if tag != before.tag && find_by_tag(before.project_id, tag).await?.is_some() {
+            return Err(AppError::Conflict(format!("快照 {} 已存在", tag)));
+        }
+    }
+    // 检查之后可能有构建发布, 写入时再判断一次
+    let n = execute_affected(
+        r#"UPDATE tb_config_snapshot SET tag = ?, remark = ?, update_user = ?, update_time = NOW()
+where id = ? and not exists (select 1 from tb_version_build_record where config_snapshot_id = ? and (is_release = 1 or release_state = 'released'))"#,
+        &sql_args![
+            tag.unwrap_or(&before.tag),
+            params.remark.clone().or_else(|| before.remark.clone()),
+            &user.username,
+            before.id,
+            before.id
+        ],
+    )
+    .await?;
+    if n == 0 {
+        return Err(AppError::Conflict(format!(
+            "快照 {} 已被发布的构建使用, 不能修改",
+            before.tag
+        )));
+    }
+
+    let after = find(before.id).await?;
+    audit(
+        &req,
+        &user,
+        "update",
+        before.id,
+        Some(&before),
+        Some(&after),
+    )
+    .await;
+    Ok(response_ok(json!({ "snapshot": after })))
+}
+
+/// 被构建引用的快照不能删除
+#[delete("/config/snapshots/{id}")]
+pub async fn remove(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(i64,)>,
+) -> AppResult<HttpResponse> {
+    let s = find(path.into_inner().0).await?;
+    let user = authorize(id, s.project_id, Op::Delete).await?;
+    let refs = references_of(s.id).await?;
+    check_editable(&s, &refs)?;
+    if refs.builds > 0 {
+        return Err(AppError::Conflict(format!(
+            "快照 {} 已被 {} 个构建使用, 不能删除",
+            s.tag, refs.builds
+        )));
+    }
+
+    let n = execute_affected(
+        "DELETE FROM tb_config_snapshot where id = ? and not exists (select 1 from tb_version_build_record where config_snapshot_id = ?)",
+        &sql_args![s.id, s.id],
+    )
+    .await?;
+    if n == 0 {
+        return Err(AppError::Conflict(format!(
+            "快照 {} 已被构建使用, 不能删除",
+            s.tag
+        )));
+    }
+    audit(&req, &user, "delete", s.id, Some(&s), None).await;
+    Ok(response_success("成功"))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::{check_editable, check_tag, content, ConfigSnapshot, References};
+    use crate::{error::AppError, snapshot::ConfigValues};
+
+    #[test]
+    fn test_content() {
+        let mut a = ConfigValues::new();
+        a.insert("b".to_string(), "2".to_string());
+        a.insert("a".to_string(), "1".to_string());
+        let (text, hash) = content(&a).unwrap();
+        assert_eq!(r#"{"a":"1","b":"2"}"#, text);
+        assert_eq!(64, hash.len());
+
+        let mut b = a.clone();
+        assert_eq!(hash, content(&b).unwrap().1);
+        b.insert("a".to_string(), "3".to_string());
+        assert_ne!(hash, content(&b).unwrap().1);
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(check_tag("v1.2-prod").is_ok());
+        assert!(check_tag("").is_err());
+        assert!(check_tag("a b").is_err());
+
+        let s = ConfigSnapshot {
+            id: 1,
+            project_id: 1,
+            version_id: 0,
+            tag: "v1".to_string(),
+            content_hash: String::new(),
+            content: "{}".to_string(),
+            remark: None,
+            create_user: "u".to_string(),
+            create_time: Utc::now(),
+            update_user: None,
+            update_time: None,
+        };
+        let mut refs = References {
+            id: 1,
+            builds: 2,
+            released: 0,
+        };
+        assert!(check_editable(&s, &refs).is_ok());
+        refs.released = 1;
+        assert!(matches!(
+            check_editable(&s, &refs),
+            Err(AppError::Conflict(_))
+        ));
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::BTreeMap;
+
+use actix_identity::Identity;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    config_type::{check_value, Constraints},
+    config_value::{self, Scope},
+    error::{AppError, AppResult, FieldError},
+    http_response::response_ok,
+    mysql::count_args,
+    rbac::{require, Op},
+    sql_args,
+};
+
+use super::{
+    _audit, check_user, client_ip,
+    mdm45_config::{self, MdmConfig},
+    CurrentUser,
+};
+
+/// 和配置项使用同一个权限
+const PAGE: &str = "versionconfigmdm45";
+const AUDIT_PAGE: &str = "configvalue";
+
+#[derive(Deserialize, Debug)]
+pub struct ScopeQuery {
+    pub version: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ValuesParams {
+    /// 值集名称, 不传时不修改
+    pub name: Option<String>,
+    /// config_key -> 值, null 为删除覆盖, 恢复继承的值
+    #[serde(default)]
+    pub values: BTreeMap<String, Value>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CopyParams {
+    /// 0 为默认值集
+    pub from_project: i64,
+    pub from_version: Option<i64>,
+    /// 目标值集的版本, 不传时为项目的值集
+    pub version: Option<i64>,
+    /// 目标已经覆盖的 key 是否也替换
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+/// 项目 0 为默认值集, 需要全局权限
+async fn authorize(id: Identity, scope: Scope, op: Op) -> AppResult<CurrentUser> {
+    let user = check_user(id).await?;
+    let project = if scope.project_id == 0 {
+        None
+    } else {
+        Some(scope.project_id)
+    };
+    require(&user, PAGE, op, project).await?;
+    Ok(user)
+}
+
+/// 项目和版本必须存在且没有删除
+pub async fn check_scope(scope: Scope) -> AppResult<()> {
+    if scope.project_id != 0
+        && count_args(
+            "SELECT COUNT(*) FROM tb_project where project_id = ? and is_delete is null",
+            &sql_args![scope.project_id],
+        )
+        .await?
+            == 0
+    {
+        return Err(AppError::NotFound(format!(
+            "项目 {} 不存在",
+            scope.project_id
+        )));
+    }
+    if scope.version_id != 0
+        && count_args(
+            "SELECT COUNT(*) FROM tb_version_mdm45 where id = ? and is_delete is null",
+            &sql_args![scope.version_id],
+        )
+        .await?
+            == 0
+    {
+        return Err(AppError::NotFound(format!(
+            "版本 {} 不存在",
+            scope.version_id
+        )));
+    }
+    Ok(())
+}
+
+fn keys(defs: &[MdmConfig]) -> Vec<String> {
+    defs.iter().map(|d| d.config_key.clone()).collect()
+}
+
+/// 字符串原样使用, 其他 json 值转成文本; 检查 key 是否定义和值是否符合类型
+pub fn changes(
+    defs: &[MdmConfig],
+    values: &BTreeMap<String, Value>,
+) -> AppResult<BTreeMap<String, Option<String>>> {
+    let mut changes = BTreeMap::new();
+    let mut errors = Vec::new();
+    for (key, value) in values {
+        let def = match defs.iter().find(|d| &d.config_key == key) {
+            Some(d) => d,
+            None => {
+                errors.push(FieldError::new(key, "没有定义"));
+                continue;
+            }
+        };
+        let text = match value {
+            Value::Null => None,
+            Value::String(s) => Some(s.clone()),
+            v => Some(v.to_string()),
+        };
+        if let Some(text) = &text {
+            let checked = Constraints::parse(def.constraints.as_deref())
+                .map(|c| check_value(key, &def.config_type, &c, text));
+            match checked {
+                Ok(Some(e)) => errors.push(e),
+                Ok(None) => {}
+                Err(e) => errors.push(FieldError::new(key, format!("约束错误: {}", e.message))),
+            }
+        }
+        changes.insert(key.clone(), text);
+    }
+    AppError::check_fields(errors)?;
+    Ok(changes)
+}
+
+async fn detail(scope: Scope) -> AppResult<Value> {
+    let defs = mdm45_config::all().await?;
+    let layers = config_value::layers(scope).await?;
+    let list = config_value::resolve(&keys(&defs), &layers);
+
+    let values: Vec<Value> = defs
+        .iter()
+        .zip(list)
+        .map(|(d, e)| {
+            let mut v = serde_json::to_value(&e).unwrap_or_default();
+            v["name"] = json!(d.config_name);
+            v["config_type"] = json!(d.config_type);
+            v["module"] = json!(d.module);
+            v["category"] = json!(d.category);
+            v
+        })
+        .collect();
+    Ok(json!({
+        "scope": scope,
+        "source": scope.source(),
+        "set": config_value::find_set(scope).await?,
+        "values": values,
+    }))
+}
+
+/// 值集中每个配置项的最终值和来源
+#[get("/config/values/{project}")]
+pub async fn effective(
+    id: Identity,
+    path: web::Path<(i64,)>,
+    query: web::Query<ScopeQuery>,
+) -> AppResult<HttpResponse> {
+    let scope = Scope::new(path.into_inner().0, query.version)?;
+    authorize(id, scope, Op::Query).await?;
+    check_scope(scope).await?;
+
+    Ok(response_ok(detail(scope).await?))
+}
+
+async fn apply(
+    req: &HttpRequest,
+    user: &CurrentUser,
+    scope: Scope,
+    set_id: i64,
+    action: &str,
+    changes: &BTreeMap<String, Option<String>>,
+) -> AppResult<()> {
+    let before = config_value::values(set_id).await?;
+    config_value::save(set_id, changes, &user.username).await?;
+
+    let before: BTreeMap<&String, Option<&String>> = changes
+        .keys()
+        .map(|k| (k, before.get(k).map(|v| &v.value)))
+        .collect();
+    _audit(crate::audit::Entry {
+        username: &user.username,
+        page: AUDIT_PAGE,
+        entity_id: Some(&set_id.to_string()),
+        action,
+        client_ip: client_ip(req).as_deref(),
+        before: Some(json!({ "scope": scope, "values": before })),
+        after: Some(json!({ "scope": scope, "values": changes })),
+    })
+    .await;
+    Ok(())
+}
+
+/// 覆盖或恢复继承的值, 没有值集时创建
+#[post("/config/values/{project}")]
+pub async fn update(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(i64,)>,
+    query: web::Query<ScopeQuery>,
+    params: web::Json<ValuesParams>,
+) -> AppResult<HttpResponse> {
+    let scope = Scope::new(path.into_inner().0, query.version)?;
+    let user = authorize(id, scope, Op::Update).await?;
+    check_scope(scope).await?;
+
+    let name = params.name.as_deref().map(str::trim);
+    if name.map_or(false, |n| n.is_empty() || n.chars().count() > 128) {
+        return Err(AppError::Fields(vec![FieldError::new(
+            "name",
+            "不能为空且不超过 128 个字符",
+        )]));
+    }
+    let defs = mdm45_config::all().await?;
+    let changes = changes(&defs, &params.values)?;
+
+    let set_id = config_value::ensure_set(scope, name, &user.username).await?;
+    apply(&req, &user, scope, set_id, "update", &changes).await?;
+    Ok(response_ok(detail(scope).await?))
+}
+
+/// 复制另一个值集中覆盖的值 (不包括它继承的值)
+#[post("/config/values/{project}/copy")]
+pub async fn copy(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(i64,)>,
+    params: web::Json<CopyParams>,
+) -> AppResult<HttpResponse> {
+    let scope = Scope::new(path.into_inner().0, params.version)?;
+    let from = Scope::new(params.from_project, params.from_version)?;
+    if from == scope {
+        return Err(AppError::Validation("不能复制到同一个值集".to_string()));
+    }
+    let user = authorize(id, scope, Op::Update).await?;
+    authorize_from(&user, from).await?;
+    check_scope(scope).await?;
+
+    let from_set = config_value::find_set(from)
+        .await?
+        .ok_or_else(|| AppError::NotFound("源值集不存在".to_string()))?;
+    let set_id = config_value::ensure_set(scope, None, &user.username).await?;
+
+    let defs = mdm45_config::all().await?;
+    let changes = config_value::copy_changes(
+        &config_value::values(from_set.id).await?,
+        &config_value::values(set_id).await?,
+        &keys(&defs),
+        params.overwrite,
+    );
+    apply(&req, &user, scope, set_id, "copy", &changes).await?;
+
+    let mut result = detail(scope).await?;
+    result["copied"] = json!(changes.len());
+    Ok(response_ok(result))
+}
+
+async fn authorize_from(user: &CurrentUser, from: Scope) -> AppResult<()> {
+    if from.project_id != 0 {
+        require(user, PAGE, Op::Query, Some(from.project_id)).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    use super::changes;
+    use crate::{
+        api::mdm45_config::{test_def, MdmConfig},
+        error::AppError,
+    };
+
+    fn def(key: &str, config_type: &str, constraints: Option<&str>) -> MdmConfig {
+        MdmConfig {
+            constraints: constraints.map(str::to_string),
+            ..test_def(key, config_type)
+        }
+    }
+
+    #[test]
+    fn test_changes() {
+        let defs = vec![
+            def("debug", "bool", None),
+            def("timeout", "int", Some(r#"{"max": 60}"#)),
+        ];
+        let mut values = BTreeMap::new();
+        values.insert("debug".to_string(), json!(true));
+        values.insert("timeout".to_string(), json!(null));
+        let c = changes(&defs, &values).unwrap();
+        assert_eq!(Some(&Some("true".to_string())), c.get("debug"));
+        assert_eq!(Some(&None), c.get("timeout"));
+
+        values.insert("timeout".to_string(), json!(120));
+        values.insert("other".to_string(), json!("x"));
+        match changes(&defs, &values) {
+            Err(AppError::Fields(errors)) => {
+                let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+                assert_eq!(vec!["other", "timeout"], fields);
+            }
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -1,16 +1,28 @@
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use log::info;
-use mysql::execute;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::{
-    mysql::{self, count, sql_page_str},
-    mysql_query, result_err,
+    error::{AppError, AppResult},
+    mysql::{count, execute_args, insert_args, sql_page_str},
+    mysql_query, sql_args,
 };
 
-use super::page_base::{ListData, PageBase, QueryInfo};
+use super::{
+    page_base::{ListData, PageBase, QueryInfo},
+    trash::Trash,
+};
+
+static TRASH: Trash = Trash {
+    table: "tb_version_mdm45",
+    id_column: "id",
+    name_column: "name",
+    refs: &[
+        ("tb_config_value_set", "version_id"),
+        ("tb_config_snapshot", "version_id"),
+    ],
+};
 
 #[derive(sqlx::FromRow, Debug, Serialize, Deserialize, Clone)]
 pub struct Version {
@@ -33,25 +45,45 @@ pub struct Mdm45Page;
 #[async_trait]
 impl PageBase for Mdm45Page {
     #[inline]
-    async fn query(&self, info: &QueryInfo) -> Result<Value, String> {
+    async fn query(&self, info: &QueryInfo) -> AppResult<Value> {
         let limit = info.limit.or(Some(20)).unwrap();
         let page = info.page.or(Some(1)).unwrap();
 
         _query(limit, page).await
     }
 
-    async fn update(&self, user: &str, params: &str) -> Result<(), String> {
-        let v = serde_json::from_str::<Version>(params).map_err(result_err!())?;
-        _update(user, &v).await
+    async fn update(&self, user: &str, params: &str) -> AppResult<String> {
+        let v = serde_json::from_str::<Version>(params)?;
+        Ok(_update(user, &v).await?.to_string())
     }
 
-    async fn delete(&self, user: &str, id: u32) -> Result<(), String> {
+    async fn delete(&self, user: &str, id: u32) -> AppResult<()> {
         _delete(user, id).await
     }
+
+    async fn find(&self, id: &str) -> AppResult<Option<Value>> {
+        let mut data: Vec<Version> = Vec::new();
+        mysql_query!(
+            Version,
+            data,
+            "select id, revision, name, version_prop, create_user, create_time, update_time, update_user, remark
+from tb_version_mdm45 where id = ?",
+            &sql_args![id]
+        )?;
+
+        data.pop()
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(AppError::internal)
+    }
+
+    fn trash(&self) -> Option<&'static Trash> {
+        Some(&TRASH)
+    }
 }
 
 #[inline]
-async fn _query(limit: u32, page: u32) -> Result<Value, String> {
+async fn _query(limit: u32, page: u32) -> AppResult<Value> {
     let sql = sql_page_str(
         r#"
 select id, revision, name, version_prop, create_user, create_time,  update_time, update_user, remark, is_delete
@@ -78,45 +110,50 @@ order by id desc
         total: count,
         page_list: data,
     })
-    .map_err(result_err!())?)
+    .map_err(AppError::internal)?)
 }
 
-pub async fn _update(user: &str, params: &Version) -> Result<(), String> {
-    let remark: String = match params.remark.clone() {
-        Some(x) => format!("'{}'", x),
-        None => "null".to_string(),
+pub async fn _update(user: &str, params: &Version) -> AppResult<i64> {
+    let id = match params.id {
+        Some(id) => {
+            execute_args(
+                r#"UPDATE tb_version_mdm45 
+SET revision = ?, name = ?, version_prop = ?, update_user = ?,  remark = ?, update_time = NOW()
+where id = ? "#,
+                &sql_args![
+                    &params.revision,
+                    &params.name,
+                    params.version_prop,
+                    user,
+                    params.remark.clone(),
+                    id
+                ],
+            )
+            .await?;
+            id
+        }
+        None => insert_args(
+            "insert into tb_version_mdm45 (create_time, revision, name, version_prop, create_user, remark)  
+values (NOW(), ?, ?, ?, ?, ?)",
+            &sql_args![
+                &params.revision,
+                &params.name,
+                params.version_prop,
+                user,
+                params.remark.clone()
+            ],
+        )
+        .await? as i64,
     };
 
-    let mut sql = format!(
-        "insert into tb_version_mdm45 (create_time, revision, name, version_prop, create_user, remark)  
-values (NOW() ,'{}', '{}', {}, '{}', {})",
-        params.revision, params.name, params.version_prop, user, remark
-    );
-
-    if params.id.is_some() {
-        sql = format!(
-            r#"UPDATE tb_version_mdm45 
-SET revision = '{}', name = '{}', version_prop = {}, update_user = '{}',  remark = {}, update_time = NOW()
-where id={} "#,
-            params.revision,
-            params.name,
-            params.version_prop,
-            user,
-            remark,
-            params.id.unwrap()
-        );
-    }
-
-    execute(&sql).await?;
-
-    Ok(())
+    Ok(id)
 }
 
-pub async fn _delete(user: &str, id: u32) -> Result<(), String> {
-    execute(&format!(
-        "UPDATE tb_version_mdm45 SET is_delete = 'Y', update_user = '{}', update_time = NOW()  where id={} ",
-        user, id
-    ))
+pub async fn _delete(user: &str, id: u32) -> AppResult<()> {
+    execute_args(
+        "UPDATE tb_version_mdm45 SET is_delete = 'Y', update_user = ?, update_time = NOW(), delete_user = ?, delete_time = NOW()  where id = ? ",
+        &sql_args![user, user, id],
+    )
     .await?;
 
     Ok(())
@@ -1,16 +1,26 @@
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use log::info;
-use mysql::execute;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::{
-    mysql::{self, count, sql_page_str},
-    mysql_query, result_err,
+    config_type,
+    error::{AppError, AppResult, FieldError},
+    mysql::{count, execute_args, insert_args, json_text, sql_page_str},
+    mysql_query, sql_args,
 };
 
-use super::page_base::{ListData, PageBase, QueryInfo};
+use super::{
+    page_base::{ListData, PageBase, QueryInfo},
+    trash::Trash,
+};
+
+static TRASH: Trash = Trash {
+    table: "tb_version_config_mdm45",
+    id_column: "id",
+    name_column: "config_key",
+    refs: &[],
+};
 
 #[derive(sqlx::FromRow, Debug, Serialize, Deserialize, Clone)]
 pub struct MdmConfig {
@@ -29,6 +39,29 @@ pub struct MdmConfig {
     pub category: String,
     pub module: String,
     pub sort: i64,
+    /// 约束 (json), 见 config_type::Constraints
+    #[serde(default, with = "json_text")]
+    pub constraints: Option<String>,
+}
+
+/// 测试用的配置定义
+#[cfg(test)]
+pub fn test_def(key: &str, config_type: &str) -> MdmConfig {
+    MdmConfig {
+        id: Some(1),
+        config_key: key.to_string(),
+        config_name: None,
+        config_type: config_type.to_string(),
+        remark: None,
+        create_user: "u".to_string(),
+        update_user: None,
+        create_time: Utc::now(),
+        update_time: None,
+        category: "c".to_string(),
+        module: "m".to_string(),
+        sort: 1,
+        constraints: None,
+    }
 }
 
 pub struct Mdm45ConfigPage;
@@ -36,29 +69,49 @@ pub struct Mdm45ConfigPage;
 #[async_trait]
 impl PageBase for Mdm45ConfigPage {
     #[inline]
-    async fn query(&self, info: &QueryInfo) -> Result<Value, String> {
+    async fn query(&self, info: &QueryInfo) -> AppResult<Value> {
         let limit = info.limit.or(Some(2000)).unwrap();
         let page = info.page.or(Some(1)).unwrap();
 
         _query(limit, page).await
     }
 
-    async fn update(&self, user: &str, params: &str) -> Result<(), String> {
-        let v = serde_json::from_str::<MdmConfig>(params).map_err(result_err!())?;
-        _update(user, &v).await
+    async fn update(&self, user: &str, params: &str) -> AppResult<String> {
+        let v = serde_json::from_str::<MdmConfig>(params)?;
+        Ok(_update(user, &v).await?.to_string())
     }
 
-    async fn delete(&self, user: &str, id: u32) -> Result<(), String> {
+    async fn delete(&self, user: &str, id: u32) -> AppResult<()> {
         _delete(user, id).await
     }
+
+    async fn find(&self, id: &str) -> AppResult<Option<Value>> {
+        let mut data: Vec<MdmConfig> = Vec::new();
+        mysql_query!(
+            MdmConfig,
+            data,
+            "select id, config_key, config_name, config_type, category, remark, create_user, create_time, update_user, update_time, module, sort, constraints
+from tb_version_config_mdm45 where id = ?",
+            &sql_args![id]
+        )?;
+
+        data.pop()
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(AppError::internal)
+    }
+
+    fn trash(&self) -> Option<&'static Trash> {
+        Some(&TRASH)
+    }
 }
 
 #[inline]
-async fn _query(limit: u32, page: u32) -> Result<Value, String> {
+async fn _query(limit: u32, page: u32) -> AppResult<Value> {
     let sql = sql_page_str(
         r#"
-select  id, config_key, config_name, config_type, category, remark, create_user, create_time, update_user, update_time, module, sort
-from tb_version_config_mdm45 order by id desc
+select  id, config_key, config_name, config_type, category, remark, create_user, create_time, update_user, update_time, module, sort, constraints
+from tb_version_config_mdm45 where is_delete is null order by id desc
             "#,
         limit,
         page,
@@ -66,7 +119,8 @@ from tb_version_config_mdm45 order by id desc
 
     let mut data: Vec<MdmConfig> = Vec::new();
 
-    let count = count("SELECT COUNT(id) FROM tb_version_config_mdm45").await?;
+    let count =
+        count("SELECT COUNT(id) FROM tb_version_config_mdm45 where is_delete is null").await?;
 
     mysql_query!(MdmConfig, data, &sql)?;
 
@@ -76,53 +130,84 @@ from tb_version_config_mdm45 order by id desc
         total: count,
         page_list: data,
     })
-    .map_err(result_err!())?)
+    .map_err(AppError::internal)?)
 }
 
-pub async fn _update(user: &str, params: &MdmConfig) -> Result<(), String> {
-    let remark: String = match params.remark.clone() {
-        Some(x) => format!("'{}'", x),
-        None => "null".to_string(),
-    };
-
-    let name: String = match params.config_name.clone() {
-        Some(x) => format!("'{}'", x),
-        None => "null".to_string(),
-    };
+/// 所有未删除的配置项, 按 module, category, sort 排序
+pub async fn all() -> AppResult<Vec<MdmConfig>> {
+    let mut data: Vec<MdmConfig> = Vec::new();
+    mysql_query!(
+        MdmConfig,
+        data,
+        r#"select id, config_key, config_name, config_type, category, remark, create_user, create_time, update_user, update_time, module, sort, constraints
+from tb_version_config_mdm45 where is_delete is null order by module, category, sort, id"#
+    )?;
+    Ok(data)
+}
 
-    let mut sql = format!(
-        "insert into tb_version_config_mdm45 (create_time, config_key, config_name, category, create_user, remark, module, sort, config_type)  
-values (NOW() ,'{}', {}, {}, '{}', {}, '{}', {}, '{}')",
-        params.config_key, name, params.category, user, remark, params.module, params.sort, params.config_type
-    );
-
-    if params.id.is_some() {
-        sql = format!(
-            r#"UPDATE tb_version_config_mdm45
-SET config_key = '{}', config_name = {}, category = '{}', update_user = '{}',  remark = {}, module = '{}', sort = {} , config_type ='{}', update_time = NOW()
-where id={} "#,
-            params.config_key,
-            name,
-            params.category,
-            user,
-            remark,
-            params.module,
-            params.sort,
-            params.config_type,
-            params.id.unwrap()
-        );
+/// 检查 key, 类型和约束, 错误按字段返回
+pub fn check(params: &MdmConfig) -> AppResult<()> {
+    let mut errors = Vec::new();
+    if params.config_key.trim().is_empty() {
+        errors.push(FieldError::new("config_key", "不能为空"));
     }
+    errors.extend(config_type::check_definition(
+        &params.config_type,
+        params.constraints.as_deref(),
+    ));
+    AppError::check_fields(errors)
+}
 
-    execute(&sql).await?;
+pub async fn _update(user: &str, params: &MdmConfig) -> AppResult<i64> {
+    check(params)?;
+    let id = match params.id {
+        Some(id) => {
+            execute_args(
+                r#"UPDATE tb_version_config_mdm45
+SET config_key = ?, config_name = ?, category = ?, update_user = ?,  remark = ?, module = ?, sort = ?, config_type = ?, constraints = ?, update_time = NOW()
+where id = ? "#,
+                &sql_args![
+                    &params.config_key,
+                    params.config_name.clone(),
+                    &params.category,
+                    user,
+                    params.remark.clone(),
+                    &params.module,
+                    params.sort,
+                    &params.config_type,
+                    params.constraints.clone(),
+                    id
+                ],
+            )
+            .await?;
+            id
+        }
+        None => insert_args(
+            "insert into tb_version_config_mdm45 (create_time, config_key, config_name, category, create_user, remark, module, sort, config_type, constraints)
+values (NOW(), ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            &sql_args![
+                &params.config_key,
+                params.config_name.clone(),
+                &params.category,
+                user,
+                params.remark.clone(),
+                &params.module,
+                params.sort,
+                &params.config_type,
+                params.constraints.clone()
+            ],
+        )
+        .await? as i64,
+    };
 
-    Ok(())
+    Ok(id)
 }
 
-pub async fn _delete(user: &str, id: u32) -> Result<(), String> {
-    execute(&format!(
-        "UPDATE tb_version_config_mdm45 SET is_delete = 'Y', update_user = '{}', update_time = NOW()  where id={} ",
-        user, id
-    ))
+pub async fn _delete(user: &str, id: u32) -> AppResult<()> {
+    execute_args(
+        "UPDATE tb_version_config_mdm45 SET is_delete = 'Y', update_user = ?, update_time = NOW(), delete_user = ?, delete_time = NOW()  where id = ? ",
+        &sql_args![user, user, id],
+    )
     .await?;
 
     Ok(())
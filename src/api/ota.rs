@@ -0,0 +1,430 @@
+use actix_web::{
+    get,
+    http::{header, StatusCode},
+    web, HttpRequest, HttpResponse,
+};
+use std::collections::{HashMap, HashSet};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    artifact::{self, Artifact},
+    config::Config,
+    error::{AppError, AppResult},
+    http_response::ok_body,
+    mysql::SqlBuilder,
+    mysql_query,
+    rollout::{self, Rollout},
+    sha::sha256_hex,
+    vcs::LogEntry,
+};
+
+use super::{
+    artifact::{is_apk, serve},
+    build_record::{self, BuildRecord, SELECT_BUILD},
+    changelog::{self, Changelog},
+    release::CHANNELS,
+};
+
+/// 客户端的更新检查, 不需要登录
+#[derive(Deserialize, Debug)]
+pub struct CheckQuery {
+    pub project: i64,
+    /// 客户端当前的 versionCode
+    pub version_code: i64,
+    /// 默认 stable
+    pub channel: Option<String>,
+    /// 设备 id, 用于灰度发布, 没有时只能收到全量发布的版本
+    pub device_id: Option<String>,
+}
+
+/// 客户端能收到的渠道, beta 也会收到 stable 的发布
+pub fn channels_of(channel: Option<&str>) -> AppResult<&'static [&'static str]> {
+    match channel.unwrap_or(CHANNELS[0]) {
+        "stable" => Ok(&CHANNELS[..1]),
+        "beta" => Ok(&CHANNELS[..]),
+        c => Err(AppError::Validation(format!(
+            "channel {} 不支持, 可选 {:?}",
+            c, CHANNELS
+        ))),
+    }
+}
+
+/// 发布设置的 min/max 限制了哪些客户端可以收到
+pub fn eligible(record: &BuildRecord, version_code: i64) -> bool {
+    record.version_code > version_code
+        && record
+            .min_version_code
+            .map_or(true, |min| version_code >= min)
+        && record
+            .max_version_code
+            .map_or(true, |max| version_code <= max)
+}
+
+/// 优先返回 apk
+pub fn pick_artifact<'a>(build_uuid: &str, artifacts: &'a [Artifact]) -> Option<&'a Artifact> {
+    let files: Vec<&Artifact> = artifacts
+        .iter()
+        .filter(|a| a.build_uuid == build_uuid)
+        .collect();
+    files
+        .iter()
+        .find(|a| is_apk(&a.file_name))
+        .or_else(|| files.first())
+        .copied()
+}
+
+/// 带上 device_id, 下载时按同样的灰度规则检查
+pub fn download_url(base_url: &str, a: &Artifact, device_id: Option<&str>) -> String {
+    let url = format!(
+        "{}/ota/download/{}/{}",
+        base_url.trim_end_matches('/'),
+        a.build_uuid,
+        a.file_name
+    );
+    match device_id {
+        Some(d) => format!(
+            "{}?device_id={}",
+            url,
+            url::form_urlencoded::byte_serialize(d.as_bytes()).collect::<String>()
+        ),
+        None => url,
+    }
+}
+
+/// 比当前版本新, 灰度规则允许且有安装包的发布中 version_code 最大的一个,
+/// 跳过的版本中有强制更新时也要求强制更新
+pub fn latest(
+    records: &[BuildRecord],
+    artifacts: &[Artifact],
+    rollouts: &HashMap<i64, Rollout>,
+    info: &CheckQuery,
+    base_url: &str,
+) -> Value {
+    let full = Rollout::default();
+    let candidates: Vec<&BuildRecord> = records
+        .iter()
+        .filter(|r| eligible(r, info.version_code))
+        .filter(|r| {
+            r.id.and_then(|id| rollouts.get(&id))
+                .unwrap_or(&full)
+                .allows(&r.build_uuid, info.device_id.as_deref())
+        })
+        .collect();
+
+    let found = candidates
+        .iter()
+        .find_map(|r| pick_artifact(&r.build_uuid, artifacts).map(|a| (r, a)));
+
+    match found {
+        Some((r, a)) => json!({
+            "update": true,
+            "version_code": r.version_code,
+            "version_name": r.version_name,
+            "build_uuid": r.build_uuid,
+            "download_url": download_url(base_url, a, info.device_id.as_deref()),
+            "sha256": a.sha256,
+            "size": a.size,
+            "changelog": r.release_note,
+            "force_update": candidates
+                .iter()
+                .filter(|c| c.version_code <= r.version_code)
+                .any(|c| c.force_update == Some(1)),
+        }),
+        None => json!({ "update": false }),
+    }
+}
+
+/// 从当前版本到 target 之间每次发布的提交记录, 新的在前
+pub fn commits(
+    records: &[BuildRecord],
+    target: i64,
+    changelogs: &HashMap<i64, Changelog>,
+    max: usize,
+) -> Vec<LogEntry> {
+    let mut seen = HashSet::new();
+    records
+        .iter()
+        .filter(|r| r.version_code <= target)
+        .filter_map(|r| r.id.and_then(|id| changelogs.get(&id)))
+        .flat_map(|c| c.entries.iter())
+        .filter(|e| seen.insert(e.revision.clone()))
+        .take(max)
+        .cloned()
+        .collect()
+}
+
+fn not_modified(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |v| v.split(',').any(|t| t.trim() == etag))
+}
+
+#[get("/check")]
+pub async fn check(req: HttpRequest, info: web::Query<CheckQuery>) -> AppResult<HttpResponse> {
+    let channels = channels_of(info.channel.as_deref())?;
+
+    let mut w = SqlBuilder::new("project_id = ? and is_release = 1 and version_code > ?");
+    w.bind(info.project).bind(info.version_code);
+    // 之前发布的构建没有渠道, 视为 stable
+    w.push(&format!(
+        " and coalesce(release_channel, ?) in ({})",
+        vec!["?"; channels.len()].join(", ")
+    ))
+    .bind(CHANNELS[0]);
+    for c in channels {
+        w.bind(*c);
+    }
+
+    let mut records: Vec<BuildRecord> = Vec::new();
+    mysql_query!(
+        BuildRecord,
+        records,
+        &format!(
+            "{} where {} order by version_code desc",
+            SELECT_BUILD,
+            w.sql()
+        ),
+        w.args()
+    )?;
+
+    let uuids: Vec<String> = records.iter().map(|r| r.build_uuid.clone()).collect();
+    let artifacts = artifact::list(&uuids).await?;
+    let ids: Vec<i64> = records.iter().filter_map(|r| r.id).collect();
+    let rollouts = rollout::list(&ids).await?;
+
+    let config = &Config::get().ota;
+    let mut result = latest(&records, &artifacts, &rollouts, &info, &config.base_url);
+    if let Some(target) = result["version_code"].as_i64() {
+        let changelogs = changelog::stored(&ids).await?;
+        let max = Config::get().changelog.max_entries as usize;
+        result["commits"] = serde_json::to_value(commits(&records, target, &changelogs, max))
+            .map_err(AppError::internal)?;
+    }
+    let body = ok_body(result);
+    let etag = format!("\"{}\"", sha256_hex(&body));
+    let cache = format!("public, max-age={}", config.cache_secs);
+
+    if not_modified(&req, &etag) {
+        return Ok(HttpResponse::build(StatusCode::NOT_MODIFIED)
+            .insert_header((header::ETAG, etag))
+            .insert_header((header::CACHE_CONTROL, cache))
+            .finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, etag))
+        .insert_header((header::CACHE_CONTROL, cache))
+        .body(body))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DownloadQuery {
+    pub device_id: Option<String>,
+}
+
+/// 只能下载已发布构建的安装包, 灰度中的构建只有灰度范围内的设备可以下载
+#[get("/download/{build_uuid}/{file_name}")]
+pub async fn download(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    info: web::Query<DownloadQuery>,
+) -> AppResult<HttpResponse> {
+    let (build_uuid, file_name) = path.into_inner();
+    let record = build_record::find_by_uuid(&build_uuid).await?;
+    if record.is_release != Some(1) {
+        return Err(AppError::NotFound(format!("构建 {} 没有发布", build_uuid)));
+    }
+    let rollout = rollout::find(record.id.unwrap_or_default()).await?;
+    if !rollout.allows(&build_uuid, info.device_id.as_deref()) {
+        return Err(AppError::NotFound(format!(
+            "构建 {} 没有对该设备发布",
+            build_uuid
+        )));
+    }
+
+    // 只能下载更新检查会返回的文件, 其他产物 (mapping, 配置文件等) 需要登录后下载
+    let artifacts = artifact::list(&[build_uuid.clone()]).await?;
+    match pick_artifact(&build_uuid, &artifacts) {
+        Some(a) if a.file_name == file_name => serve(&req, a).await,
+        _ => Err(AppError::NotFound(format!("文件 {} 不存在", file_name))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::Utc;
+
+    use super::{channels_of, commits, eligible, latest, pick_artifact, CheckQuery};
+    use crate::{
+        api::{
+            build_record::{test_record, BuildRecord},
+            changelog::Changelog,
+        },
+        artifact::Artifact,
+        rollout::{Rollout, RolloutState},
+        vcs::LogEntry,
+    };
+
+    fn record(version_code: i64) -> BuildRecord {
+        BuildRecord {
+            is_release: Some(1),
+            ..test_record(version_code)
+        }
+    }
+
+    fn artifact(build_uuid: &str, file_name: &str) -> Artifact {
+        Artifact {
+            id: 1,
+            build_id: 1,
+            build_uuid: build_uuid.to_string(),
+            file_name: file_name.to_string(),
+            content_type: None,
+            size: 10,
+            sha256: "00".to_string(),
+            storage: "local".to_string(),
+            create_user: None,
+            create_time: Utc::now(),
+            apk_info: None,
+        }
+    }
+
+    #[test]
+    fn test_channels_of() {
+        assert_eq!(&["stable"], channels_of(None).unwrap());
+        assert_eq!(&["stable", "beta"], channels_of(Some("beta")).unwrap());
+        assert!(channels_of(Some("alpha")).is_err());
+    }
+
+    #[test]
+    fn test_eligible() {
+        let mut r = record(10);
+        assert!(eligible(&r, 9));
+        assert!(!eligible(&r, 10));
+
+        r.min_version_code = Some(5);
+        r.max_version_code = Some(8);
+        assert!(!eligible(&r, 4));
+        assert!(eligible(&r, 5));
+        assert!(eligible(&r, 8));
+        assert!(!eligible(&r, 9));
+    }
+
+    #[test]
+    fn test_pick_artifact() {
+        let files = vec![
+            artifact("b1", "mapping.txt"),
+            artifact("b1", "app.APK"),
+            artifact("b2", "app.apk"),
+        ];
+        assert_eq!("app.APK", pick_artifact("b1", &files).unwrap().file_name);
+        assert!(pick_artifact("b3", &files).is_none());
+
+        let files = vec![artifact("b1", "mapping.txt")];
+        assert_eq!(
+            "mapping.txt",
+            pick_artifact("b1", &files).unwrap().file_name
+        );
+    }
+
+    #[test]
+    fn test_latest() {
+        let mut forced = record(11);
+        forced.force_update = Some(1);
+        // 按 version_code 从大到小
+        let records = vec![record(13), record(12), forced];
+        let files = vec![artifact("b12", "app.apk"), artifact("b11", "app.apk")];
+
+        let query = |version_code: i64, device_id: Option<&str>| CheckQuery {
+            project: 1,
+            version_code,
+            channel: None,
+            device_id: device_id.map(str::to_string),
+        };
+        let mut rollouts = HashMap::new();
+
+        // 13 没有安装包, 跳过
+        let v = latest(
+            &records,
+            &files,
+            &rollouts,
+            &query(10, None),
+            "https://ota.example.com/",
+        );
+        assert_eq!(true, v["update"]);
+        assert_eq!(12, v["version_code"]);
+        assert_eq!(
+            "https://ota.example.com/ota/download/b12/app.apk",
+            v["download_url"]
+        );
+        assert_eq!(true, v["force_update"]);
+
+        let v = latest(&records, &files, &rollouts, &query(11, None), "");
+        assert_eq!(false, v["force_update"]);
+        assert_eq!("/ota/download/b12/app.apk", v["download_url"]);
+
+        assert_eq!(
+            false,
+            latest(&records, &files, &rollouts, &query(12, None), "")["update"]
+        );
+
+        // 12 灰度中, 不在白名单的设备收到 11
+        rollouts.insert(
+            12,
+            Rollout {
+                percentage: 0,
+                allow_devices: vec!["tester".to_string()],
+                ..Rollout::default()
+            },
+        );
+        let v = latest(&records, &files, &rollouts, &query(10, Some("d1")), "");
+        assert_eq!(11, v["version_code"]);
+        let v = latest(&records, &files, &rollouts, &query(10, Some("tester")), "");
+        assert_eq!(12, v["version_code"]);
+        assert_eq!(
+            "/ota/download/b12/app.apk?device_id=tester",
+            v["download_url"]
+        );
+
+        rollouts.get_mut(&12).unwrap().state = RolloutState::Halted;
+        let v = latest(&records, &files, &rollouts, &query(10, Some("tester")), "");
+        assert_eq!(11, v["version_code"]);
+    }
+
+    #[test]
+    fn test_commits() {
+        let entry = |revision: &str| LogEntry {
+            revision: revision.to_string(),
+            author: "a".to_string(),
+            date: None,
+            message: format!("change {}", revision),
+        };
+        let log = |build_id: i64, revisions: &[&str]| Changelog {
+            build_id,
+            from_build_id: None,
+            from_revision: None,
+            to_revision: String::new(),
+            entries: revisions.iter().map(|r| entry(r)).collect(),
+        };
+        let records = vec![record(13), record(12), record(11)];
+        let mut changelogs = HashMap::new();
+        changelogs.insert(13, log(13, &["6"]));
+        changelogs.insert(12, log(12, &["5", "4"]));
+        changelogs.insert(11, log(11, &["4", "3"]));
+
+        let revisions =
+            |v: Vec<LogEntry>| -> Vec<String> { v.into_iter().map(|e| e.revision).collect() };
+        assert_eq!(
+            vec!["5", "4", "3"],
+            revisions(commits(&records, 12, &changelogs, 10))
+        );
+        assert_eq!(
+            vec!["6", "5"],
+            revisions(commits(&records, 13, &changelogs, 2))
+        );
+    }
+}
@@ -1,8 +1,13 @@
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use serde_json::Value;
 
 use serde::{Deserialize, Serialize};
 
+use crate::error::{AppError, AppResult};
+
+use super::trash::Trash;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ListData<T> {
     #[serde(rename = "currPage")]
@@ -15,7 +20,7 @@ pub struct ListData<T> {
     pub page_list: Vec<T>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default)]
 pub struct QueryInfo {
     pub limit: Option<u32>,
     pub page: Option<u32>,
@@ -23,29 +28,90 @@ pub struct QueryInfo {
     pub version: Option<String>,
     #[serde(rename = "s_project")]
     pub project: Option<u32>,
+    /// 关键字, 每个页面搜索的字段不同
     pub query: Option<String>,
+    #[serde(rename = "s_result")]
+    pub build_result: Option<String>,
+    #[serde(rename = "s_user")]
+    pub build_user: Option<String>,
+    #[serde(rename = "s_release")]
+    pub is_release: Option<i64>,
+    #[serde(rename = "s_tag")]
+    pub config_tag: Option<String>,
+    #[serde(rename = "s_revision")]
+    pub revision: Option<String>,
+    /// rfc3339, 如 2021-01-01T00:00:00Z
+    #[serde(rename = "s_start")]
+    pub start: Option<DateTime<Utc>>,
+    #[serde(rename = "s_end")]
+    pub end: Option<DateTime<Utc>>,
+    /// 排序字段, 每个页面有自己的白名单
+    pub sort: Option<String>,
+    /// asc / desc
+    pub order: Option<String>,
 }
 
 #[async_trait]
 pub trait PageBase {
-    async fn query(&self, info: &QueryInfo) -> Result<Value, String>;
-    async fn update(&self, user: &str, params: &str) -> Result<(), String>;
-    async fn delete(&self, user: &str, id: u32) -> Result<(), String>;
+    async fn query(&self, info: &QueryInfo) -> AppResult<Value>;
+    /// 返回记录的 id, 新建时是插入后的 id
+    async fn update(&self, user: &str, params: &str) -> AppResult<String>;
+    async fn delete(&self, user: &str, id: u32) -> AppResult<()>;
+
+    /// 只有管理员可以使用的页面, 查询和修改都需要 admin 权限, 不受 `*` 通配的 query 授权影响
+    fn admin_only(&self) -> bool {
+        false
+    }
+
+    /// 列表是否按 s_project 过滤, 不过滤的页面查询需要全局权限
+    fn filters_by_project(&self) -> bool {
+        false
+    }
+
+    /// update 请求所属的项目, 用于按项目授权
+    fn project_of_params(&self, params: &str) -> Option<i64> {
+        serde_json::from_str::<Value>(params)
+            .ok()
+            .and_then(|v| v["project_id"].as_i64())
+    }
+
+    /// delete 请求所属的项目, 用于按项目授权
+    async fn project_of_id(&self, _id: u32) -> AppResult<Option<i64>> {
+        Ok(None)
+    }
+
+    /// update 请求修改的记录 id, 没有时是新建, 用于审计
+    fn id_of_params(&self, params: &str) -> Option<String> {
+        serde_json::from_str::<Value>(params)
+            .ok()
+            .and_then(|v| v["id"].as_i64())
+            .map(|id| id.to_string())
+    }
+
+    /// 记录当前的内容, 用于审计
+    async fn find(&self, _id: &str) -> AppResult<Option<Value>> {
+        Ok(None)
+    }
+
+    /// 软删除的页面返回回收站的表信息
+    fn trash(&self) -> Option<&'static Trash> {
+        None
+    }
 }
 
 pub struct NotFoundPage;
 
 #[async_trait]
 impl PageBase for NotFoundPage {
-    async fn query(&self, _info: &QueryInfo) -> Result<Value, String> {
-        Err("not found".to_string())
+    async fn query(&self, _info: &QueryInfo) -> AppResult<Value> {
+        Err(AppError::NotFound("not found".to_string()))
     }
 
-    async fn update(&self, _user: &str, _params: &str) -> Result<(), String> {
-        Err("not found".to_string())
+    async fn update(&self, _user: &str, _params: &str) -> AppResult<String> {
+        Err(AppError::NotFound("not found".to_string()))
     }
 
-    async fn delete(&self, _user: &str, _id: u32) -> Result<(), String> {
-        Err("not found".to_string())
+    async fn delete(&self, _user: &str, _id: u32) -> AppResult<()> {
+        Err(AppError::NotFound("not found".to_string()))
     }
 }
@@ -1,16 +1,31 @@
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use log::info;
-use mysql::execute;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::{
-    mysql::{self, count, sql_page_str},
-    mysql_query, result_err,
+    error::{AppError, AppResult},
+    mysql::{count_args, execute_args, insert_args, like_pattern, sql_page_str, SqlBuilder},
+    mysql_query, sql_args,
 };
 
-use super::page_base::{ListData, PageBase, QueryInfo};
+use super::{
+    page_base::{ListData, PageBase, QueryInfo},
+    trash::Trash,
+};
+
+static TRASH: Trash = Trash {
+    table: "tb_project",
+    id_column: "project_id",
+    name_column: "name",
+    refs: &[
+        ("tb_version_build_record", "project_id"),
+        ("sys_user_role", "project_id"),
+        ("tb_config_value_set", "project_id"),
+        ("tb_config_snapshot", "project_id"),
+        ("sys_ci_token", "project_id"),
+    ],
+};
 
 #[derive(sqlx::FromRow, Debug, Serialize, Deserialize, Clone)]
 pub struct Project {
@@ -33,34 +48,61 @@ pub struct ProjectPage;
 #[async_trait]
 impl PageBase for ProjectPage {
     #[inline]
-    async fn query(&self, info: &QueryInfo) -> Result<Value, String> {
+    async fn query(&self, info: &QueryInfo) -> AppResult<Value> {
         _query(info).await
     }
 
-    async fn update(&self, user: &str, params: &str) -> Result<(), String> {
-        let v = serde_json::from_str::<Project>(params).map_err(result_err!())?;
+    async fn update(&self, user: &str, params: &str) -> AppResult<String> {
+        let v = serde_json::from_str::<Project>(params)?;
 
-        _update(user, &v).await
+        Ok(_update(user, &v).await?.to_string())
     }
 
-    async fn delete(&self, user: &str, id: u32) -> Result<(), String> {
+    async fn delete(&self, user: &str, id: u32) -> AppResult<()> {
         _delete(user, id).await
     }
+
+    async fn project_of_id(&self, id: u32) -> AppResult<Option<i64>> {
+        Ok(Some(id.into()))
+    }
+
+    fn id_of_params(&self, params: &str) -> Option<String> {
+        serde_json::from_str::<Value>(params)
+            .ok()
+            .and_then(|v| v["project_id"].as_i64())
+            .map(|id| id.to_string())
+    }
+
+    async fn find(&self, id: &str) -> AppResult<Option<Value>> {
+        let mut data: Vec<Project> = Vec::new();
+        mysql_query!(
+            Project,
+            data,
+            "select project_id, no, name, status, create_time, create_user, update_time, update_user, version_svn_url
+from tb_project where project_id = ?",
+            &sql_args![id]
+        )?;
+
+        data.pop()
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(AppError::internal)
+    }
+
+    fn trash(&self) -> Option<&'static Trash> {
+        Some(&TRASH)
+    }
 }
 
 #[inline]
-async fn _query(info: &QueryInfo) -> Result<Value, String> {
+pub async fn _query(info: &QueryInfo) -> AppResult<Value> {
     let limit = info.limit.or(Some(20)).unwrap();
     let page = info.page.or(Some(1)).unwrap();
 
-    let mut w = "is_delete is null and name is not null".to_string();
+    let mut w = SqlBuilder::new("is_delete is null and name is not null");
 
-    if info.query.is_some() {
-        w = format!(
-            "{} and name like concat('%{}%') ",
-            w,
-            info.query.clone().unwrap()
-        );
+    if let Some(q) = &info.query {
+        w.push(" and name like ?").bind(like_pattern(q));
     }
 
     let sql = sql_page_str(
@@ -70,7 +112,7 @@ async fn _query(info: &QueryInfo) -> Result<Value, String> {
     from tb_project where {}
     order by project_id desc 
             "#,
-            w
+            w.sql()
         ),
         limit,
         page,
@@ -78,14 +120,17 @@ async fn _query(info: &QueryInfo) -> Result<Value, String> {
 
     let mut data: Vec<Project> = Vec::new();
 
-    let count = count(&format!(
-        "SELECT COUNT(project_id) FROM tb_project 
+    let count = count_args(
+        &format!(
+            "SELECT COUNT(project_id) FROM tb_project 
         where {}",
-        w
-    ))
+            w.sql()
+        ),
+        w.args(),
+    )
     .await?;
 
-    mysql_query!(Project, data, &sql)?;
+    mysql_query!(Project, data, &sql, w.args())?;
 
     Ok(serde_json::to_value(ListData::<Project> {
         current_page: page,
@@ -93,45 +138,52 @@ async fn _query(info: &QueryInfo) -> Result<Value, String> {
         total: count,
         page_list: data,
     })
-    .map_err(result_err!())?)
+    .map_err(AppError::internal)?)
 }
 
-pub async fn _update(user: &str, params: &Project) -> Result<(), String> {
-    let version_svn_url: String = match params.version_svn_url.clone() {
-        Some(x) => format!("'{}'", x),
-        None => "null".to_string(),
+pub async fn _update(user: &str, params: &Project) -> AppResult<i64> {
+    let id = match params.project_id {
+        Some(id) => {
+            execute_args(
+                r#"UPDATE tb_project 
+SET no = ?, name = ?, status = ?, update_user = ?,  version_svn_url = ?, update_time = NOW()
+where project_id = ? "#,
+                &sql_args![
+                    &params.no,
+                    &params.name,
+                    params.status,
+                    user,
+                    params.version_svn_url.clone(),
+                    id
+                ],
+            )
+            .await?;
+            id
+        }
+        None => {
+            insert_args(
+                "insert into tb_project (no, name, status, create_user, version_svn_url)  
+values (?, ?, ?, ?, ?)",
+                &sql_args![
+                    &params.no,
+                    &params.name,
+                    params.status,
+                    user,
+                    params.version_svn_url.clone()
+                ],
+            )
+            .await? as i64
+        }
     };
 
-    let mut sql = format!(
-        "insert into tb_project (no, name, status, create_user, version_svn_url)  
-values ('{}', '{}', {}, '{}', {})",
-        params.no, params.name, params.status, user, version_svn_url
-    );
-
-    if params.project_id.is_some() {
-        sql = format!(
-            r#"UPDATE tb_project 
-SET no = '{}', name = '{}', status = {}, update_user = '{}',  version_svn_url = {}, update_time = NOW()
-where project_id={} "#,
-            params.no,
-            params.name,
-            params.status,
-            user,
-            version_svn_url,
-            params.project_id.unwrap()
-        );
-    }
-
-    execute(&sql).await?;
-
-    Ok(())
+    Ok(id)
 }
 
-pub async fn _delete(user: &str, id: u32) -> Result<(), String> {
-    execute(&format!(
-        "UPDATE tb_project SET is_delete = 'Y', update_user = '{}', update_time = NOW()  where project_id={} ",
-        user, id
-    ))
+pub async fn _delete(user: &str, id: u32) -> AppResult<()> {
+    execute_args(
+        "UPDATE tb_project SET is_delete = 'Y', update_user = ?, update_time = NOW(), delete_user = ?, delete_time = NOW()  where project_id = ? ",
+        &sql_args![user, user, id],
+    )
     .await?;
 
     Ok(())
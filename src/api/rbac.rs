@@ -0,0 +1,212 @@
+use actix_identity::Identity;
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    audit::{ACTION_CREATE, ACTION_DELETE},
+    error::{AppError, AppResult},
+    http_response::{response_ok, response_success},
+    mysql::{execute_affected, insert_args, SqlBuilder},
+    mysql_find_one, mysql_query,
+    rbac::{permissions, require, Op},
+    sql_args,
+};
+
+use super::{_audit, check_user, client_ip};
+
+const PAGE: &str = "rbac";
+
+#[derive(sqlx::FromRow, Debug, Serialize, Clone)]
+pub struct RolePermission {
+    pub code: String,
+    pub name: String,
+    pub page: Option<String>,
+    pub op: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize, Clone)]
+pub struct UserRole {
+    pub id: i64,
+    pub username: String,
+    pub role_code: String,
+    pub role_name: String,
+    pub project_id: Option<i64>,
+    pub create_user: Option<String>,
+    pub create_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UserRoleQuery {
+    pub username: Option<String>,
+    #[serde(rename = "s_project")]
+    pub project: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AssignParams {
+    pub username: String,
+    pub role: String,
+    pub project_id: Option<i64>,
+}
+
+/// 当前用户和他的有效权限, 前端据此隐藏按钮
+#[get("/me")]
+pub async fn me(id: Identity) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    let p = permissions(&user).await?;
+
+    Ok(response_ok(json!({
+        "username": user.username,
+        "name": user.name,
+        "permissions": p.to_value(),
+    })))
+}
+
+#[get("/rbac/roles")]
+pub async fn roles(id: Identity) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    require(&user, PAGE, Op::Admin, None).await?;
+
+    let mut data: Vec<RolePermission> = Vec::new();
+    mysql_query!(
+        RolePermission,
+        data,
+        r#"select r.code, r.name, p.page, p.op
+from sys_role r left join sys_role_permission p on p.role_id = r.id
+order by r.id, p.page, p.op"#
+    )?;
+
+    Ok(response_ok(
+        serde_json::to_value(data).map_err(AppError::internal)?,
+    ))
+}
+
+const SELECT_USER_ROLE: &str = r#"select ur.id, ur.username, r.code as role_code, r.name as role_name, ur.project_id, ur.create_user, ur.create_time
+from sys_user_role ur join sys_role r on r.id = ur.role_id"#;
+
+async fn find_user_role(id: i64) -> AppResult<Option<Value>> {
+    let mut data: Vec<UserRole> = Vec::new();
+    mysql_query!(
+        UserRole,
+        data,
+        &format!("{} where ur.id = ?", SELECT_USER_ROLE),
+        &sql_args![id]
+    )?;
+    data.pop()
+        .map(serde_json::to_value)
+        .transpose()
+        .map_err(AppError::internal)
+}
+
+#[get("/rbac/user_roles")]
+pub async fn user_roles(id: Identity, info: web::Query<UserRoleQuery>) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    require(&user, PAGE, Op::Admin, None).await?;
+
+    let mut w = SqlBuilder::new("1 = 1");
+    if let Some(username) = &info.username {
+        w.push(" and ur.username = ?").bind(username);
+    }
+    if let Some(project) = info.project {
+        w.push(" and ur.project_id = ?").bind(project);
+    }
+
+    let sql = format!(
+        "{} where {} order by ur.username, ur.id",
+        SELECT_USER_ROLE,
+        w.sql()
+    );
+
+    let mut data: Vec<UserRole> = Vec::new();
+    mysql_query!(UserRole, data, &sql, w.args())?;
+
+    Ok(response_ok(
+        serde_json::to_value(data).map_err(AppError::internal)?,
+    ))
+}
+
+#[post("/rbac/user_roles")]
+pub async fn assign(
+    id: Identity,
+    req: HttpRequest,
+    params: web::Json<AssignParams>,
+) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    require(&user, PAGE, Op::Admin, None).await?;
+
+    mysql_find_one!(
+        String,
+        "select username from sys_user where username = ?",
+        &sql_args![&params.username]
+    )
+    .map_err(|_| AppError::Validation(format!("用户 {} 不存在", params.username)))?;
+
+    let role_id = mysql_find_one!(
+        i64,
+        "select id from sys_role where code = ?",
+        &sql_args![&params.role]
+    )
+    .map_err(|_| AppError::Validation(format!("角色 {} 不存在", params.role)))?;
+
+    if let Some(project_id) = params.project_id {
+        mysql_find_one!(
+            i64,
+            "select project_id from tb_project where project_id = ? and is_delete is null",
+            &sql_args![project_id]
+        )
+        .map_err(|_| AppError::Validation(format!("项目 {} 不存在", project_id)))?;
+    }
+
+    let user_role_id = insert_args(
+        "insert into sys_user_role (username, role_id, project_id, create_user, create_time)
+values (?, ?, ?, ?, NOW())",
+        &sql_args![&params.username, role_id, params.project_id, &user.name],
+    )
+    .await? as i64;
+
+    _audit(crate::audit::Entry {
+        username: &user.username,
+        page: PAGE,
+        entity_id: Some(&user_role_id.to_string()),
+        action: ACTION_CREATE,
+        client_ip: client_ip(&req).as_deref(),
+        before: None,
+        after: find_user_role(user_role_id).await?,
+    })
+    .await;
+    Ok(response_success("成功"))
+}
+
+#[delete("/rbac/user_roles/{id}")]
+pub async fn unassign(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(u32,)>,
+) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    require(&user, PAGE, Op::Admin, None).await?;
+
+    let user_role_id = i64::from(path.into_inner().0);
+    let before = find_user_role(user_role_id).await?;
+    let n = execute_affected(
+        "DELETE FROM sys_user_role where id = ?",
+        &sql_args![user_role_id],
+    )
+    .await?;
+
+    if n > 0 {
+        _audit(crate::audit::Entry {
+            username: &user.username,
+            page: PAGE,
+            entity_id: Some(&user_role_id.to_string()),
+            action: ACTION_DELETE,
+            client_ip: client_ip(&req).as_deref(),
+            before,
+            after: None,
+        })
+        .await;
+    }
+    Ok(response_success("成功"))
+}
@@ -0,0 +1,392 @@
+use actix_identity::Identity;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use crate::{
+    audit::{ACTION_APPROVE, ACTION_DEMOTE, ACTION_PROMOTE},
+    config::Config,
+    error::{AppError, AppResult},
+    http_response::{response_ok, response_success},
+    mysql::{execute_affected, SqlBuilder},
+    mysql_find_one, mysql_query,
+    rbac::{require, Op},
+    sql_args,
+};
+
+use super::{
+    _audit,
+    build_record::{self, BuildRecord, BuildStatus, SELECT_BUILD},
+    changelog, check_user, client_ip, page_of, CurrentUser,
+};
+
+/// 发布权限, 和构建记录的权限分开授权
+const PAGE: &str = "release";
+/// 审计日志记在构建记录上
+const AUDIT_PAGE: &str = "versionbuildrecord";
+
+pub const STATE_PENDING: &str = "pending";
+pub const STATE_RELEASED: &str = "released";
+
+/// OTA 渠道, beta 的客户端也能收到 stable 的发布
+pub const CHANNELS: [&str; 2] = ["stable", "beta"];
+
+#[derive(Deserialize, Debug, Default)]
+pub struct PromoteParams {
+    pub release_file_arch: Option<String>,
+    /// 默认 stable
+    pub channel: Option<String>,
+    /// 更新说明, OTA 检查时返回给客户端
+    pub release_note: Option<String>,
+    pub force_update: Option<bool>,
+    /// 只推送给 version_code 在这个范围内的客户端
+    pub min_version_code: Option<i64>,
+    pub max_version_code: Option<i64>,
+}
+
+impl PromoteParams {
+    pub fn channel(&self) -> &str {
+        self.channel.as_deref().unwrap_or(CHANNELS[0])
+    }
+
+    pub fn validate(&self) -> AppResult<()> {
+        if !CHANNELS.contains(&self.channel()) {
+            return Err(AppError::Validation(format!(
+                "channel 只能是 {:?}",
+                CHANNELS
+            )));
+        }
+        if let (Some(min), Some(max)) = (self.min_version_code, self.max_version_code) {
+            if min > max {
+                return Err(AppError::Validation(
+                    "min_version_code 不能大于 max_version_code".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CurrentQuery {
+    pub project: Option<i64>,
+}
+
+/// 发布前的检查, previous 为项目中其他已发布构建的最大 version_code
+pub fn check_promote(record: &BuildRecord, previous: Option<i64>) -> AppResult<()> {
+    if record.status() != BuildStatus::Success {
+        return Err(AppError::Conflict(format!(
+            "构建状态为 {}, 只能发布成功的构建",
+            record.status().as_str()
+        )));
+    }
+    if record.release_state.is_some() || record.is_release == Some(1) {
+        return Err(AppError::Conflict(format!(
+            "构建 {} 已发布或正在等待审批",
+            record.build_uuid
+        )));
+    }
+    check_version(record, previous)
+}
+
+/// version_code 必须大于项目之前发布的版本
+pub fn check_version(record: &BuildRecord, previous: Option<i64>) -> AppResult<()> {
+    match previous {
+        Some(prev) if record.version_code <= prev => Err(AppError::Conflict(format!(
+            "version_code {} 必须大于已发布的 {}",
+            record.version_code, prev
+        ))),
+        _ => Ok(()),
+    }
+}
+
+pub fn check_approver(record: &BuildRecord, approver: &str) -> AppResult<()> {
+    if record.release_state.as_deref() != Some(STATE_PENDING) {
+        return Err(AppError::Conflict(format!(
+            "构建 {} 没有待审批的发布",
+            record.build_uuid
+        )));
+    }
+    if record.release_request_user.as_deref() == Some(approver) {
+        return Err(AppError::Forbidden("不能审批自己发起的发布".to_string()));
+    }
+    Ok(())
+}
+
+async fn previous_release(record: &BuildRecord) -> AppResult<Option<i64>> {
+    mysql_find_one!(
+        Option<i64>,
+        "select max(version_code) from tb_version_build_record where project_id = ? and is_release = 1 and id <> ?",
+        &sql_args![record.project_id, record.id]
+    )
+}
+
+/// 并发发布时由这个条件保证 version_code 递增, 子查询包一层是为了 mysql 允许读同一张表
+const NEWER_THAN_RELEASED: &str = r#"version_code > (select v from (
+    select coalesce(max(version_code), 0) as v from tb_version_build_record where project_id = ? and is_release = 1
+) t)"#;
+
+/// 不需要审批时直接发布, 否则进入待审批状态
+pub async fn promote(
+    record: &BuildRecord,
+    user: &CurrentUser,
+    params: &PromoteParams,
+) -> AppResult<&'static str> {
+    params.validate()?;
+    check_promote(record, previous_release(record).await?)?;
+
+    let mut w = SqlBuilder::new(
+        r#"UPDATE tb_version_build_record SET release_request_user = ?, release_request_time = NOW(),
+release_channel = ?, release_note = ?, force_update = ?, min_version_code = ?, max_version_code = ?"#,
+    );
+    w.bind(&user.username)
+        .bind(params.channel())
+        .bind(params.release_note.clone())
+        .bind(params.force_update.unwrap_or(false) as i64)
+        .bind(params.min_version_code)
+        .bind(params.max_version_code);
+    if let Some(arch) = &params.release_file_arch {
+        w.push(", release_file_arch = ?").bind(arch);
+    }
+
+    let state = if Config::get().release.require_approval {
+        w.push(", release_state = ?").bind(STATE_PENDING);
+        STATE_PENDING
+    } else {
+        w.push(", release_state = ?, is_release = 1, release_user = ?, release_time = NOW()")
+            .bind(STATE_RELEASED)
+            .bind(&user.username);
+        STATE_RELEASED
+    };
+    w.push(" where id = ? and release_state is null and ")
+        .bind(record.id)
+        .push(NEWER_THAN_RELEASED)
+        .bind(record.project_id);
+
+    if execute_affected(w.sql(), w.args()).await? == 0 {
+        return Err(AppError::Conflict("发布状态已被修改, 请重试".to_string()));
+    }
+    Ok(state)
+}
+
+pub async fn approve(record: &BuildRecord, user: &CurrentUser) -> AppResult<()> {
+    check_approver(record, &user.username)?;
+    // 审批期间可能发布了更高的版本
+    check_version(record, previous_release(record).await?)?;
+
+    let n = execute_affected(
+        &format!(
+            "UPDATE tb_version_build_record SET release_state = ?, is_release = 1, release_user = ?, release_time = NOW() where id = ? and release_state = ? and {}",
+            NEWER_THAN_RELEASED
+        ),
+        &sql_args![
+            STATE_RELEASED,
+            &user.username,
+            record.id,
+            STATE_PENDING,
+            record.project_id
+        ],
+    )
+    .await?;
+
+    if n == 0 {
+        return Err(AppError::Conflict("发布状态已被修改, 请重试".to_string()));
+    }
+    Ok(())
+}
+
+/// 撤销发布, 也用于取消待审批的发布, 项目的当前版本回到之前发布的构建
+pub async fn demote(record: &BuildRecord) -> AppResult<()> {
+    let n = execute_affected(
+        r#"UPDATE tb_version_build_record SET is_release = 0, release_state = null, release_request_user = null,
+release_request_time = null, release_user = null, release_time = null
+where id = ? and (is_release = 1 or release_state is not null)"#,
+        &sql_args![record.id],
+    )
+    .await?;
+
+    if n == 0 {
+        return Err(AppError::Conflict(format!(
+            "构建 {} 没有发布",
+            record.build_uuid
+        )));
+    }
+    Ok(())
+}
+
+/// 每个项目当前发布的构建, 即已发布中 version_code 最大的
+pub async fn current(project: Option<i64>) -> AppResult<Vec<BuildRecord>> {
+    let mut w = SqlBuilder::new(
+        r#"r.is_release = 1 and r.version_code = (
+    select max(version_code) from tb_version_build_record where project_id = r.project_id and is_release = 1
+)"#,
+    );
+    if let Some(project) = project {
+        w.push(" and r.project_id = ?").bind(project);
+    }
+
+    let mut data: Vec<BuildRecord> = Vec::new();
+    mysql_query!(
+        BuildRecord,
+        data,
+        &format!(
+            "select * from ({}) r where {} order by r.project_id",
+            SELECT_BUILD,
+            w.sql()
+        ),
+        w.args()
+    )?;
+    Ok(data)
+}
+
+async fn audit_change(
+    req: &HttpRequest,
+    user: &CurrentUser,
+    action: &str,
+    id: u32,
+    before: Option<serde_json::Value>,
+) {
+    let entity_id = id.to_string();
+    let after = page_of(AUDIT_PAGE).find(&entity_id).await.unwrap_or(None);
+    _audit(crate::audit::Entry {
+        username: &user.username,
+        page: AUDIT_PAGE,
+        entity_id: Some(&entity_id),
+        action,
+        client_ip: client_ip(req).as_deref(),
+        before,
+        after,
+    })
+    .await;
+}
+
+async fn authorize(id: Identity, record: &BuildRecord) -> AppResult<CurrentUser> {
+    let user = check_user(id).await?;
+    require(&user, PAGE, Op::Update, Some(record.project_id)).await?;
+    Ok(user)
+}
+
+#[post("/release/promote/{id}")]
+pub async fn promote_build(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(u32,)>,
+    params: Option<web::Json<PromoteParams>>,
+) -> AppResult<HttpResponse> {
+    let build_id = path.into_inner().0;
+    let record = build_record::find_by_id(build_id).await?;
+    let user = authorize(id, &record).await?;
+
+    let before = page_of(AUDIT_PAGE).find(&build_id.to_string()).await?;
+    let params = params.map(web::Json::into_inner).unwrap_or_default();
+    let state = promote(&record, &user, &params).await?;
+    if state == STATE_RELEASED {
+        changelog::spawn_generate(record);
+    }
+
+    audit_change(&req, &user, ACTION_PROMOTE, build_id, before).await;
+    Ok(response_ok(serde_json::json!({ "release_state": state })))
+}
+
+#[post("/release/approve/{id}")]
+pub async fn approve_build(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(u32,)>,
+) -> AppResult<HttpResponse> {
+    let build_id = path.into_inner().0;
+    let record = build_record::find_by_id(build_id).await?;
+    let user = authorize(id, &record).await?;
+
+    let before = page_of(AUDIT_PAGE).find(&build_id.to_string()).await?;
+    approve(&record, &user).await?;
+    changelog::spawn_generate(record);
+
+    audit_change(&req, &user, ACTION_APPROVE, build_id, before).await;
+    Ok(response_success("成功"))
+}
+
+#[post("/release/demote/{id}")]
+pub async fn demote_build(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(u32,)>,
+) -> AppResult<HttpResponse> {
+    let build_id = path.into_inner().0;
+    let record = build_record::find_by_id(build_id).await?;
+    let user = authorize(id, &record).await?;
+
+    let before = page_of(AUDIT_PAGE).find(&build_id.to_string()).await?;
+    demote(&record).await?;
+
+    audit_change(&req, &user, ACTION_DEMOTE, build_id, before).await;
+    Ok(response_success("成功"))
+}
+
+/// 每个项目的当前发布, 带构建产物
+#[get("/release/current")]
+pub async fn current_release(
+    id: Identity,
+    info: web::Query<CurrentQuery>,
+) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    require(&user, AUDIT_PAGE, Op::Query, info.project).await?;
+
+    let data = build_record::with_details(current(info.project).await?).await?;
+    Ok(response_ok(
+        serde_json::to_value(data).map_err(AppError::internal)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_approver, check_promote, PromoteParams, STATE_PENDING, STATE_RELEASED};
+    use crate::api::build_record::test_record;
+
+    #[test]
+    fn test_promote_params() {
+        let mut p = PromoteParams::default();
+        assert!(p.validate().is_ok());
+        assert_eq!("stable", p.channel());
+
+        p.channel = Some("alpha".to_string());
+        assert!(p.validate().is_err());
+
+        p.channel = Some("beta".to_string());
+        p.min_version_code = Some(5);
+        p.max_version_code = Some(4);
+        assert!(p.validate().is_err());
+    }
+
+    #[test]
+    fn test_check_promote() {
+        assert!(check_promote(&test_record(10), None).is_ok());
+        assert!(check_promote(&test_record(10), Some(9)).is_ok());
+        assert!(check_promote(&test_record(10), Some(10)).is_err());
+        assert!(check_promote(&test_record(10), Some(11)).is_err());
+
+        let mut r = test_record(10);
+        r.build_status = Some("running".to_string());
+        assert!(check_promote(&r, None).is_err());
+
+        // 旧记录没有状态
+        r.build_status = None;
+        assert!(check_promote(&r, None).is_ok());
+        r.build_result = "failed".to_string();
+        assert!(check_promote(&r, None).is_err());
+        r.build_result = "success".to_string();
+
+        r.release_state = Some(STATE_RELEASED.to_string());
+        assert!(check_promote(&r, None).is_err());
+    }
+
+    #[test]
+    fn test_check_approver() {
+        let mut r = test_record(10);
+        assert!(check_approver(&r, "bob").is_err());
+
+        r.release_state = Some(STATE_PENDING.to_string());
+        r.release_request_user = Some("alice".to_string());
+        assert!(check_approver(&r, "alice").is_err());
+        assert!(check_approver(&r, "bob").is_ok());
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+
+use actix_identity::Identity;
+use actix_web::{http::header, post, web, HttpResponse};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    config_type::{check_value, Constraints},
+    config_value::{self, Scope},
+    error::{AppError, AppResult, FieldError},
+    http_response::response_ok,
+    rbac::{require, Op},
+    render::{self, Format, Item, Kind},
+};
+
+use super::{
+    check_user,
+    mdm45_config::{self, MdmConfig},
+};
+
+const PAGE: &str = "versionconfigmdm45";
+
+#[derive(Deserialize, Debug)]
+pub struct RenderParams {
+    pub project: i64,
+    /// 使用项目中该版本的值集
+    pub version: Option<i64>,
+    /// properties / xml / json / gradle
+    pub format: String,
+    /// config_key -> 值, 覆盖值集中的值
+    #[serde(default)]
+    pub values: BTreeMap<String, Value>,
+}
+
+/// 字符串原样使用, 其他 json 值转成文本, null 视为没有选择
+fn value_text(v: &Value) -> Option<String> {
+    match v {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        v => Some(v.to_string()),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Selection {
+    pub items: Vec<Item>,
+    /// 没有选择值的配置项
+    pub missing: Vec<String>,
+    /// 没有定义的 key
+    pub unknown: Vec<String>,
+    /// 不符合类型或约束的值
+    pub errors: Vec<FieldError>,
+}
+
+pub fn select(defs: &[MdmConfig], values: &BTreeMap<String, Value>) -> Selection {
+    let mut s = Selection::default();
+    for d in defs {
+        let constraints = match Constraints::parse(d.constraints.as_deref()) {
+            Ok(c) => c,
+            Err(e) => {
+                s.errors.push(FieldError::new(
+                    &d.config_key,
+                    format!("约束错误: {}", e.message),
+                ));
+                continue;
+            }
+        };
+        let value = values.get(&d.config_key).and_then(value_text);
+        let checked = match &value {
+            Some(v) => check_value(&d.config_key, &d.config_type, &constraints, v),
+            None if constraints.required => Some(FieldError::new(&d.config_key, "必须选择值")),
+            None => None,
+        };
+        if let Some(e) = checked {
+            s.errors.push(e);
+            continue;
+        }
+        match value {
+            Some(value) => s.items.push(Item {
+                key: d.config_key.clone(),
+                name: d.config_name.clone(),
+                kind: Kind::of(&d.config_type),
+                module: d.module.clone(),
+                category: d.category.clone(),
+                sort: d.sort,
+                value,
+            }),
+            None => s.missing.push(d.config_key.clone()),
+        }
+    }
+    s.unknown = values
+        .keys()
+        .filter(|k| !defs.iter().any(|d| &d.config_key == *k))
+        .cloned()
+        .collect();
+    s
+}
+
+async fn prepare(id: Identity, params: &RenderParams) -> AppResult<(Format, Selection, String)> {
+    let user = check_user(id).await?;
+    require(&user, PAGE, Op::Query, Some(params.project)).await?;
+    let format = Format::parse(&params.format)?;
+
+    let mut values: BTreeMap<String, Value> =
+        config_value::effective_values(Scope::new(params.project, params.version)?)
+            .await?
+            .into_iter()
+            .map(|(k, v)| (k, Value::String(v)))
+            .collect();
+    values.extend(params.values.clone());
+
+    let defs = mdm45_config::all().await?;
+    let selection = select(&defs, &values);
+    AppError::check_fields(selection.errors.clone())?;
+    let content = render::render(format, &selection.items)?;
+    Ok((format, selection, content))
+}
+
+/// 预览生成的配置文件
+#[post("/config/render/preview")]
+pub async fn preview(id: Identity, params: web::Json<RenderParams>) -> AppResult<HttpResponse> {
+    let (format, selection, content) = prepare(id, &params).await?;
+    Ok(response_ok(json!({
+        "format": params.format,
+        "file_name": format.file_name(),
+        "content_type": format.content_type(),
+        "content": content,
+        "missing": selection.missing,
+        "unknown": selection.unknown,
+    })))
+}
+
+#[post("/config/render/download")]
+pub async fn download(id: Identity, params: web::Json<RenderParams>) -> AppResult<HttpResponse> {
+    let (format, _, content) = prepare(id, &params).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, format.content_type()))
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", format.file_name()),
+        ))
+        .body(content))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    use super::select;
+    use crate::{api::mdm45_config::test_def, render::Kind};
+
+    #[test]
+    fn test_select() {
+        let defs = vec![
+            test_def("debug", "bool"),
+            test_def("url", "string"),
+            test_def("timeout", "int"),
+        ];
+        let mut values = BTreeMap::new();
+        values.insert("debug".to_string(), json!(true));
+        values.insert("timeout".to_string(), json!(null));
+        values.insert("url".to_string(), json!("http://a"));
+        values.insert("other".to_string(), json!("x"));
+
+        let s = select(&defs, &values);
+        assert_eq!(2, s.items.len());
+        assert_eq!("true", s.items[0].value);
+        assert_eq!(Kind::Bool, s.items[0].kind);
+        assert_eq!("http://a", s.items[1].value);
+        assert_eq!(vec!["timeout".to_string()], s.missing);
+        assert_eq!(vec!["other".to_string()], s.unknown);
+        assert!(s.errors.is_empty());
+    }
+
+    #[test]
+    fn test_select_errors() {
+        let mut timeout = test_def("timeout", "int");
+        timeout.constraints = Some(r#"{"min": 1, "max": 60}"#.to_string());
+        let mut env = test_def("env", "enum");
+        env.constraints = Some(r#"{"values": ["dev", "prod"], "required": true}"#.to_string());
+        let defs = vec![test_def("debug", "bool"), timeout, env];
+
+        let mut values = BTreeMap::new();
+        values.insert("debug".to_string(), json!("maybe"));
+        values.insert("timeout".to_string(), json!(120));
+
+        let s = select(&defs, &values);
+        assert!(s.items.is_empty());
+        let fields: Vec<&str> = s.errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(vec!["debug", "timeout", "env"], fields);
+    }
+}
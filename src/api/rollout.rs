@@ -0,0 +1,119 @@
+use actix_identity::Identity;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use serde_json::json;
+
+use crate::{
+    error::{AppError, AppResult},
+    http_response::response_ok,
+    rbac::{require, Op},
+    rollout::{
+        self, Rollout, RolloutParams, ACTION_HALT, ACTION_PAUSE, ACTION_RESUME, ACTION_UPDATE,
+    },
+};
+
+use super::{
+    _audit,
+    build_record::{self, BuildRecord},
+    check_user, client_ip, CurrentUser,
+};
+
+/// 和发布使用同一个权限
+const PAGE: &str = "release";
+const AUDIT_PAGE: &str = "rollout";
+
+async fn authorize(id: Identity, record: &BuildRecord, op: Op) -> AppResult<CurrentUser> {
+    let user = check_user(id).await?;
+    require(&user, PAGE, op, Some(record.project_id)).await?;
+    Ok(user)
+}
+
+/// 只有发布或等待审批的构建可以设置灰度
+fn check_released(record: &BuildRecord) -> AppResult<()> {
+    if record.release_state.is_none() && record.is_release != Some(1) {
+        return Err(AppError::Conflict(format!(
+            "构建 {} 没有发布",
+            record.build_uuid
+        )));
+    }
+    Ok(())
+}
+
+async fn change(
+    req: &HttpRequest,
+    user: &CurrentUser,
+    record: &BuildRecord,
+    action: &str,
+    before: &Rollout,
+    after: &Rollout,
+) -> AppResult<HttpResponse> {
+    let build_id = record.id.unwrap_or_default();
+    rollout::save(build_id, after, action, &user.username).await?;
+
+    _audit(crate::audit::Entry {
+        username: &user.username,
+        page: AUDIT_PAGE,
+        entity_id: Some(&build_id.to_string()),
+        action,
+        client_ip: client_ip(req).as_deref(),
+        before: serde_json::to_value(before).ok(),
+        after: serde_json::to_value(after).ok(),
+    })
+    .await;
+    Ok(response_ok(
+        serde_json::to_value(after).map_err(AppError::internal)?,
+    ))
+}
+
+/// 当前规则和修改历史
+#[get("/rollout/detail/{id}")]
+pub async fn detail(id: Identity, path: web::Path<(u32,)>) -> AppResult<HttpResponse> {
+    let record = build_record::find_by_id(path.into_inner().0).await?;
+    authorize(id, &record, Op::Query).await?;
+
+    let build_id = record.id.unwrap_or_default();
+    Ok(response_ok(json!({
+        "rollout": rollout::find(build_id).await?,
+        "history": rollout::history(build_id).await?,
+    })))
+}
+
+#[post("/rollout/update/{id}")]
+pub async fn update(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(u32,)>,
+    params: web::Json<RolloutParams>,
+) -> AppResult<HttpResponse> {
+    let record = build_record::find_by_id(path.into_inner().0).await?;
+    let user = authorize(id, &record, Op::Update).await?;
+    check_released(&record)?;
+
+    let before = rollout::find(record.id.unwrap_or_default()).await?;
+    let after = params.apply(&before)?;
+    change(&req, &user, &record, ACTION_UPDATE, &before, &after).await
+}
+
+/// pause / resume / halt
+#[post("/rollout/{action}/{id}")]
+pub async fn control(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(String, u32)>,
+) -> AppResult<HttpResponse> {
+    let (action, build_id) = path.into_inner();
+    let action = [ACTION_PAUSE, ACTION_RESUME, ACTION_HALT]
+        .iter()
+        .find(|a| **a == action)
+        .ok_or_else(|| AppError::NotFound(format!("not found {}", action)))?;
+
+    let record = build_record::find_by_id(build_id).await?;
+    let user = authorize(id, &record, Op::Update).await?;
+    check_released(&record)?;
+
+    let before = rollout::find(record.id.unwrap_or_default()).await?;
+    let after = Rollout {
+        state: before.state.apply(action)?,
+        ..before.clone()
+    };
+    change(&req, &user, &record, action, &before, &after).await
+}
@@ -0,0 +1,66 @@
+use actix_identity::Identity;
+use actix_web::{delete, get, post, web, HttpResponse};
+use serde::Deserialize;
+
+use crate::{
+    error::{AppError, AppResult},
+    http_response::{response_ok, response_success},
+    rbac::{require, Op},
+    session,
+};
+
+use super::check_user;
+
+#[derive(Deserialize, Debug)]
+pub struct SessionQuery {
+    pub username: Option<String>,
+}
+
+fn check_store() -> AppResult<()> {
+    if session::is_store_enabled() {
+        Ok(())
+    } else {
+        Err(AppError::Validation("未启用服务端会话".to_string()))
+    }
+}
+
+/// 注销自己所有的登录
+#[post("/session/logout_all")]
+pub async fn logout_all(id: Identity) -> AppResult<HttpResponse> {
+    check_store()?;
+    let user = check_user(id).await?;
+    session::revoke_user(&user.username).await?;
+    Ok(response_success("成功"))
+}
+
+#[get("/session/list")]
+pub async fn list(id: Identity, info: web::Query<SessionQuery>) -> AppResult<HttpResponse> {
+    check_store()?;
+    let user = check_user(id).await?;
+    require(&user, "session", Op::Admin, None).await?;
+
+    let data = session::list(info.username.as_deref()).await?;
+    Ok(response_ok(
+        serde_json::to_value(data).map_err(AppError::internal)?,
+    ))
+}
+
+#[delete("/session/revoke/{session_id}")]
+pub async fn revoke(id: Identity, path: web::Path<(String,)>) -> AppResult<HttpResponse> {
+    check_store()?;
+    let user = check_user(id).await?;
+    require(&user, "session", Op::Admin, None).await?;
+
+    session::revoke(&path.into_inner().0).await?;
+    Ok(response_success("成功"))
+}
+
+#[post("/session/revoke_user/{username}")]
+pub async fn revoke_user(id: Identity, path: web::Path<(String,)>) -> AppResult<HttpResponse> {
+    check_store()?;
+    let user = check_user(id).await?;
+    require(&user, "session", Op::Admin, None).await?;
+
+    session::revoke_user(&path.into_inner().0).await?;
+    Ok(response_success("成功"))
+}
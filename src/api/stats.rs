@@ -0,0 +1,342 @@
+use std::{future::Future, time::Duration};
+
+use actix_identity::Identity;
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    cache::TtlCache,
+    config::Config,
+    error::{AppError, AppResult},
+    http_response::response_ok,
+    mysql::SqlBuilder,
+    mysql_query,
+    rbac::{require, Op},
+};
+
+use super::{
+    build_record::{with_details, BuildRecord, SELECT_BUILD},
+    check_user,
+};
+
+const PAGE: &str = "versionbuildrecord";
+/// 缓存的不同查询的个数上限
+const CACHE_ENTRIES: usize = 256;
+const MAX_LIMIT: u32 = 100;
+
+#[derive(Deserialize, Debug)]
+pub struct StatsQuery {
+    pub project: Option<i64>,
+    /// day / week, 默认 day
+    pub period: Option<String>,
+    /// rfc3339, 默认按天统计最近 30 天, 按周统计最近 26 周
+    #[serde(rename = "s_start")]
+    pub start: Option<DateTime<Utc>>,
+    #[serde(rename = "s_end")]
+    pub end: Option<DateTime<Utc>>,
+    pub limit: Option<u32>,
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize, Clone)]
+struct PeriodCount {
+    project_id: i64,
+    project_name: String,
+    period: String,
+    count: i64,
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize, Clone)]
+struct ResultCount {
+    build_result: String,
+    count: i64,
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize, Clone)]
+struct UserCount {
+    build_user: String,
+    count: i64,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+struct ReleaseSpan {
+    project_id: i64,
+    project_name: String,
+    releases: i64,
+    span_secs: Option<i64>,
+}
+
+/// 分组的日期表达式, 按周时为周一的日期
+fn period_expr(period: Option<&str>) -> AppResult<&'static str> {
+    match period.unwrap_or("day") {
+        "day" => Ok("DATE_FORMAT(build_time, '%Y-%m-%d')"),
+        "week" => {
+            Ok("DATE_FORMAT(DATE_SUB(build_time, INTERVAL WEEKDAY(build_time) DAY), '%Y-%m-%d')")
+        }
+        p => Err(AppError::Validation(format!(
+            "period {} 不支持, 可选 day, week",
+            p
+        ))),
+    }
+}
+
+fn default_start(period: Option<&str>, now: DateTime<Utc>) -> DateTime<Utc> {
+    match period {
+        Some("week") => now - chrono::Duration::weeks(26),
+        _ => now - chrono::Duration::days(30),
+    }
+}
+
+/// 项目和时间范围
+fn filter(info: &StatsQuery, start: Option<DateTime<Utc>>) -> SqlBuilder {
+    let mut w = SqlBuilder::new("build_result is not null");
+    if let Some(project) = info.project {
+        w.push(" and project_id = ?").bind(project);
+    }
+    if let Some(start) = info.start.or(start) {
+        w.push(" and build_time >= ?").bind(start);
+    }
+    if let Some(end) = info.end {
+        w.push(" and build_time < ?").bind(end);
+    }
+    w
+}
+
+fn result_rates(rows: &[ResultCount]) -> Value {
+    let total: i64 = rows.iter().map(|r| r.count).sum();
+    let items: Vec<Value> = rows
+        .iter()
+        .map(|r| {
+            json!({
+                "build_result": r.build_result,
+                "count": r.count,
+                "rate": if total > 0 { r.count as f64 / total as f64 } else { 0.0 },
+            })
+        })
+        .collect();
+    json!({ "total": total, "results": items })
+}
+
+/// 第一次到最后一次发布的间隔除以发布次数 - 1
+fn avg_interval_days(releases: i64, span_secs: Option<i64>) -> Option<f64> {
+    match span_secs {
+        Some(span) if releases > 1 => Some(span as f64 / (releases - 1) as f64 / 86400.0),
+        _ => None,
+    }
+}
+
+static CACHE: OnceCell<TtlCache<Value>> = OnceCell::new();
+
+async fn cached<F, Fut>(key: String, f: F) -> AppResult<Value>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = AppResult<Value>>,
+{
+    let secs = Config::get().stats.cache_secs;
+    if secs == 0 {
+        return f().await;
+    }
+
+    let cache = CACHE.get_or_init(|| TtlCache::new(Duration::from_secs(secs), CACHE_ENTRIES));
+    if let Some(v) = cache.get(&key) {
+        return Ok(v);
+    }
+    let v = f().await?;
+    cache.insert(key, v.clone());
+    Ok(v)
+}
+
+async fn authorize(id: Identity, info: &StatsQuery) -> AppResult<()> {
+    let user = check_user(id).await?;
+    require(&user, PAGE, Op::Query, info.project).await
+}
+
+/// 每个项目每天/每周的构建数
+#[get("/stats/builds")]
+pub async fn builds(id: Identity, info: web::Query<StatsQuery>) -> AppResult<HttpResponse> {
+    authorize(id, &info).await?;
+    let expr = period_expr(info.period.as_deref())?;
+
+    let value = cached(format!("builds:{:?}", info), || async {
+        let w = filter(&info, Some(default_start(info.period.as_deref(), Utc::now())));
+        let mut data: Vec<PeriodCount> = Vec::new();
+        mysql_query!(
+            PeriodCount,
+            data,
+            &format!(
+                r#"select project_id, max(project_name) as project_name, {} as period, count(id) as count
+from tb_version_build_record where {} group by project_id, period order by period, project_id"#,
+                expr,
+                w.sql()
+            ),
+            w.args()
+        )?;
+        serde_json::to_value(data).map_err(AppError::internal)
+    })
+    .await?;
+    Ok(response_ok(value))
+}
+
+/// 按 build_result 统计的成功率
+#[get("/stats/results")]
+pub async fn results(id: Identity, info: web::Query<StatsQuery>) -> AppResult<HttpResponse> {
+    authorize(id, &info).await?;
+
+    let value = cached(format!("results:{:?}", info), || async {
+        let w = filter(&info, None);
+        let mut data: Vec<ResultCount> = Vec::new();
+        mysql_query!(
+            ResultCount,
+            data,
+            &format!(
+                "select build_result, count(id) as count from tb_version_build_record where {} group by build_result order by count desc",
+                w.sql()
+            ),
+            w.args()
+        )?;
+        Ok(result_rates(&data))
+    })
+    .await?;
+    Ok(response_ok(value))
+}
+
+/// 构建次数最多的人
+#[get("/stats/builders")]
+pub async fn builders(id: Identity, info: web::Query<StatsQuery>) -> AppResult<HttpResponse> {
+    authorize(id, &info).await?;
+    let limit = info.limit.unwrap_or(10).clamp(1, MAX_LIMIT);
+
+    let value = cached(format!("builders:{:?}", info), || async {
+        let w = filter(&info, None);
+        let mut data: Vec<UserCount> = Vec::new();
+        mysql_query!(
+            UserCount,
+            data,
+            &format!(
+                "select build_user, count(id) as count from tb_version_build_record where {} group by build_user order by count desc, build_user limit {}",
+                w.sql(),
+                limit
+            ),
+            w.args()
+        )?;
+        serde_json::to_value(data).map_err(AppError::internal)
+    })
+    .await?;
+    Ok(response_ok(value))
+}
+
+/// 每个项目两次发布之间的平均天数
+#[get("/stats/release_interval")]
+pub async fn release_interval(
+    id: Identity,
+    info: web::Query<StatsQuery>,
+) -> AppResult<HttpResponse> {
+    authorize(id, &info).await?;
+
+    let value = cached(format!("release_interval:{:?}", info), || async {
+        let mut w = filter(&info, None);
+        w.push(" and is_release = 1");
+        let mut data: Vec<ReleaseSpan> = Vec::new();
+        mysql_query!(
+            ReleaseSpan,
+            data,
+            &format!(
+                r#"select project_id, max(project_name) as project_name, count(id) as releases,
+TIMESTAMPDIFF(SECOND, min(COALESCE(release_time, build_time)), max(COALESCE(release_time, build_time))) as span_secs
+from tb_version_build_record where {} group by project_id order by project_id"#,
+                w.sql()
+            ),
+            w.args()
+        )?;
+
+        Ok(Value::Array(
+            data.iter()
+                .map(|r| {
+                    json!({
+                        "project_id": r.project_id,
+                        "project_name": r.project_name,
+                        "releases": r.releases,
+                        "avg_interval_days": avg_interval_days(r.releases, r.span_secs),
+                    })
+                })
+                .collect(),
+        ))
+    })
+    .await?;
+    Ok(response_ok(value))
+}
+
+/// 每个项目最近的一次构建
+#[get("/stats/latest")]
+pub async fn latest(id: Identity, info: web::Query<StatsQuery>) -> AppResult<HttpResponse> {
+    authorize(id, &info).await?;
+
+    let value = cached(format!("latest:{:?}", info), || async {
+        let w = filter(&info, None);
+        let mut data: Vec<BuildRecord> = Vec::new();
+        mysql_query!(
+            BuildRecord,
+            data,
+            &format!(
+                "{} where id in (select max(id) from tb_version_build_record where {} group by project_id) order by project_id",
+                SELECT_BUILD,
+                w.sql()
+            ),
+            w.args()
+        )?;
+        serde_json::to_value(with_details(data).await?).map_err(AppError::internal)
+    })
+    .await?;
+    Ok(response_ok(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+
+    use super::{avg_interval_days, default_start, period_expr, result_rates, ResultCount};
+
+    #[test]
+    fn test_period() {
+        assert!(period_expr(None).unwrap().contains("build_time"));
+        assert!(period_expr(Some("week")).unwrap().contains("WEEKDAY"));
+        assert!(period_expr(Some("month")).is_err());
+
+        let now = "2021-03-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(
+            "2021-01-30T00:00:00Z".parse::<DateTime<Utc>>().unwrap(),
+            default_start(None, now)
+        );
+        assert_eq!(
+            "2020-08-31T00:00:00Z".parse::<DateTime<Utc>>().unwrap(),
+            default_start(Some("week"), now)
+        );
+    }
+
+    #[test]
+    fn test_result_rates() {
+        let rows = vec![
+            ResultCount {
+                build_result: "success".to_string(),
+                count: 3,
+            },
+            ResultCount {
+                build_result: "failed".to_string(),
+                count: 1,
+            },
+        ];
+        let v = result_rates(&rows);
+        assert_eq!(4, v["total"]);
+        assert_eq!(0.75, v["results"][0]["rate"]);
+        assert_eq!(0.0, result_rates(&[])["total"].as_f64().unwrap());
+    }
+
+    #[test]
+    fn test_avg_interval_days() {
+        assert_eq!(None, avg_interval_days(1, Some(0)));
+        assert_eq!(None, avg_interval_days(2, None));
+        assert_eq!(Some(5.0), avg_interval_days(3, Some(10 * 86400)));
+    }
+}
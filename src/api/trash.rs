@@ -0,0 +1,294 @@
+use actix_identity::Identity;
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    audit::{ACTION_PURGE, ACTION_RESTORE},
+    config::Config,
+    error::{AppError, AppResult},
+    http_response::{response_ok, response_success},
+    mysql::{count_args, execute_affected, like_pattern, sql_page_str, SqlBuilder, Tx},
+    mysql_query,
+    rbac::{require, Op},
+    sql_args,
+};
+
+use super::{
+    _audit, _project_of_id, check_user, client_ip, get_page,
+    page_base::{ListData, QueryInfo},
+    page_of,
+};
+
+/// 使用 is_delete = 'Y' 软删除的表, 表名和列名都是常量, 可以直接拼到 sql 里
+pub struct Trash {
+    pub table: &'static str,
+    pub id_column: &'static str,
+    /// 回收站列表中显示的名称
+    pub name_column: &'static str,
+    /// 引用该表 id 的 (表, 列), 仍被引用时不能彻底删除
+    pub refs: &'static [(&'static str, &'static str)],
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize, Clone)]
+pub struct TrashItem {
+    pub id: i64,
+    pub name: Option<String>,
+    pub delete_user: Option<String>,
+    pub delete_time: Option<DateTime<Utc>>,
+}
+
+impl Trash {
+    /// 之前删除的记录没有 delete_time, 用 update_time 代替
+    const DELETE_TIME: &'static str = "COALESCE(delete_time, update_time)";
+
+    pub async fn list(&self, info: &QueryInfo) -> AppResult<Value> {
+        let limit = info.limit.unwrap_or(20);
+        let page = info.page.unwrap_or(1);
+
+        let mut w = SqlBuilder::new("is_delete = 'Y'");
+        if let Some(q) = &info.query {
+            w.push(&format!(" and {} like ?", self.name_column))
+                .bind(like_pattern(q));
+        }
+
+        let sql = sql_page_str(
+            &format!(
+                "select {} as id, {} as name, delete_user, {} as delete_time from {} where {} order by delete_time desc",
+                self.id_column,
+                self.name_column,
+                Self::DELETE_TIME,
+                self.table,
+                w.sql()
+            ),
+            limit,
+            page,
+        )?;
+
+        let count = count_args(
+            &format!(
+                "SELECT COUNT({}) FROM {} where {}",
+                self.id_column,
+                self.table,
+                w.sql()
+            ),
+            w.args(),
+        )
+        .await?;
+
+        let mut data: Vec<TrashItem> = Vec::new();
+        mysql_query!(TrashItem, data, &sql, w.args())?;
+
+        serde_json::to_value(ListData::<TrashItem> {
+            current_page: page,
+            page_size: limit,
+            total: count,
+            page_list: data,
+        })
+        .map_err(AppError::internal)
+    }
+
+    pub async fn restore(&self, user: &str, id: u32) -> AppResult<()> {
+        let n = execute_affected(
+            &format!(
+                "UPDATE {} SET is_delete = null, delete_user = null, delete_time = null, update_user = ?, update_time = NOW()
+where {} = ? and is_delete = 'Y'",
+                self.table, self.id_column
+            ),
+            &sql_args![user, id],
+        )
+        .await?;
+
+        if n == 0 {
+            return Err(AppError::NotFound(format!("回收站中没有 {}", id)));
+        }
+        Ok(())
+    }
+
+    async fn check_refs(&self, tx: &mut Tx, id: u32) -> AppResult<()> {
+        for (table, column) in self.refs {
+            let n = tx
+                .count(
+                    &format!("SELECT COUNT(*) FROM {} where {} = ?", table, column),
+                    &sql_args![id],
+                )
+                .await?;
+            if n > 0 {
+                return Err(AppError::Conflict(format!(
+                    "仍被 {} 中的 {} 条记录引用, 不能彻底删除",
+                    table, n
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// 彻底删除, 只能删除回收站中的记录.
+    /// 锁住要删除的记录后再检查引用, 检查和删除在同一个事务中
+    pub async fn purge(&self, id: u32) -> AppResult<()> {
+        let mut tx = Tx::begin().await?;
+        let n = tx
+            .count(
+                &format!(
+                    "SELECT COUNT(*) FROM {} where {} = ? and is_delete = 'Y' for update",
+                    self.table, self.id_column
+                ),
+                &sql_args![id],
+            )
+            .await?;
+        if n == 0 {
+            return Err(AppError::NotFound(format!("回收站中没有 {}", id)));
+        }
+        self.check_refs(&mut tx, id).await?;
+
+        tx.execute(
+            &format!(
+                "DELETE FROM {} where {} = ? and is_delete = 'Y'",
+                self.table, self.id_column
+            ),
+            &sql_args![id],
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    fn purge_expired_sql(&self) -> String {
+        let mut sql = format!(
+            "DELETE FROM {} where is_delete = 'Y' and {} < DATE_SUB(NOW(), INTERVAL ? DAY)",
+            self.table,
+            Self::DELETE_TIME
+        );
+        for (table, column) in self.refs {
+            sql.push_str(&format!(
+                " and not exists (select 1 from {} r where r.{} = {}.{})",
+                table, column, self.table, self.id_column
+            ));
+        }
+        sql
+    }
+
+    /// 彻底删除超过保留天数的记录, 跳过仍被引用的
+    pub async fn purge_expired(&self, retention_days: u32) -> AppResult<u64> {
+        execute_affected(&self.purge_expired_sql(), &sql_args![retention_days]).await
+    }
+}
+
+fn trash_of(mode: &str) -> AppResult<&'static Trash> {
+    page_of(mode)
+        .trash()
+        .ok_or_else(|| AppError::NotFound(format!("{} 没有回收站", mode)))
+}
+
+/// 定时清理所有页面中超过保留天数的记录
+pub async fn purge_expired() -> AppResult<()> {
+    let days = Config::get().trash.retention_days;
+    if days == 0 {
+        return Ok(());
+    }
+
+    for (mode, page) in get_page() {
+        if let Some(trash) = page.trash() {
+            let n = trash.purge_expired(days).await?;
+            if n > 0 {
+                info!("purged {} rows from {} trash", n, mode);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[get("/{page}/trash")]
+pub async fn query(
+    id: Identity,
+    page: web::Path<(String,)>,
+    info: web::Query<QueryInfo>,
+) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    let mode = page.into_inner().0;
+    // 回收站不按项目过滤, 需要全局权限
+    require(&user, &mode, Op::Query, None).await?;
+
+    Ok(response_ok(trash_of(&mode)?.list(&info).await?))
+}
+
+#[post("/{page}/restore/{id}")]
+pub async fn restore(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(String, u32)>,
+) -> AppResult<HttpResponse> {
+    let (mode, entity_id) = path.into_inner();
+    let user = check_user(id).await?;
+    require(
+        &user,
+        &mode,
+        Op::Delete,
+        _project_of_id(&mode, entity_id).await?,
+    )
+    .await?;
+
+    trash_of(&mode)?.restore(&user.name, entity_id).await?;
+
+    _audit(crate::audit::Entry {
+        username: &user.username,
+        page: &mode,
+        entity_id: Some(&entity_id.to_string()),
+        action: ACTION_RESTORE,
+        client_ip: client_ip(&req).as_deref(),
+        before: None,
+        after: page_of(&mode).find(&entity_id.to_string()).await?,
+    })
+    .await;
+    Ok(response_success("成功"))
+}
+
+#[delete("/{page}/purge/{id}")]
+pub async fn purge(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(String, u32)>,
+) -> AppResult<HttpResponse> {
+    let (mode, entity_id) = path.into_inner();
+    let user = check_user(id).await?;
+    require(&user, &mode, Op::Admin, None).await?;
+
+    let trash = trash_of(&mode)?;
+    // 删除后就读不到了, 先读出来用于审计
+    let before = page_of(&mode).find(&entity_id.to_string()).await?;
+    trash.purge(entity_id).await?;
+
+    _audit(crate::audit::Entry {
+        username: &user.username,
+        page: &mode,
+        entity_id: Some(&entity_id.to_string()),
+        action: ACTION_PURGE,
+        client_ip: client_ip(&req).as_deref(),
+        before,
+        after: None,
+    })
+    .await;
+    Ok(response_success("成功"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Trash;
+
+    #[test]
+    fn test_purge_expired_sql() {
+        let trash = Trash {
+            table: "tb_project",
+            id_column: "project_id",
+            name_column: "name",
+            refs: &[("tb_version_build_record", "project_id")],
+        };
+
+        assert_eq!(
+            "DELETE FROM tb_project where is_delete = 'Y' and COALESCE(delete_time, update_time) < DATE_SUB(NOW(), INTERVAL ? DAY) \
+and not exists (select 1 from tb_version_build_record r where r.project_id = tb_project.project_id)",
+            trash.purge_expired_sql()
+        );
+    }
+}
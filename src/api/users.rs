@@ -0,0 +1,352 @@
+use actix_identity::Identity;
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    audit::{ACTION_DISABLE, ACTION_ENABLE, ACTION_RESET_PASSWORD, ACTION_UNLOCK},
+    error::{AppError, AppResult},
+    http_response::{response_ok, response_success},
+    login_guard,
+    mysql::{self, count_args, execute_args, like_pattern, sql_page_str, SqlBuilder},
+    mysql_find_one, mysql_query, password,
+    rbac::{require, Op},
+    session, sql_args,
+};
+
+use super::{
+    _audit, check_login, check_user, client_ip,
+    page_base::{ListData, PageBase, QueryInfo},
+    CurrentUser,
+};
+
+const PAGE: &str = "users";
+
+/// 新建用户时 sys_user.salt 的长度, 只有旧格式密码用到
+const SALT_LEN: usize = 20;
+/// 重置后临时密码的长度
+const TEMP_PASSWORD_LEN: usize = 10;
+
+#[derive(sqlx::FromRow, Debug, Serialize, Deserialize, Clone)]
+pub struct SysUser {
+    pub username: String,
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_disabled: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub must_change_password: Option<String>,
+}
+
+/// 带 password 时新建用户, 否则修改已有用户的姓名
+#[derive(Deserialize, Debug)]
+pub struct UserParams {
+    pub username: String,
+    pub name: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UnlockQuery {
+    /// 同时解除该 ip 的锁定
+    pub ip: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChangePasswordParams {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+pub struct UserPage;
+
+#[async_trait]
+impl PageBase for UserPage {
+    fn admin_only(&self) -> bool {
+        true
+    }
+
+    async fn query(&self, info: &QueryInfo) -> AppResult<Value> {
+        _query(info).await
+    }
+
+    async fn update(&self, _user: &str, params: &str) -> AppResult<String> {
+        let v = serde_json::from_str::<UserParams>(params)?;
+        _update(&v).await?;
+        Ok(v.username.trim().to_string())
+    }
+
+    async fn delete(&self, _user: &str, _id: u32) -> AppResult<()> {
+        Err(AppError::Validation("用户不能删除, 请使用禁用".to_string()))
+    }
+
+    fn id_of_params(&self, params: &str) -> Option<String> {
+        serde_json::from_str::<UserParams>(params)
+            .ok()
+            .map(|v| v.username.trim().to_string())
+    }
+
+    async fn find(&self, id: &str) -> AppResult<Option<Value>> {
+        let mut data: Vec<SysUser> = Vec::new();
+        mysql_query!(
+            SysUser,
+            data,
+            "select username, name, is_disabled, must_change_password from sys_user where username = ?",
+            &sql_args![id]
+        )?;
+
+        data.pop()
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(AppError::internal)
+    }
+}
+
+async fn _query(info: &QueryInfo) -> AppResult<Value> {
+    let limit = info.limit.unwrap_or(20);
+    let page = info.page.unwrap_or(1);
+
+    let mut w = SqlBuilder::new("1 = 1");
+    if let Some(q) = &info.query {
+        let q = like_pattern(q);
+        w.push(" and (username like ? or name like ?)")
+            .bind(&q)
+            .bind(&q);
+    }
+
+    let sql = sql_page_str(
+        &format!(
+            "select username, name, is_disabled, must_change_password from sys_user where {} order by username",
+            w.sql()
+        ),
+        limit,
+        page,
+    )?;
+
+    let count = count_args(
+        &format!("SELECT COUNT(username) FROM sys_user where {}", w.sql()),
+        w.args(),
+    )
+    .await?;
+
+    let mut data: Vec<SysUser> = Vec::new();
+    mysql_query!(SysUser, data, &sql, w.args())?;
+
+    serde_json::to_value(ListData::<SysUser> {
+        current_page: page,
+        page_size: limit,
+        total: count,
+        page_list: data,
+    })
+    .map_err(AppError::internal)
+}
+
+async fn _update(params: &UserParams) -> AppResult<()> {
+    if params.username.trim().is_empty() {
+        return Err(AppError::Validation("用户名不能为空".to_string()));
+    }
+
+    match &params.password {
+        Some(pd) => {
+            password::check_strength(pd)?;
+            execute_args(
+                "insert into sys_user (username, name, password, salt) values (?, ?, ?, ?)",
+                &sql_args![
+                    params.username.trim(),
+                    params.name.clone(),
+                    password::hash_blocking(pd).await?,
+                    password::random_string(SALT_LEN)
+                ],
+            )
+            .await
+            .map_err(|err| match err {
+                AppError::Conflict(_) => {
+                    AppError::Conflict(format!("用户 {} 已存在", params.username))
+                }
+                e => e,
+            })
+        }
+        None => {
+            ensure_user(&params.username).await?;
+            execute_args(
+                "UPDATE sys_user SET name = ? where username = ?",
+                &sql_args![params.name.clone(), &params.username],
+            )
+            .await
+        }
+    }
+}
+
+async fn ensure_user(username: &str) -> AppResult<()> {
+    mysql_find_one!(
+        String,
+        "select username from sys_user where username = ?",
+        &sql_args![username]
+    )
+    .map_err(|err| match err {
+        AppError::NotFound(_) => AppError::NotFound(format!("用户 {} 不存在", username)),
+        e => e,
+    })?;
+    Ok(())
+}
+
+async fn audit(
+    req: &HttpRequest,
+    user: &CurrentUser,
+    username: &str,
+    action: &str,
+    before: Option<Value>,
+    after: Option<Value>,
+) {
+    _audit(crate::audit::Entry {
+        username: &user.username,
+        page: PAGE,
+        entity_id: Some(username),
+        action,
+        client_ip: client_ip(req).as_deref(),
+        before,
+        after,
+    })
+    .await;
+}
+
+async fn set_disabled(
+    id: Identity,
+    req: HttpRequest,
+    username: &str,
+    disabled: bool,
+) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    require(&user, PAGE, Op::Admin, None).await?;
+
+    if disabled && user.username == username {
+        return Err(AppError::Validation("不能禁用自己".to_string()));
+    }
+
+    ensure_user(username).await?;
+    let before = UserPage.find(username).await?;
+    execute_args(
+        "UPDATE sys_user SET is_disabled = ? where username = ?",
+        &sql_args![if disabled { Some("Y") } else { None }, username],
+    )
+    .await?;
+
+    if disabled && session::is_store_enabled() {
+        session::revoke_user(username).await?;
+    }
+
+    let action = if disabled {
+        ACTION_DISABLE
+    } else {
+        ACTION_ENABLE
+    };
+    audit(
+        &req,
+        &user,
+        username,
+        action,
+        before,
+        UserPage.find(username).await?,
+    )
+    .await;
+    Ok(response_success("成功"))
+}
+
+#[post("/users/disable/{username}")]
+pub async fn disable(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+) -> AppResult<HttpResponse> {
+    set_disabled(id, req, &path.into_inner().0, true).await
+}
+
+#[post("/users/enable/{username}")]
+pub async fn enable(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+) -> AppResult<HttpResponse> {
+    set_disabled(id, req, &path.into_inner().0, false).await
+}
+
+/// 生成临时密码, 用户下次登录后需要修改. 审计不记录密码
+#[post("/users/reset_password/{username}")]
+pub async fn reset_password(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    require(&user, PAGE, Op::Admin, None).await?;
+
+    let username = path.into_inner().0;
+    ensure_user(&username).await?;
+    let before = UserPage.find(&username).await?;
+
+    let temp = password::random_string(TEMP_PASSWORD_LEN);
+    execute_args(
+        "UPDATE sys_user SET password = ?, must_change_password = 'Y' where username = ?",
+        &sql_args![password::hash_blocking(&temp).await?, &username],
+    )
+    .await?;
+
+    if session::is_store_enabled() {
+        session::revoke_user(&username).await?;
+    }
+
+    let after = UserPage.find(&username).await?;
+    audit(&req, &user, &username, ACTION_RESET_PASSWORD, before, after).await;
+    Ok(response_ok(json!({ "password": temp })))
+}
+
+/// 解除登录失败导致的锁定
+#[post("/users/unlock/{username}")]
+pub async fn unlock(
+    id: Identity,
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    info: web::Query<UnlockQuery>,
+) -> AppResult<HttpResponse> {
+    let user = check_user(id).await?;
+    require(&user, PAGE, Op::Admin, None).await?;
+
+    let username = path.into_inner().0;
+    login_guard::unlock(&username, info.ip.as_deref()).await?;
+
+    let after = json!({ "ip": info.ip });
+    audit(&req, &user, &username, ACTION_UNLOCK, None, Some(after)).await;
+    Ok(response_success("成功"))
+}
+
+/// 修改自己的密码, 旧密码走和登录相同的校验
+#[post("/password")]
+pub async fn change_password(
+    id: Identity,
+    params: web::Json<ChangePasswordParams>,
+) -> AppResult<HttpResponse> {
+    let (user, _) = check_login(id).await?;
+
+    mysql::check_password(&user.username, &params.old_password)
+        .await
+        .map_err(|err| match err {
+            AppError::Auth(_) => AppError::Validation("旧密码错误".to_string()),
+            e => e,
+        })?;
+
+    password::check_strength(&params.new_password)?;
+    if params.new_password == params.old_password {
+        return Err(AppError::Validation("新密码不能和旧密码相同".to_string()));
+    }
+
+    execute_args(
+        "UPDATE sys_user SET password = ?, must_change_password = null where username = ?",
+        &sql_args![
+            password::hash_blocking(&params.new_password).await?,
+            &user.username
+        ],
+    )
+    .await?;
+
+    Ok(response_success("成功"))
+}
@@ -0,0 +1,775 @@
+use std::{
+    collections::BTreeSet,
+    convert::TryFrom,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{AppError, AppResult},
+    sha::sha256_bytes_hex,
+};
+
+/// AndroidManifest.xml 的大小上限, 防止压缩炸弹
+const MAX_MANIFEST_SIZE: u64 = 10 * 1024 * 1024;
+/// 签名证书的大小上限
+const MAX_SIGNATURE_SIZE: u64 = 1024 * 1024;
+
+/// 从 apk 中读出的信息, 证书指纹为 DER 编码证书的 sha256
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ApkInfo {
+    pub package: String,
+    pub version_code: i64,
+    pub version_name: Option<String>,
+    pub min_sdk: Option<i64>,
+    pub target_sdk: Option<i64>,
+    pub permissions: Vec<String>,
+    /// lib/ 下的原生库架构, 如 arm64-v8a
+    pub abis: Vec<String>,
+    /// v1 / v2 / v3
+    pub signature_schemes: Vec<String>,
+    pub cert_sha256: Vec<String>,
+}
+
+fn invalid(msg: impl std::fmt::Display) -> AppError {
+    AppError::Validation(format!("apk 解析失败: {}", msg))
+}
+
+impl ApkInfo {
+    /// 和构建记录中的版本不一致时拒绝
+    pub fn check(&self, version_code: i64, version_name: &str) -> AppResult<()> {
+        let mut errors = Vec::new();
+        if self.version_code != version_code {
+            errors.push(format!(
+                "versionCode {} 与构建记录的 {} 不一致",
+                self.version_code, version_code
+            ));
+        }
+        if self.version_name.as_deref() != Some(version_name) {
+            errors.push(format!(
+                "versionName {} 与构建记录的 {} 不一致",
+                self.version_name.as_deref().unwrap_or("(空)"),
+                version_name
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::Validation(errors.join(", ")))
+        }
+    }
+}
+
+pub fn parse_file(path: &Path) -> AppResult<ApkInfo> {
+    parse(File::open(path).map_err(AppError::internal)?)
+}
+
+pub fn parse<R: Read + Seek>(mut reader: R) -> AppResult<ApkInfo> {
+    let mut info = ApkInfo::default();
+    let (schemes, mut certs) = signing_block_certs(&mut reader)?;
+    reader.seek(SeekFrom::Start(0)).map_err(invalid)?;
+
+    let mut zip = zip::ZipArchive::new(reader).map_err(invalid)?;
+    let names: Vec<String> = zip.file_names().map(str::to_string).collect();
+
+    let manifest = read_entry(&mut zip, "AndroidManifest.xml", MAX_MANIFEST_SIZE)?;
+    parse_manifest(&manifest, &mut info)?;
+
+    let mut abis = BTreeSet::new();
+    let mut v1 = false;
+    for name in &names {
+        let parts: Vec<&str> = name.split('/').collect();
+        if parts.len() == 3 && parts[0] == "lib" && parts[2].ends_with(".so") {
+            abis.insert(parts[1].to_string());
+        }
+
+        let upper = name.to_ascii_uppercase();
+        if upper.starts_with("META-INF/")
+            && (upper.ends_with(".RSA") || upper.ends_with(".DSA") || upper.ends_with(".EC"))
+        {
+            let data = read_entry(&mut zip, name, MAX_SIGNATURE_SIZE)?;
+            certs.extend(pkcs7_certs(&data)?);
+            v1 = true;
+        }
+    }
+
+    info.abis = abis.into_iter().collect();
+    if v1 {
+        info.signature_schemes.push("v1".to_string());
+    }
+    info.signature_schemes.extend(schemes);
+
+    let mut seen = BTreeSet::new();
+    info.cert_sha256 = certs
+        .into_iter()
+        .filter(|c| seen.insert(c.clone()))
+        .collect();
+    Ok(info)
+}
+
+fn read_entry<R: Read + Seek>(
+    zip: &mut zip::ZipArchive<R>,
+    name: &str,
+    max: u64,
+) -> AppResult<Vec<u8>> {
+    let entry = zip
+        .by_name(name)
+        .map_err(|e| invalid(format!("{}: {}", name, e)))?;
+    if entry.size() > max {
+        return Err(invalid(format!("{} 太大", name)));
+    }
+    let mut data = Vec::with_capacity(entry.size() as usize);
+    entry.take(max).read_to_end(&mut data).map_err(invalid)?;
+    Ok(data)
+}
+
+fn u16_at(data: &[u8], pos: usize) -> AppResult<u16> {
+    data.get(pos..pos + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| invalid("数据不完整"))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> AppResult<u32> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid("数据不完整"))
+}
+
+const RES_XML_TYPE: u16 = 0x0003;
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_XML_RESOURCE_MAP_TYPE: u16 = 0x0180;
+const RES_XML_START_ELEMENT_TYPE: u16 = 0x0102;
+
+const TYPE_STRING: u8 = 0x03;
+const TYPE_INT_DEC: u8 = 0x10;
+const TYPE_INT_HEX: u8 = 0x11;
+const NO_ENTRY: u32 = 0xffff_ffff;
+
+/// 属性名被混淆时按资源 id 匹配
+const ATTR_NAME: u32 = 0x0101_0003;
+const ATTR_VERSION_CODE: u32 = 0x0101_021b;
+const ATTR_VERSION_NAME: u32 = 0x0101_021c;
+const ATTR_MIN_SDK: u32 = 0x0101_020c;
+const ATTR_TARGET_SDK: u32 = 0x0101_0270;
+
+fn string_pool(data: &[u8], start: usize) -> AppResult<Vec<String>> {
+    let header_size = u16_at(data, start + 2)? as usize;
+    let count = u32_at(data, start + 8)? as usize;
+    let flags = u32_at(data, start + 16)?;
+    let strings_start = start + u32_at(data, start + 20)? as usize;
+    let utf8 = flags & 0x100 != 0;
+
+    let mut strings = Vec::with_capacity(count.min(65536));
+    for i in 0..count {
+        let mut pos = strings_start + u32_at(data, start + header_size + i * 4)? as usize;
+        let s = if utf8 {
+            // utf16 长度和 utf8 长度, 各 1 或 2 字节
+            let mut len = 0;
+            for _ in 0..2 {
+                let b = *data.get(pos).ok_or_else(|| invalid("字符串越界"))? as usize;
+                len = if b & 0x80 != 0 {
+                    let b2 = *data.get(pos + 1).ok_or_else(|| invalid("字符串越界"))? as usize;
+                    pos += 2;
+                    ((b & 0x7f) << 8) | b2
+                } else {
+                    pos += 1;
+                    b
+                };
+            }
+            let bytes = data
+                .get(pos..pos + len)
+                .ok_or_else(|| invalid("字符串越界"))?;
+            String::from_utf8_lossy(bytes).into_owned()
+        } else {
+            let mut len = u16_at(data, pos)? as usize;
+            pos += 2;
+            if len & 0x8000 != 0 {
+                len = ((len & 0x7fff) << 16) | u16_at(data, pos)? as usize;
+                pos += 2;
+            }
+            let units = (0..len)
+                .map(|i| u16_at(data, pos + i * 2))
+                .collect::<AppResult<Vec<u16>>>()?;
+            String::from_utf16_lossy(&units)
+        };
+        strings.push(s);
+    }
+    Ok(strings)
+}
+
+/// 字符串只保存在字符串池中的下标, 不复制
+struct Attr {
+    name: usize,
+    res_id: Option<u32>,
+    raw: Option<usize>,
+    data_type: u8,
+    data: u32,
+}
+
+fn string_at(strings: &[String], idx: usize) -> &str {
+    strings.get(idx).map_or("", String::as_str)
+}
+
+impl Attr {
+    fn is(&self, strings: &[String], name: &str, res_id: u32) -> bool {
+        self.res_id == Some(res_id) || string_at(strings, self.name) == name
+    }
+
+    fn raw<'a>(&self, strings: &'a [String]) -> Option<&'a str> {
+        self.raw.and_then(|i| strings.get(i)).map(String::as_str)
+    }
+
+    fn as_string(&self, strings: &[String]) -> Option<String> {
+        match self.data_type {
+            TYPE_INT_DEC | TYPE_INT_HEX => Some((self.data as i32).to_string()),
+            _ => self.raw(strings).map(str::to_string),
+        }
+    }
+
+    fn as_int(&self, strings: &[String]) -> Option<i64> {
+        match self.data_type {
+            TYPE_INT_DEC | TYPE_INT_HEX => Some(self.data as i32 as i64),
+            _ => self.raw(strings).and_then(|s| s.trim().parse().ok()),
+        }
+    }
+}
+
+/// 二进制 AndroidManifest.xml, 只读取需要的元素
+fn parse_manifest(data: &[u8], info: &mut ApkInfo) -> AppResult<()> {
+    if u16_at(data, 0)? != RES_XML_TYPE {
+        return Err(invalid("AndroidManifest.xml 不是二进制 xml"));
+    }
+
+    let mut strings: Vec<String> = Vec::new();
+    let mut res_ids: Vec<u32> = Vec::new();
+    let mut permissions = BTreeSet::new();
+    let mut found = false;
+
+    let mut pos = u16_at(data, 2)? as usize;
+    while pos + 8 <= data.len() {
+        let chunk_type = u16_at(data, pos)?;
+        let header_size = u16_at(data, pos + 2)? as usize;
+        let size = u32_at(data, pos + 4)? as usize;
+        if size < 8 || pos + size > data.len() {
+            return Err(invalid("chunk 大小错误"));
+        }
+
+        match chunk_type {
+            RES_STRING_POOL_TYPE => strings = string_pool(data, pos)?,
+            RES_XML_RESOURCE_MAP_TYPE => {
+                res_ids = (pos + header_size..pos + size)
+                    .step_by(4)
+                    .map(|p| u32_at(data, p))
+                    .collect::<AppResult<Vec<u32>>>()?;
+            }
+            RES_XML_START_ELEMENT_TYPE => {
+                let ext = pos + header_size;
+                let name = string_at(&strings, u32_at(data, ext + 4)? as usize);
+                let attr_start = u16_at(data, ext + 8)? as usize;
+                let attr_size = u16_at(data, ext + 10)? as usize;
+                let attr_count = u16_at(data, ext + 12)? as usize;
+                // 属性必须都在这个 chunk 内, 否则伪造的 attr_count 会占用大量内存
+                if attr_size < 20 || ext + attr_start + attr_count * attr_size > pos + size {
+                    return Err(invalid("属性越界"));
+                }
+
+                let mut attrs = Vec::with_capacity(attr_count);
+                for i in 0..attr_count {
+                    let a = ext + attr_start + i * attr_size;
+                    let name_idx = u32_at(data, a + 4)? as usize;
+                    let raw = u32_at(data, a + 8)?;
+                    attrs.push(Attr {
+                        name: name_idx,
+                        res_id: res_ids.get(name_idx).copied(),
+                        raw: if raw == NO_ENTRY {
+                            None
+                        } else {
+                            Some(raw as usize)
+                        },
+                        data_type: *data.get(a + 15).ok_or_else(|| invalid("属性越界"))?,
+                        data: u32_at(data, a + 16)?,
+                    });
+                }
+                let attr =
+                    |name: &str, res_id: u32| attrs.iter().find(|a| a.is(&strings, name, res_id));
+                let as_string = |a: &Attr| a.as_string(&strings);
+                let as_int = |a: &Attr| a.as_int(&strings);
+
+                match name {
+                    "manifest" => {
+                        found = true;
+                        info.package = attrs
+                            .iter()
+                            .find(|a| string_at(&strings, a.name) == "package")
+                            .and_then(as_string)
+                            .unwrap_or_default();
+                        info.version_code = attr("versionCode", ATTR_VERSION_CODE)
+                            .and_then(as_int)
+                            .unwrap_or_default();
+                        info.version_name = attr("versionName", ATTR_VERSION_NAME)
+                            .filter(|a| a.data_type == TYPE_STRING || a.raw.is_some())
+                            .and_then(as_string);
+                    }
+                    "uses-sdk" => {
+                        info.min_sdk = attr("minSdkVersion", ATTR_MIN_SDK).and_then(as_int);
+                        info.target_sdk =
+                            attr("targetSdkVersion", ATTR_TARGET_SDK).and_then(as_int);
+                    }
+                    "uses-permission" | "uses-permission-sdk-23" => {
+                        if let Some(p) = attr("name", ATTR_NAME).and_then(as_string) {
+                            permissions.insert(p);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        pos += size;
+    }
+
+    if !found || info.package.is_empty() {
+        return Err(invalid("AndroidManifest.xml 中没有 package"));
+    }
+    info.permissions = permissions.into_iter().collect();
+    Ok(())
+}
+
+/// DER 的 tag, 内容开始位置和长度
+fn der_header(data: &[u8], pos: usize) -> AppResult<(u8, usize, usize)> {
+    let tag = *data.get(pos).ok_or_else(|| invalid("证书格式错误"))?;
+    let first = *data.get(pos + 1).ok_or_else(|| invalid("证书格式错误"))? as usize;
+    let (len, start) = if first < 0x80 {
+        (first, pos + 2)
+    } else {
+        let n = first & 0x7f;
+        if n == 0 || n > 4 {
+            return Err(invalid("证书格式错误"));
+        }
+        let bytes = data
+            .get(pos + 2..pos + 2 + n)
+            .ok_or_else(|| invalid("证书格式错误"))?;
+        (
+            bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize),
+            pos + 2 + n,
+        )
+    };
+    if start + len > data.len() {
+        return Err(invalid("证书格式错误"));
+    }
+    Ok((tag, start, len))
+}
+
+/// v1 签名的 PKCS#7 SignedData 中的证书
+fn pkcs7_certs(data: &[u8]) -> AppResult<Vec<String>> {
+    // ContentInfo ::= SEQUENCE { contentType OID, content [0] EXPLICIT SignedData }
+    let (_, ci, _) = der_header(data, 0)?;
+    let (_, oid, oid_len) = der_header(data, ci)?;
+    let (_, explicit, _) = der_header(data, oid + oid_len)?;
+    // SignedData ::= SEQUENCE { version, digestAlgorithms, encapContentInfo, certificates [0] IMPLICIT .. }
+    let (_, signed, signed_len) = der_header(data, explicit)?;
+    let end = signed + signed_len;
+
+    let mut pos = signed;
+    for _ in 0..3 {
+        let (_, start, len) = der_header(data, pos)?;
+        pos = start + len;
+    }
+    if pos >= end {
+        return Ok(Vec::new());
+    }
+
+    let (tag, start, len) = der_header(data, pos)?;
+    if tag != 0xa0 {
+        return Ok(Vec::new());
+    }
+    let mut certs = Vec::new();
+    let mut p = start;
+    while p < start + len {
+        let (_, s, l) = der_header(data, p)?;
+        certs.push(sha256_bytes_hex(&data[p..s + l]));
+        p = s + l;
+    }
+    Ok(certs)
+}
+
+const APK_SIG_BLOCK_MAGIC: &[u8] = b"APK Sig Block 42";
+const APK_SIGNATURE_SCHEME_V2_ID: u32 = 0x7109_871a;
+const APK_SIGNATURE_SCHEME_V3_ID: u32 = 0xf053_68c0;
+const EOCD_SIGNATURE: u32 = 0x0605_4b50;
+const EOCD_SIZE: u64 = 22;
+
+/// 长度前缀为 u32 的数据
+fn take_prefixed<'a>(data: &'a [u8], pos: &mut usize) -> AppResult<&'a [u8]> {
+    let len = u32_at(data, *pos)? as usize;
+    let v = data
+        .get(*pos + 4..*pos + 4 + len)
+        .ok_or_else(|| invalid("签名块格式错误"))?;
+    *pos += 4 + len;
+    Ok(v)
+}
+
+/// v2/v3 签名方案: signers -> signer -> signed data -> certificates
+fn scheme_certs(value: &[u8]) -> AppResult<Vec<String>> {
+    let mut certs = Vec::new();
+    let mut pos = 0;
+    let signers = take_prefixed(value, &mut pos)?;
+
+    let mut p = 0;
+    while p < signers.len() {
+        let signer = take_prefixed(signers, &mut p)?;
+        let mut s = 0;
+        let signed_data = take_prefixed(signer, &mut s)?;
+
+        let mut d = 0;
+        take_prefixed(signed_data, &mut d)?;
+        let list = take_prefixed(signed_data, &mut d)?;
+        let mut c = 0;
+        while c < list.len() {
+            certs.push(sha256_bytes_hex(take_prefixed(list, &mut c)?));
+        }
+    }
+    Ok(certs)
+}
+
+/// 中央目录之前的 APK Signing Block, 没有时返回空
+fn signing_block_certs<R: Read + Seek>(reader: &mut R) -> AppResult<(Vec<String>, Vec<String>)> {
+    let file_size = reader.seek(SeekFrom::End(0)).map_err(invalid)?;
+    if file_size < EOCD_SIZE {
+        return Err(invalid("不是 zip 文件"));
+    }
+
+    // EOCD 之后最多有 65535 字节的注释
+    let tail_len = file_size.min(EOCD_SIZE + 65535);
+    let mut tail = vec![0; tail_len as usize];
+    reader
+        .seek(SeekFrom::Start(file_size - tail_len))
+        .map_err(invalid)?;
+    reader.read_exact(&mut tail).map_err(invalid)?;
+
+    let eocd = (0..=tail.len() - EOCD_SIZE as usize)
+        .rev()
+        .find(|&i| u32_at(&tail, i).ok() == Some(EOCD_SIGNATURE))
+        .ok_or_else(|| invalid("不是 zip 文件"))?;
+    let cd_offset = u32_at(&tail, eocd + 16)? as u64;
+    if cd_offset < 32 || cd_offset > file_size {
+        return Ok((Vec::new(), Vec::new()));
+    }
+
+    let mut footer = [0u8; 24];
+    reader
+        .seek(SeekFrom::Start(cd_offset - 24))
+        .map_err(invalid)?;
+    reader.read_exact(&mut footer).map_err(invalid)?;
+    if &footer[8..] != APK_SIG_BLOCK_MAGIC {
+        return Ok((Vec::new(), Vec::new()));
+    }
+
+    let mut size = [0u8; 8];
+    size.copy_from_slice(&footer[..8]);
+    let block_size = u64::from_le_bytes(size);
+    if block_size < 24 || block_size > MAX_SIGNATURE_SIZE * 16 || block_size + 8 > cd_offset {
+        return Err(invalid("签名块大小错误"));
+    }
+
+    // 开头和结尾各有一个 u64 的大小, 中间是 (u64 长度, u32 id, 内容) 的列表
+    let mut block = vec![0; (block_size - 24) as usize];
+    reader
+        .seek(SeekFrom::Start(cd_offset - block_size))
+        .map_err(invalid)?;
+    reader.read_exact(&mut block).map_err(invalid)?;
+
+    let mut schemes = Vec::new();
+    let mut certs = Vec::new();
+    let mut pos = 0;
+    while pos + 12 <= block.len() {
+        let len = u64::from(u32_at(&block, pos)?) | (u64::from(u32_at(&block, pos + 4)?) << 32);
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| (pos + 8).checked_add(len))
+            .filter(|&end| len >= 4 && end <= block.len())
+            .ok_or_else(|| invalid("签名块格式错误"))?;
+        let id = u32_at(&block, pos + 8)?;
+        let value = &block[pos + 12..end];
+
+        let scheme = match id {
+            APK_SIGNATURE_SCHEME_V2_ID => Some("v2"),
+            APK_SIGNATURE_SCHEME_V3_ID => Some("v3"),
+            _ => None,
+        };
+        if let Some(scheme) = scheme {
+            schemes.push(scheme.to_string());
+            certs.extend(scheme_certs(value)?);
+        }
+        pos = end;
+    }
+    Ok((schemes, certs))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::{
+        parse, parse_manifest, pkcs7_certs, signing_block_certs, ApkInfo, TYPE_INT_DEC, TYPE_STRING,
+    };
+    use crate::sha::sha256_bytes_hex;
+
+    fn push_u16(buf: &mut Vec<u8>, v: u16) {
+        buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn push_u32(buf: &mut Vec<u8>, v: u32) {
+        buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    /// 构造二进制 xml, 元素为 (名称, [(属性名, 类型, 值)]), 字符串类型的值是字符串下标
+    fn axml(strings: &[&str], elements: &[(u32, Vec<(u32, u8, u32)>)]) -> Vec<u8> {
+        let mut pool = Vec::new();
+        let mut offsets = Vec::new();
+        for s in strings {
+            offsets.push(pool.len() as u32);
+            let units: Vec<u16> = s.encode_utf16().collect();
+            push_u16(&mut pool, units.len() as u16);
+            for u in units {
+                push_u16(&mut pool, u);
+            }
+            push_u16(&mut pool, 0);
+        }
+        while pool.len() % 4 != 0 {
+            pool.push(0);
+        }
+
+        let mut chunk = Vec::new();
+        push_u16(&mut chunk, 0x0001);
+        push_u16(&mut chunk, 28);
+        push_u32(&mut chunk, (28 + offsets.len() * 4 + pool.len()) as u32);
+        push_u32(&mut chunk, strings.len() as u32);
+        push_u32(&mut chunk, 0);
+        push_u32(&mut chunk, 0);
+        push_u32(&mut chunk, (28 + offsets.len() * 4) as u32);
+        push_u32(&mut chunk, 0);
+        for o in offsets {
+            push_u32(&mut chunk, o);
+        }
+        chunk.extend(pool);
+
+        for (name, attrs) in elements {
+            push_u16(&mut chunk, 0x0102);
+            push_u16(&mut chunk, 16);
+            push_u32(&mut chunk, (16 + 20 + 20 * attrs.len()) as u32);
+            push_u32(&mut chunk, 1);
+            push_u32(&mut chunk, 0xffff_ffff);
+            push_u32(&mut chunk, 0xffff_ffff);
+            push_u32(&mut chunk, *name);
+            push_u16(&mut chunk, 20);
+            push_u16(&mut chunk, 20);
+            push_u16(&mut chunk, attrs.len() as u16);
+            push_u16(&mut chunk, 0);
+            push_u16(&mut chunk, 0);
+            push_u16(&mut chunk, 0);
+            for (attr, data_type, value) in attrs {
+                push_u32(&mut chunk, 0xffff_ffff);
+                push_u32(&mut chunk, *attr);
+                push_u32(
+                    &mut chunk,
+                    if *data_type == TYPE_STRING {
+                        *value
+                    } else {
+                        0xffff_ffff
+                    },
+                );
+                push_u16(&mut chunk, 8);
+                chunk.push(0);
+                chunk.push(*data_type);
+                push_u32(&mut chunk, *value);
+            }
+        }
+
+        let mut xml = Vec::new();
+        push_u16(&mut xml, 0x0003);
+        push_u16(&mut xml, 8);
+        push_u32(&mut xml, (8 + chunk.len()) as u32);
+        xml.extend(chunk);
+        xml
+    }
+
+    fn manifest() -> Vec<u8> {
+        let strings = [
+            "manifest",
+            "package",
+            "versionCode",
+            "versionName",
+            "uses-sdk",
+            "minSdkVersion",
+            "targetSdkVersion",
+            "uses-permission",
+            "name",
+            "com.example.app",
+            "1.0.10",
+            "android.permission.INTERNET",
+            "android.permission.CAMERA",
+        ];
+        axml(
+            &strings,
+            &[
+                (
+                    0,
+                    vec![
+                        (1, TYPE_STRING, 9),
+                        (2, TYPE_INT_DEC, 10),
+                        (3, TYPE_STRING, 10),
+                    ],
+                ),
+                (4, vec![(5, TYPE_INT_DEC, 21), (6, TYPE_INT_DEC, 30)]),
+                (7, vec![(8, TYPE_STRING, 12)]),
+                (7, vec![(8, TYPE_STRING, 11)]),
+            ],
+        )
+    }
+
+    /// 只有一个证书的 SignedData
+    fn pkcs7(cert: &[u8]) -> Vec<u8> {
+        fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+            let mut v = vec![tag, content.len() as u8];
+            v.extend_from_slice(content);
+            v
+        }
+        let signed = [
+            der(0x02, &[1]),
+            der(0x31, &[]),
+            der(0x30, &[]),
+            der(0xa0, cert),
+        ]
+        .concat();
+        let content = [
+            der(0x06, &[0x2a, 0x86, 0x48]),
+            der(0xa0, &der(0x30, &signed)),
+        ]
+        .concat();
+        der(0x30, &content)
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default();
+        let cert = [0x30, 0x03, 0x02, 0x01, 0x05];
+        let files: Vec<(&str, Vec<u8>)> = vec![
+            ("AndroidManifest.xml", manifest()),
+            ("lib/arm64-v8a/libfoo.so", vec![0]),
+            ("lib/armeabi-v7a/libfoo.so", vec![0]),
+            ("lib/arm64-v8a/libbar.so", vec![0]),
+            ("META-INF/CERT.RSA", pkcs7(&cert)),
+        ];
+        for (name, data) in files {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(&data).unwrap();
+        }
+        let data = zip.finish().unwrap().into_inner();
+
+        let info = parse(Cursor::new(data)).unwrap();
+        assert_eq!("com.example.app", info.package);
+        assert_eq!(10, info.version_code);
+        assert_eq!(Some("1.0.10".to_string()), info.version_name);
+        assert_eq!(Some(21), info.min_sdk);
+        assert_eq!(Some(30), info.target_sdk);
+        assert_eq!(
+            vec!["android.permission.CAMERA", "android.permission.INTERNET"],
+            info.permissions
+        );
+        assert_eq!(vec!["arm64-v8a", "armeabi-v7a"], info.abis);
+        assert_eq!(vec!["v1"], info.signature_schemes);
+        assert_eq!(vec![sha256_bytes_hex(&cert)], info.cert_sha256);
+
+        assert!(info.check(10, "1.0.10").is_ok());
+        assert!(info.check(11, "1.0.10").is_err());
+        assert!(info.check(10, "1.0.11").is_err());
+
+        assert!(parse(Cursor::new(b"not a zip".to_vec())).is_err());
+    }
+
+    #[test]
+    fn test_pkcs7_certs() {
+        let cert = [0x30, 0x00];
+        assert_eq!(
+            vec![sha256_bytes_hex(&cert)],
+            pkcs7_certs(&pkcs7(&cert)).unwrap()
+        );
+        assert!(pkcs7_certs(&[0x30, 0x05, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_signing_block() {
+        fn prefixed(data: &[u8]) -> Vec<u8> {
+            let mut v = (data.len() as u32).to_le_bytes().to_vec();
+            v.extend_from_slice(data);
+            v
+        }
+
+        let cert = b"certificate".to_vec();
+        let signed_data = [prefixed(&[]), prefixed(&prefixed(&cert))].concat();
+        let signer = prefixed(&signed_data);
+        let value = prefixed(&prefixed(&signer));
+
+        let (schemes, certs) = signing_block_certs(&mut Cursor::new(signing_block(
+            (value.len() + 4) as u64,
+            &value,
+        )))
+        .unwrap();
+        assert_eq!(vec!["v2"], schemes);
+        assert_eq!(vec![sha256_bytes_hex(&cert)], certs);
+
+        // 长度接近 u64::MAX 时不能溢出
+        let data = signing_block(u64::MAX - 3, &value);
+        assert!(signing_block_certs(&mut Cursor::new(data)).is_err());
+    }
+
+    /// 只有签名块和 EOCD 的文件, 签名块中有一个 v2 签名, len 为写入的长度
+    fn signing_block(len: u64, value: &[u8]) -> Vec<u8> {
+        let mut pair = Vec::new();
+        pair.extend_from_slice(&len.to_le_bytes());
+        pair.extend_from_slice(&0x7109_871au32.to_le_bytes());
+        pair.extend_from_slice(value);
+
+        let block_size = (pair.len() + 24) as u64;
+        let mut data = b"zip entries".to_vec();
+        data.extend_from_slice(&block_size.to_le_bytes());
+        data.extend_from_slice(&pair);
+        data.extend_from_slice(&block_size.to_le_bytes());
+        data.extend_from_slice(b"APK Sig Block 42");
+
+        // 没有中央目录, EOCD 直接指向签名块之后
+        let cd_offset = data.len() as u32;
+        data.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        data.extend_from_slice(&[0; 12]);
+        data.extend_from_slice(&cd_offset.to_le_bytes());
+        data.extend_from_slice(&[0; 2]);
+        data
+    }
+
+    #[test]
+    fn test_manifest_attr_bounds() {
+        let mut data = manifest();
+        assert!(parse_manifest(&data, &mut ApkInfo::default()).is_ok());
+
+        // 第一个元素的属性大小改为 0, 数量改为 65535
+        let start = data
+            .windows(4)
+            .position(|w| w == [0x02, 0x01, 16, 0])
+            .unwrap();
+        let ext = start + 16;
+        data[ext + 10..ext + 12].copy_from_slice(&0u16.to_le_bytes());
+        data[ext + 12..ext + 14].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert!(parse_manifest(&data, &mut ApkInfo::default()).is_err());
+
+        data[ext + 10..ext + 12].copy_from_slice(&20u16.to_le_bytes());
+        assert!(parse_manifest(&data, &mut ApkInfo::default()).is_err());
+    }
+}
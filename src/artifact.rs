@@ -0,0 +1,528 @@
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    pin::Pin,
+};
+
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, Stream, StreamExt};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+use crate::{
+    apk::ApkInfo,
+    config::{ArtifactConfig, Config},
+    error::{AppError, AppResult},
+    mysql::{execute_affected, insert_args, json_text},
+    mysql_query, password, sql_args,
+};
+
+/// 下载时每次读取的大小
+const CHUNK_SIZE: usize = 64 * 1024;
+/// 临时文件名中随机部分的长度
+const TMP_SUFFIX_LEN: usize = 12;
+
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>>>>;
+
+/// 构建产物的存储后端, key 形如 `{build_uuid}/{file_name}`
+#[async_trait]
+pub trait ArtifactStore: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn create(&self, key: &str) -> AppResult<Box<dyn ArtifactWriter>>;
+    /// 从 start 开始读取 len 字节
+    async fn open(&self, key: &str, start: u64, len: u64) -> AppResult<ByteStream>;
+    async fn delete(&self, key: &str) -> AppResult<()>;
+    /// 可以直接读取的本地文件, 用于解析 apk, 远程存储需要先下载到临时文件
+    async fn local_path(&self, key: &str) -> AppResult<PathBuf>;
+}
+
+/// 写入完成前其他请求读不到, 出错时调用 abort 清理
+#[async_trait]
+pub trait ArtifactWriter: Send {
+    async fn write(&mut self, chunk: &[u8]) -> AppResult<()>;
+    async fn finish(self: Box<Self>) -> AppResult<()>;
+    async fn abort(self: Box<Self>);
+}
+
+/// 本地文件系统, 先写到 .part 文件, 完成后改名
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+struct LocalWriter {
+    file: File,
+    tmp: PathBuf,
+    path: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(root: &Path) -> Self {
+        LocalStore {
+            root: root.to_path_buf(),
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+#[async_trait]
+impl ArtifactStore for LocalStore {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    async fn create(&self, key: &str) -> AppResult<Box<dyn ArtifactWriter>> {
+        let path = self.path(key);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await.map_err(AppError::internal)?;
+        }
+
+        // 同一个 key 可能同时上传, 每次写到不同的临时文件
+        let tmp = PathBuf::from(format!(
+            "{}.{}.part",
+            path.display(),
+            password::random_string(TMP_SUFFIX_LEN)
+        ));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp)
+            .await
+            .map_err(AppError::internal)?;
+        Ok(Box::new(LocalWriter { file, tmp, path }))
+    }
+
+    async fn open(&self, key: &str, start: u64, len: u64) -> AppResult<ByteStream> {
+        let mut file = File::open(self.path(key)).await.map_err(|err| {
+            if err.kind() == std::io::ErrorKind::NotFound {
+                AppError::NotFound(format!("文件 {} 不存在", key))
+            } else {
+                AppError::internal(err)
+            }
+        })?;
+        file.seek(SeekFrom::Start(start))
+            .await
+            .map_err(AppError::internal)?;
+
+        let s = stream::unfold((file, len), |(mut file, remaining)| async move {
+            if remaining == 0 {
+                return None;
+            }
+            let mut buf = vec![0u8; CHUNK_SIZE.min(remaining as usize)];
+            match file.read(&mut buf).await {
+                Ok(0) => None,
+                Ok(n) => {
+                    buf.truncate(n);
+                    Some((Ok(Bytes::from(buf)), (file, remaining - n as u64)))
+                }
+                Err(err) => Some((Err(err), (file, 0))),
+            }
+        });
+        Ok(Box::pin(s))
+    }
+
+    async fn delete(&self, key: &str) -> AppResult<()> {
+        match fs::remove_file(self.path(key)).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(AppError::internal(err)),
+        }
+    }
+
+    async fn local_path(&self, key: &str) -> AppResult<PathBuf> {
+        Ok(self.path(key))
+    }
+}
+
+#[async_trait]
+impl ArtifactWriter for LocalWriter {
+    async fn write(&mut self, chunk: &[u8]) -> AppResult<()> {
+        self.file.write_all(chunk).await.map_err(AppError::internal)
+    }
+
+    async fn finish(mut self: Box<Self>) -> AppResult<()> {
+        self.file.flush().await.map_err(AppError::internal)?;
+        self.file.sync_all().await.map_err(AppError::internal)?;
+        fs::rename(&self.tmp, &self.path)
+            .await
+            .map_err(AppError::internal)
+    }
+
+    async fn abort(self: Box<Self>) {
+        drop(self.file);
+        let _ = fs::remove_file(&self.tmp).await;
+    }
+}
+
+static STORE: OnceCell<Box<dyn ArtifactStore>> = OnceCell::new();
+
+fn new_store(config: &ArtifactConfig) -> Box<dyn ArtifactStore> {
+    // 目前只有 local, backend 在 Config::validate 中检查, 增加后端时在这里按 backend 选择
+    Box::new(LocalStore::new(&config.dir))
+}
+
+pub fn store() -> &'static dyn ArtifactStore {
+    STORE
+        .get_or_init(|| new_store(&Config::get().artifact))
+        .as_ref()
+}
+
+/// build_uuid 和文件名都会成为路径的一部分, 只允许安全的字符
+pub fn check_segment(field: &str, s: &str) -> AppResult<()> {
+    let ok = !s.is_empty()
+        && s.len() <= 128
+        && !s.starts_with('.')
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if ok {
+        Ok(())
+    } else {
+        Err(AppError::Validation(format!(
+            "{} 只能包含字母, 数字, '-', '_' 和 '.', 且不能以 '.' 开头",
+            field
+        )))
+    }
+}
+
+pub fn key(build_uuid: &str, file_name: &str) -> String {
+    format!("{}/{}", build_uuid, file_name)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+    Full,
+    /// [start, end] 闭区间
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// 解析 Range 头, 只支持单个区间, 多个区间时返回整个文件
+pub fn parse_range(header: Option<&str>, size: u64) -> ByteRange {
+    let spec = match header.and_then(|h| h.trim().strip_prefix("bytes=")) {
+        Some(s) if !s.contains(',') => s.trim(),
+        _ => return ByteRange::Full,
+    };
+
+    let (start, end) = match spec.find('-') {
+        Some(i) => (&spec[..i], &spec[i + 1..]),
+        None => return ByteRange::Full,
+    };
+
+    let range = match (start.parse::<u64>().ok(), end.parse::<u64>().ok()) {
+        // bytes=-500 最后 500 字节
+        (None, Some(suffix)) if start.is_empty() => {
+            if suffix == 0 || size == 0 {
+                return ByteRange::Unsatisfiable;
+            }
+            (size.saturating_sub(suffix), size - 1)
+        }
+        (Some(s), None) if end.is_empty() => (s, size.saturating_sub(1)),
+        (Some(s), Some(e)) if s <= e => (s, e.min(size.saturating_sub(1))),
+        _ => return ByteRange::Full,
+    };
+
+    if range.0 >= size {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial(range.0, range.1)
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize, Clone)]
+pub struct Artifact {
+    pub id: i64,
+    pub build_id: i64,
+    pub build_uuid: String,
+    pub file_name: String,
+    pub content_type: Option<String>,
+    pub size: i64,
+    pub sha256: String,
+    pub storage: String,
+    pub create_user: Option<String>,
+    pub create_time: DateTime<Utc>,
+    /// apk 解析出的信息 (json)
+    #[serde(serialize_with = "json_text::serialize")]
+    pub apk_info: Option<String>,
+}
+
+impl Artifact {
+    pub fn apk(&self) -> Option<ApkInfo> {
+        self.apk_info
+            .as_deref()
+            .and_then(|s| serde_json::from_str(s).ok())
+    }
+}
+
+const SELECT_ARTIFACT: &str = "select id, build_id, build_uuid, file_name, content_type, size, sha256, storage, create_user, create_time, apk_info from tb_build_artifact";
+
+pub async fn list(build_uuids: &[String]) -> AppResult<Vec<Artifact>> {
+    if build_uuids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let marks = vec!["?"; build_uuids.len()].join(", ");
+    let args: Vec<_> = build_uuids.iter().map(crate::mysql::Arg::from).collect();
+
+    let mut data: Vec<Artifact> = Vec::new();
+    mysql_query!(
+        Artifact,
+        data,
+        &format!(
+            "{} where build_uuid in ({}) and upload_state is null order by id",
+            SELECT_ARTIFACT, marks
+        ),
+        &args
+    )?;
+    Ok(data)
+}
+
+pub async fn find(build_uuid: &str, file_name: &str) -> AppResult<Artifact> {
+    let mut data: Vec<Artifact> = Vec::new();
+    mysql_query!(
+        Artifact,
+        data,
+        &format!(
+            "{} where build_uuid = ? and file_name = ? and upload_state is null",
+            SELECT_ARTIFACT
+        ),
+        &sql_args![build_uuid, file_name]
+    )?;
+    data.pop()
+        .ok_or_else(|| AppError::NotFound(format!("文件 {} 不存在", file_name)))
+}
+
+/// 读取整个文件到内存, 用于配置文件等小文件
+pub async fn read_all(a: &Artifact, max_size: u64) -> AppResult<Vec<u8>> {
+    let size = a.size.max(0) as u64;
+    if size > max_size {
+        return Err(AppError::Validation(format!(
+            "文件 {} 超过 {} 字节",
+            a.file_name, max_size
+        )));
+    }
+
+    let mut body = store()
+        .open(&key(&a.build_uuid, &a.file_name), 0, size)
+        .await?;
+    let mut data = Vec::with_capacity(size as usize);
+    while let Some(chunk) = body.next().await {
+        data.extend_from_slice(&chunk.map_err(AppError::internal)?);
+    }
+    Ok(data)
+}
+
+/// 正在上传的记录超过这个时间视为中断, 可以被重新上传占用
+const STALE_UPLOAD_SECS: i64 = 6 * 3600;
+
+pub struct NewArtifact<'a> {
+    pub build_id: i64,
+    pub build_uuid: &'a str,
+    pub file_name: &'a str,
+    pub create_user: &'a str,
+}
+
+async fn insert_reserved(a: &NewArtifact<'_>) -> AppResult<u64> {
+    insert_args(
+        "insert into tb_build_artifact (build_id, build_uuid, file_name, size, sha256, storage, storage_key, create_user, create_time, upload_state)
+values (?, ?, ?, 0, '', ?, ?, ?, NOW(), 'uploading')",
+        &sql_args![
+            a.build_id,
+            a.build_uuid,
+            a.file_name,
+            store().name(),
+            key(a.build_uuid, a.file_name),
+            a.create_user
+        ],
+    )
+    .await
+}
+
+/// 写入文件前先插入 uploading 状态的记录占用文件名, 同名文件已存在或正在上传时返回 Conflict
+pub async fn reserve(a: &NewArtifact<'_>) -> AppResult<i64> {
+    let mut retried = false;
+    loop {
+        match insert_reserved(a).await {
+            Ok(id) => return Ok(id as i64),
+            Err(AppError::Conflict(_)) if !retried => {
+                // 清理中断的上传后重试一次
+                retried = true;
+                let n = execute_affected(
+                    "DELETE FROM tb_build_artifact where build_uuid = ? and file_name = ? and upload_state = 'uploading' and create_time < NOW() - INTERVAL ? SECOND",
+                    &sql_args![a.build_uuid, a.file_name, STALE_UPLOAD_SECS],
+                )
+                .await?;
+                if n == 0 {
+                    break;
+                }
+            }
+            Err(AppError::Conflict(_)) => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(AppError::Conflict(format!(
+        "文件 {} 已存在或正在上传",
+        a.file_name
+    )))
+}
+
+pub struct StoredFile<'a> {
+    pub content_type: Option<String>,
+    pub size: u64,
+    pub sha256: &'a str,
+    pub apk_info: Option<&'a ApkInfo>,
+}
+
+/// 文件写完后更新占用的记录, 记录已不是 uploading 时返回 Conflict
+pub async fn complete(id: i64, f: &StoredFile<'_>) -> AppResult<()> {
+    let n = execute_affected(
+        "UPDATE tb_build_artifact SET content_type = ?, size = ?, sha256 = ?, apk_info = ?, create_time = NOW(), upload_state = NULL where id = ? and upload_state = 'uploading'",
+        &sql_args![
+            f.content_type.clone(),
+            f.size,
+            f.sha256,
+            f.apk_info
+                .map(|info| serde_json::to_string(info).map_err(AppError::internal))
+                .transpose()?,
+            id
+        ],
+    )
+    .await?;
+    if n == 0 {
+        return Err(AppError::Conflict("上传已被取消, 请重试".to_string()));
+    }
+    Ok(())
+}
+
+/// 上传失败时删除占用的记录, 返回是否删除; 没有删除说明记录已不属于这次上传
+pub async fn release(id: i64) -> AppResult<bool> {
+    let n = execute_affected(
+        "DELETE FROM tb_build_artifact where id = ? and upload_state = 'uploading'",
+        &sql_args![id],
+    )
+    .await?;
+    Ok(n > 0)
+}
+
+pub async fn remove(build_uuid: &str, file_name: &str) -> AppResult<()> {
+    let n = execute_affected(
+        "DELETE FROM tb_build_artifact where build_uuid = ? and file_name = ? and upload_state is null",
+        &sql_args![build_uuid, file_name],
+    )
+    .await?;
+    if n == 0 {
+        return Err(AppError::NotFound(format!("文件 {} 不存在", file_name)));
+    }
+    store().delete(&key(build_uuid, file_name)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+
+    use super::{check_segment, parse_range, ArtifactStore, ByteRange, LocalStore};
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(ByteRange::Full, parse_range(None, 100));
+        assert_eq!(
+            ByteRange::Partial(0, 9),
+            parse_range(Some("bytes=0-9"), 100)
+        );
+        assert_eq!(
+            ByteRange::Partial(90, 99),
+            parse_range(Some("bytes=90-200"), 100)
+        );
+        assert_eq!(
+            ByteRange::Partial(50, 99),
+            parse_range(Some("bytes=50-"), 100)
+        );
+        assert_eq!(
+            ByteRange::Partial(80, 99),
+            parse_range(Some("bytes=-20"), 100)
+        );
+        assert_eq!(
+            ByteRange::Partial(0, 99),
+            parse_range(Some("bytes=-200"), 100)
+        );
+        assert_eq!(
+            ByteRange::Unsatisfiable,
+            parse_range(Some("bytes=100-"), 100)
+        );
+        assert_eq!(ByteRange::Unsatisfiable, parse_range(Some("bytes=-0"), 100));
+        assert_eq!(ByteRange::Full, parse_range(Some("bytes=5-1"), 100));
+        assert_eq!(ByteRange::Full, parse_range(Some("bytes=0-1,5-6"), 100));
+        assert_eq!(ByteRange::Full, parse_range(Some("items=0-1"), 100));
+    }
+
+    #[test]
+    fn test_check_segment() {
+        assert!(check_segment("f", "app-release_1.0.apk").is_ok());
+        assert!(check_segment("f", "").is_err());
+        assert!(check_segment("f", "..").is_err());
+        assert!(check_segment("f", ".hidden").is_err());
+        assert!(check_segment("f", "a/b").is_err());
+        assert!(check_segment("f", "a\\b").is_err());
+        assert!(check_segment("f", "中文.apk").is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_local_store() {
+        let root = std::env::temp_dir().join(format!("artifacts-{}", std::process::id()));
+        let store = LocalStore::new(&root);
+
+        let mut w = store.create("u1/a.txt").await.unwrap();
+        w.write(b"hello ").await.unwrap();
+        w.write(b"world").await.unwrap();
+        // 完成前读不到
+        assert!(store.open("u1/a.txt", 0, 11).await.is_err());
+        w.finish().await.unwrap();
+
+        let read = |start, len| {
+            let store = &store;
+            async move {
+                let mut s = store.open("u1/a.txt", start, len).await.unwrap();
+                let mut out = Vec::new();
+                while let Some(chunk) = s.next().await {
+                    out.extend_from_slice(&chunk.unwrap());
+                }
+                out
+            }
+        };
+        assert_eq!(b"hello world".to_vec(), read(0, 11).await);
+        assert_eq!(b"world".to_vec(), read(6, 5).await);
+
+        // 同一个 key 同时写入互不影响
+        let mut a = store.create("u1/b.txt").await.unwrap();
+        let mut b = store.create("u1/b.txt").await.unwrap();
+        a.write(b"a").await.unwrap();
+        b.write(b"b").await.unwrap();
+        b.abort().await;
+        a.finish().await.unwrap();
+        assert_eq!(b"a".to_vec(), std::fs::read(root.join("u1/b.txt")).unwrap());
+        let parts = std::fs::read_dir(root.join("u1"))
+            .unwrap()
+            .filter(|e| {
+                e.as_ref()
+                    .unwrap()
+                    .path()
+                    .to_string_lossy()
+                    .ends_with(".part")
+            })
+            .count();
+        assert_eq!(0, parts);
+
+        store.delete("u1/a.txt").await.unwrap();
+        store.delete("u1/a.txt").await.unwrap();
+        assert!(store.open("u1/a.txt", 0, 1).await.is_err());
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,245 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    api::page_base::ListData,
+    error::{AppError, AppResult},
+    mysql::{count_args, execute_args, sql_page_str, SqlBuilder},
+    mysql_query, sql_args,
+};
+
+pub const ACTION_CREATE: &str = "create";
+pub const ACTION_UPDATE: &str = "update";
+pub const ACTION_DELETE: &str = "delete";
+/// 从回收站恢复
+pub const ACTION_RESTORE: &str = "restore";
+/// 从回收站彻底删除
+pub const ACTION_PURGE: &str = "purge";
+/// 发布构建, 需要审批时为提交发布申请
+pub const ACTION_PROMOTE: &str = "promote";
+pub const ACTION_APPROVE: &str = "approve";
+/// 撤销发布或发布申请
+pub const ACTION_DEMOTE: &str = "demote";
+pub const ACTION_DISABLE: &str = "disable";
+pub const ACTION_ENABLE: &str = "enable";
+pub const ACTION_RESET_PASSWORD: &str = "reset_password";
+/// 解除登录锁定
+pub const ACTION_UNLOCK: &str = "unlock";
+
+/// 每次修改都会变化的字段, 操作人和时间已经单独记录, 不计入 diff
+const IGNORED_FIELDS: [&str; 2] = ["update_user", "update_time"];
+
+/// 一次修改, before/after 是修改前后记录的内容, 新建时没有 before, 删除时没有 after
+#[derive(Debug)]
+pub struct Entry<'a> {
+    pub username: &'a str,
+    pub page: &'a str,
+    pub entity_id: Option<&'a str>,
+    pub action: &'a str,
+    pub client_ip: Option<&'a str>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct AuditLog {
+    pub id: i64,
+    pub username: String,
+    pub page: String,
+    pub entity_id: Option<String>,
+    pub action: String,
+    pub client_ip: Option<String>,
+    pub diff: Option<String>,
+    pub before_data: Option<String>,
+    pub after_data: Option<String>,
+    pub create_time: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AuditLogView {
+    pub id: i64,
+    pub username: String,
+    pub page: String,
+    pub entity_id: Option<String>,
+    pub action: String,
+    pub client_ip: Option<String>,
+    pub diff: Value,
+    pub before: Value,
+    pub after: Value,
+    pub create_time: DateTime<Utc>,
+}
+
+fn parse_json(s: &Option<String>) -> Value {
+    s.as_deref()
+        .and_then(|s| serde_json::from_str(s).ok())
+        .unwrap_or(Value::Null)
+}
+
+impl From<AuditLog> for AuditLogView {
+    fn from(log: AuditLog) -> Self {
+        AuditLogView {
+            diff: parse_json(&log.diff),
+            before: parse_json(&log.before_data),
+            after: parse_json(&log.after_data),
+            id: log.id,
+            username: log.username,
+            page: log.page,
+            entity_id: log.entity_id,
+            action: log.action,
+            client_ip: log.client_ip,
+            create_time: log.create_time,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AuditQuery {
+    pub limit: Option<u32>,
+    pub page: Option<u32>,
+    #[serde(rename = "s_user")]
+    pub username: Option<String>,
+    #[serde(rename = "s_page")]
+    pub page_name: Option<String>,
+    #[serde(rename = "s_entity")]
+    pub entity_id: Option<String>,
+    #[serde(rename = "s_action")]
+    pub action: Option<String>,
+    /// rfc3339, 如 2021-01-01T00:00:00Z
+    #[serde(rename = "s_start")]
+    pub start: Option<DateTime<Utc>>,
+    #[serde(rename = "s_end")]
+    pub end: Option<DateTime<Utc>>,
+}
+
+/// 字段级别的差异, `{field: {"before": .., "after": ..}}`, 只包含变化的字段
+pub fn diff(before: Option<&Value>, after: Option<&Value>) -> Value {
+    let empty = Map::new();
+    let b = before.and_then(Value::as_object).unwrap_or(&empty);
+    let a = after.and_then(Value::as_object).unwrap_or(&empty);
+
+    let mut keys: Vec<&String> = b.keys().chain(a.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    let mut changes = Map::new();
+    for key in keys {
+        if IGNORED_FIELDS.contains(&key.as_str()) {
+            continue;
+        }
+        let old = b.get(key).unwrap_or(&Value::Null);
+        let new = a.get(key).unwrap_or(&Value::Null);
+        if old != new {
+            changes.insert(key.clone(), json!({ "before": old, "after": new }));
+        }
+    }
+
+    Value::Object(changes)
+}
+
+fn to_text(v: &Option<Value>) -> Option<String> {
+    v.as_ref().map(Value::to_string)
+}
+
+pub async fn record(entry: &Entry<'_>) -> AppResult<()> {
+    let changes = diff(entry.before.as_ref(), entry.after.as_ref());
+
+    execute_args(
+        "insert into sys_audit_log (username, page, entity_id, action, client_ip, diff, before_data, after_data, create_time)
+values (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        &sql_args![
+            entry.username,
+            entry.page,
+            entry.entity_id,
+            entry.action,
+            entry.client_ip,
+            changes.to_string(),
+            to_text(&entry.before),
+            to_text(&entry.after),
+            Utc::now()
+        ],
+    )
+    .await
+}
+
+pub async fn list(info: &AuditQuery) -> AppResult<Value> {
+    let limit = info.limit.unwrap_or(20);
+    let page = info.page.unwrap_or(1);
+
+    let mut w = SqlBuilder::new("1 = 1");
+    if let Some(username) = &info.username {
+        w.push(" and username = ?").bind(username);
+    }
+    if let Some(page_name) = &info.page_name {
+        w.push(" and page = ?").bind(page_name);
+    }
+    if let Some(entity_id) = &info.entity_id {
+        w.push(" and entity_id = ?").bind(entity_id);
+    }
+    if let Some(action) = &info.action {
+        w.push(" and action = ?").bind(action);
+    }
+    if let Some(start) = info.start {
+        w.push(" and create_time >= ?").bind(start);
+    }
+    if let Some(end) = info.end {
+        w.push(" and create_time < ?").bind(end);
+    }
+
+    let sql = sql_page_str(
+        &format!(
+            r#"select id, username, page, entity_id, action, client_ip, diff, before_data, after_data, create_time
+from sys_audit_log where {} order by id desc"#,
+            w.sql()
+        ),
+        limit,
+        page,
+    )?;
+
+    let count = count_args(
+        &format!("SELECT COUNT(id) FROM sys_audit_log where {}", w.sql()),
+        w.args(),
+    )
+    .await?;
+
+    let mut data: Vec<AuditLog> = Vec::new();
+    mysql_query!(AuditLog, data, &sql, w.args())?;
+
+    serde_json::to_value(ListData::<AuditLogView> {
+        current_page: page,
+        page_size: limit,
+        total: count,
+        page_list: data.into_iter().map(AuditLogView::from).collect(),
+    })
+    .map_err(AppError::internal)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::diff;
+
+    #[test]
+    fn test_diff() {
+        let before = json!({"id": 1, "name": "a", "remark": null, "update_time": "t1", "sort": 1});
+        let after = json!({"id": 1, "name": "b", "remark": "r", "update_time": "t2", "sort": 1});
+
+        assert_eq!(
+            json!({
+                "name": {"before": "a", "after": "b"},
+                "remark": {"before": null, "after": "r"},
+            }),
+            diff(Some(&before), Some(&after))
+        );
+
+        let created = diff(None, Some(&after));
+        assert_eq!(json!({"before": null, "after": 1}), created["id"]);
+        assert!(created.get("update_time").is_none());
+
+        let deleted = diff(Some(&before), None);
+        assert_eq!(json!({"before": "a", "after": null}), deleted["name"]);
+
+        assert_eq!(json!({}), diff(Some(&before), Some(&before)));
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// 带过期时间的内存缓存, 用于计算较重但允许稍旧的结果, 如统计
+pub struct TtlCache<V> {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<String, (Instant, V)>>,
+}
+
+impl<V: Clone> TtlCache<V> {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        TtlCache {
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<V> {
+        self.get_at(key, Instant::now())
+    }
+
+    pub fn insert(&self, key: String, value: V) {
+        self.insert_at(key, value, Instant::now())
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    fn get_at(&self, key: &str, now: Instant) -> Option<V> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|(time, _)| now.duration_since(*time) < self.ttl)
+            .map(|(_, v)| v.clone())
+    }
+
+    /// 满了以后先清理过期的, 仍然满时去掉最旧的
+    fn insert_at(&self, key: String, value: V, now: Instant) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let ttl = self.ttl;
+            entries.retain(|_, (time, _)| now.duration_since(*time) < ttl);
+
+            if entries.len() >= self.max_entries {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, (time, _))| *time)
+                    .map(|(k, _)| k.clone());
+                if let Some(k) = oldest {
+                    entries.remove(&k);
+                }
+            }
+        }
+        entries.insert(key, (now, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::TtlCache;
+
+    #[test]
+    fn test_ttl_cache() {
+        let cache = TtlCache::new(Duration::from_secs(60), 2);
+        let start = Instant::now();
+
+        cache.insert_at("a".to_string(), 1, start);
+        assert_eq!(Some(1), cache.get_at("a", start + Duration::from_secs(59)));
+        assert_eq!(None, cache.get_at("a", start + Duration::from_secs(60)));
+        assert_eq!(None, cache.get_at("b", start));
+
+        // 满了以后去掉最旧的
+        cache.insert_at("b".to_string(), 2, start + Duration::from_secs(1));
+        cache.insert_at("c".to_string(), 3, start + Duration::from_secs(2));
+        let now = start + Duration::from_secs(3);
+        assert_eq!(None, cache.get_at("a", now));
+        assert_eq!(Some(2), cache.get_at("b", now));
+        assert_eq!(Some(3), cache.get_at("c", now));
+
+        cache.clear();
+        assert_eq!(None, cache.get_at("b", now));
+    }
+}
@@ -0,0 +1,167 @@
+use actix_web::{http::header, HttpRequest};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    error::{AppError, AppResult},
+    mysql::{execute_affected, execute_args, insert_args},
+    mysql_query, password,
+    sha::sha256_hex,
+    sql_args,
+};
+
+/// 生成的 token 形如 ci_xxxx, 前缀方便在日志和配置中识别
+const TOKEN_PREFIX: &str = "ci_";
+const TOKEN_LEN: usize = 40;
+
+#[derive(sqlx::FromRow, Debug, Serialize, Clone)]
+pub struct CiToken {
+    pub id: i64,
+    pub name: String,
+    pub project_id: Option<i64>,
+    pub create_user: Option<String>,
+    pub create_time: DateTime<Utc>,
+    pub last_used_time: Option<DateTime<Utc>>,
+    pub revoked: Option<String>,
+}
+
+impl CiToken {
+    /// 限定了项目的 token 只能操作该项目
+    pub fn allows_project(&self, project_id: i64) -> bool {
+        self.project_id.map(|p| p == project_id).unwrap_or(true)
+    }
+
+    pub fn check_project(&self, project_id: i64) -> AppResult<()> {
+        if self.allows_project(project_id) {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(format!(
+                "token {} 不能操作项目 {}",
+                self.name, project_id
+            )))
+        }
+    }
+
+    /// 审计日志中的操作人
+    pub fn actor(&self) -> String {
+        format!("ci:{}", self.name)
+    }
+}
+
+pub fn generate() -> String {
+    format!("{}{}", TOKEN_PREFIX, password::random_string(TOKEN_LEN))
+}
+
+/// `Authorization: Bearer <token>`
+pub fn parse_bearer(value: &str) -> Option<&str> {
+    let mut parts = value.trim().splitn(2, ' ');
+    let scheme = parts.next()?;
+    let token = parts.next()?.trim();
+    if scheme.eq_ignore_ascii_case("bearer") && !token.is_empty() {
+        Some(token)
+    } else {
+        None
+    }
+}
+
+const SELECT_TOKEN: &str =
+    "select id, name, project_id, create_user, create_time, last_used_time, revoked from sys_ci_token";
+
+/// 校验请求中的 token, 失败时返回 Auth 错误
+pub async fn authenticate(req: &HttpRequest) -> AppResult<CiToken> {
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_bearer)
+        .ok_or_else(|| AppError::Auth("缺少 Authorization: Bearer token".to_string()))?;
+
+    let mut data: Vec<CiToken> = Vec::new();
+    mysql_query!(
+        CiToken,
+        data,
+        &format!("{} where token_hash = ? and revoked is null", SELECT_TOKEN),
+        &sql_args![sha256_hex(token)]
+    )?;
+
+    let ci = data
+        .pop()
+        .ok_or_else(|| AppError::Auth("token 无效或已注销".to_string()))?;
+
+    execute_args(
+        "UPDATE sys_ci_token SET last_used_time = NOW() where id = ?",
+        &sql_args![ci.id],
+    )
+    .await?;
+
+    Ok(ci)
+}
+
+/// 返回明文 token, 只有这一次能看到
+pub async fn create(name: &str, project_id: Option<i64>, user: &str) -> AppResult<(u64, String)> {
+    let token = generate();
+    let id = insert_args(
+        "insert into sys_ci_token (name, token_hash, project_id, create_user, create_time) values (?, ?, ?, ?, NOW())",
+        &sql_args![name, sha256_hex(&token), project_id, user],
+    )
+    .await?;
+    Ok((id, token))
+}
+
+pub async fn list() -> AppResult<Vec<CiToken>> {
+    let mut data: Vec<CiToken> = Vec::new();
+    mysql_query!(CiToken, data, &format!("{} order by id desc", SELECT_TOKEN))?;
+    Ok(data)
+}
+
+pub async fn revoke(id: u32) -> AppResult<()> {
+    let n = execute_affected(
+        "UPDATE sys_ci_token SET revoked = 'Y' where id = ? and revoked is null",
+        &sql_args![id],
+    )
+    .await?;
+
+    if n == 0 {
+        return Err(AppError::NotFound(format!("token {} 不存在", id)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::{generate, parse_bearer, CiToken};
+
+    #[test]
+    fn test_parse_bearer() {
+        assert_eq!(Some("abc"), parse_bearer("Bearer abc"));
+        assert_eq!(Some("abc"), parse_bearer("bearer  abc "));
+        assert_eq!(None, parse_bearer("Basic abc"));
+        assert_eq!(None, parse_bearer("Bearer "));
+        assert_eq!(None, parse_bearer("abc"));
+    }
+
+    #[test]
+    fn test_token() {
+        let t = generate();
+        assert!(t.starts_with("ci_"));
+        assert_eq!(43, t.len());
+        assert_ne!(t, generate());
+
+        let mut ci = CiToken {
+            id: 1,
+            name: "jenkins".to_string(),
+            project_id: None,
+            create_user: None,
+            create_time: Utc::now(),
+            last_used_time: None,
+            revoked: None,
+        };
+        assert!(ci.allows_project(3));
+        ci.project_id = Some(4);
+        assert!(!ci.allows_project(3));
+        assert!(ci.check_project(4).is_ok());
+        assert_eq!("ci:jenkins", ci.actor());
+    }
+}
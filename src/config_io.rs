@@ -0,0 +1,371 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    config_type,
+    error::{AppError, AppResult, FieldError},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Yaml,
+    Csv,
+}
+
+pub const FORMATS: [&str; 3] = ["json", "yaml", "csv"];
+
+impl Format {
+    pub fn parse(s: &str) -> AppResult<Format> {
+        match s {
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "csv" => Ok(Format::Csv),
+            _ => Err(AppError::Validation(format!(
+                "不支持的格式 {}, 可选 {:?}",
+                s, FORMATS
+            ))),
+        }
+    }
+
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Format::Json => "mdm45_config.json",
+            Format::Yaml => "mdm45_config.yaml",
+            Format::Csv => "mdm45_config.csv",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Json => "application/json; charset=utf-8",
+            Format::Yaml => "application/yaml; charset=utf-8",
+            Format::Csv => "text/csv; charset=utf-8",
+        }
+    }
+}
+
+/// 导入导出的配置项定义, 按 config_key 对应
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigDef {
+    pub config_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_name: Option<String>,
+    pub config_type: String,
+    #[serde(default)]
+    pub category: String,
+    #[serde(default)]
+    pub module: String,
+    #[serde(default)]
+    pub sort: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remark: Option<String>,
+    /// json / yaml 中为对象
+    #[serde(
+        default,
+        with = "crate::mysql::json_text",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub constraints: Option<String>,
+}
+
+/// csv 的一行, constraints 为 json 文本
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CsvRow {
+    config_key: String,
+    config_name: Option<String>,
+    config_type: String,
+    #[serde(default)]
+    category: String,
+    #[serde(default)]
+    module: String,
+    #[serde(default)]
+    sort: i64,
+    remark: Option<String>,
+    constraints: Option<String>,
+}
+
+fn non_empty(s: Option<String>) -> Option<String> {
+    s.map(|s| s.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+impl ConfigDef {
+    /// 去掉首尾空白, 空字符串视为没有, constraints 统一成紧凑的 json
+    pub fn normalize(mut self) -> ConfigDef {
+        self.config_key = self.config_key.trim().to_string();
+        self.config_type = self.config_type.trim().to_string();
+        self.category = self.category.trim().to_string();
+        self.module = self.module.trim().to_string();
+        self.config_name = non_empty(self.config_name);
+        self.remark = non_empty(self.remark);
+        self.constraints =
+            non_empty(self.constraints).and_then(|c| match serde_json::from_str::<Value>(&c) {
+                Ok(Value::Null) => None,
+                Ok(v) => Some(v.to_string()),
+                Err(_) => Some(c),
+            });
+        self
+    }
+}
+
+/// 按 module, category, sort, key 排序后输出
+pub fn export(format: Format, defs: &[ConfigDef]) -> AppResult<String> {
+    let mut defs = defs.to_vec();
+    defs.sort_by(|a, b| {
+        (&a.module, &a.category, a.sort, &a.config_key).cmp(&(
+            &b.module,
+            &b.category,
+            b.sort,
+            &b.config_key,
+        ))
+    });
+
+    match format {
+        Format::Json => serde_json::to_string_pretty(&defs).map_err(AppError::internal),
+        Format::Yaml => serde_yaml::to_string(&defs).map_err(AppError::internal),
+        Format::Csv => {
+            let mut w = csv::Writer::from_writer(Vec::new());
+            for d in defs {
+                w.serialize(CsvRow {
+                    config_key: d.config_key,
+                    config_name: d.config_name,
+                    config_type: d.config_type,
+                    category: d.category,
+                    module: d.module,
+                    sort: d.sort,
+                    remark: d.remark,
+                    constraints: d.constraints,
+                })
+                .map_err(AppError::internal)?;
+            }
+            let data = w.into_inner().map_err(AppError::internal)?;
+            String::from_utf8(data).map_err(AppError::internal)
+        }
+    }
+}
+
+/// 解析并检查导入的内容, 错误的 field 为 `config_key.字段`
+pub fn parse(format: Format, text: &str) -> AppResult<Vec<ConfigDef>> {
+    let defs: Vec<ConfigDef> = match format {
+        Format::Json => serde_json::from_str(text)
+            .map_err(|e| AppError::Validation(format!("json 格式错误: {}", e)))?,
+        Format::Yaml => serde_yaml::from_str(text)
+            .map_err(|e| AppError::Validation(format!("yaml 格式错误: {}", e)))?,
+        Format::Csv => csv::Reader::from_reader(text.as_bytes())
+            .deserialize::<CsvRow>()
+            .map(|row| {
+                row.map(|r| ConfigDef {
+                    config_key: r.config_key,
+                    config_name: r.config_name,
+                    config_type: r.config_type,
+                    category: r.category,
+                    module: r.module,
+                    sort: r.sort,
+                    remark: r.remark,
+                    constraints: r.constraints,
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map_err(|e| AppError::Validation(format!("csv 格式错误: {}", e)))?,
+    };
+    let defs: Vec<ConfigDef> = defs.into_iter().map(ConfigDef::normalize).collect();
+
+    let mut errors = Vec::new();
+    let mut keys = HashSet::new();
+    for (i, d) in defs.iter().enumerate() {
+        let name = if d.config_key.is_empty() {
+            format!("#{}", i + 1)
+        } else {
+            d.config_key.clone()
+        };
+        if d.config_key.is_empty() {
+            errors.push(FieldError::new(&format!("{}.config_key", name), "不能为空"));
+        } else if !keys.insert(d.config_key.as_str()) {
+            errors.push(FieldError::new(&format!("{}.config_key", name), "重复"));
+        }
+        for e in config_type::check_definition(&d.config_type, d.constraints.as_deref()) {
+            errors.push(FieldError::new(&format!("{}.{}", name, e.field), e.message));
+        }
+    }
+    AppError::check_fields(errors)?;
+    Ok(defs)
+}
+
+/// 导入时比较的字段
+const FIELDS: [&str; 7] = [
+    "config_name",
+    "config_type",
+    "category",
+    "module",
+    "sort",
+    "remark",
+    "constraints",
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub key: String,
+    pub fields: Vec<&'static str>,
+    pub from: ConfigDef,
+    pub to: ConfigDef,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportPlan {
+    pub added: Vec<ConfigDef>,
+    pub changed: Vec<Change>,
+    /// prune 时导入内容中没有的 key
+    pub removed: Vec<ConfigDef>,
+    pub unchanged: usize,
+}
+
+impl ImportPlan {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+fn changed_fields(from: &ConfigDef, to: &ConfigDef) -> Vec<&'static str> {
+    let a = serde_json::to_value(from).unwrap_or_default();
+    let b = serde_json::to_value(to).unwrap_or_default();
+    FIELDS.iter().copied().filter(|f| a[*f] != b[*f]).collect()
+}
+
+/// current 为已有的定义, incoming 为导入的定义
+pub fn plan(current: &[ConfigDef], incoming: &[ConfigDef], prune: bool) -> ImportPlan {
+    let mut p = ImportPlan::default();
+    for to in incoming {
+        match current.iter().find(|c| c.config_key == to.config_key) {
+            None => p.added.push(to.clone()),
+            Some(from) => {
+                let from = from.clone().normalize();
+                let fields = changed_fields(&from, to);
+                if fields.is_empty() {
+                    p.unchanged += 1;
+                } else {
+                    p.changed.push(Change {
+                        key: to.config_key.clone(),
+                        fields,
+                        from,
+                        to: to.clone(),
+                    });
+                }
+            }
+        }
+    }
+    if prune {
+        p.removed = current
+            .iter()
+            .filter(|c| !incoming.iter().any(|d| d.config_key == c.config_key))
+            .cloned()
+            .collect();
+    }
+    p
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export, parse, plan, ConfigDef, Format};
+    use crate::error::AppError;
+
+    fn def(key: &str, config_type: &str, sort: i64) -> ConfigDef {
+        ConfigDef {
+            config_key: key.to_string(),
+            config_name: Some(format!("{} 名称", key)),
+            config_type: config_type.to_string(),
+            category: "c".to_string(),
+            module: "m".to_string(),
+            sort,
+            remark: None,
+            constraints: None,
+        }
+    }
+
+    fn defs() -> Vec<ConfigDef> {
+        let mut timeout = def("timeout", "int", 2);
+        timeout.constraints = Some(r#"{"max":60,"min":1}"#.to_string());
+        vec![timeout, def("debug", "bool", 1)]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for format in [Format::Json, Format::Yaml, Format::Csv].iter() {
+            let text = export(*format, &defs()).unwrap();
+            let parsed = parse(*format, &text).unwrap();
+            assert_eq!("debug", parsed[0].config_key);
+            assert_eq!(defs()[0], parsed[1], "{:?}", format);
+        }
+
+        let json = export(Format::Json, &defs()).unwrap();
+        assert!(json.contains(r#""constraints": {"#));
+        let csv = export(Format::Csv, &defs()).unwrap();
+        assert!(csv.starts_with(
+            "config_key,config_name,config_type,category,module,sort,remark,constraints\n"
+        ));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse(Format::Json, "{").is_err());
+        assert!(parse(
+            Format::Json,
+            r#"[{"config_key": "a", "config_type": "int", "x": 1}]"#
+        )
+        .is_err());
+
+        let yaml = "- config_key: a\n  config_type: date\n- config_key: a\n  config_type: int\n- config_key: ' '\n  config_type: enum\n";
+        match parse(Format::Yaml, yaml) {
+            Err(AppError::Fields(errors)) => {
+                let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+                assert_eq!(
+                    vec![
+                        "a.config_type",
+                        "a.config_key",
+                        "#3.config_key",
+                        "#3.constraints.values"
+                    ],
+                    fields
+                );
+            }
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+
+        let csv = "config_key,config_name,config_type,category,module,sort,remark,constraints\nk,,string,c,m,1,,\n";
+        let parsed = parse(Format::Csv, csv).unwrap();
+        assert_eq!(None, parsed[0].config_name);
+        assert_eq!(None, parsed[0].constraints);
+    }
+
+    #[test]
+    fn test_plan() {
+        let mut current = defs();
+        current.push(def("old", "string", 3));
+        // 数据库中的约束可能不是紧凑格式
+        current[0].constraints = Some(r#"{ "min": 1, "max": 60 }"#.to_string());
+
+        let mut incoming = defs();
+        incoming[1].config_type = "string".to_string();
+        incoming.push(def("new", "url", 4));
+
+        let p = plan(&current, &incoming, false);
+        assert_eq!(vec!["new"], keys(&p.added));
+        assert_eq!(1, p.changed.len());
+        assert_eq!("debug", p.changed[0].key);
+        assert_eq!(vec!["config_type"], p.changed[0].fields);
+        assert_eq!(1, p.unchanged);
+        assert!(p.removed.is_empty());
+
+        let p = plan(&current, &incoming, true);
+        assert_eq!(vec!["old"], keys(&p.removed));
+        assert!(!p.is_empty());
+        assert!(plan(&incoming, &incoming, true).is_empty());
+    }
+
+    fn keys(defs: &[ConfigDef]) -> Vec<&str> {
+        defs.iter().map(|d| d.config_key.as_str()).collect()
+    }
+}
@@ -0,0 +1,294 @@
+use regex::RegexBuilder;
+use serde::{Deserialize, Serialize};
+
+use crate::error::FieldError;
+
+/// 正则编译后的大小上限, 防止过大的表达式
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+const DEFAULT_SCHEMES: [&str; 2] = ["http", "https"];
+
+/// MdmConfig.config_type 支持的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigType {
+    Bool,
+    Int,
+    Str,
+    Enum,
+    Url,
+    Json,
+}
+
+pub const TYPES: [&str; 6] = ["bool", "int", "string", "enum", "url", "json"];
+
+impl ConfigType {
+    /// 兼容已有数据中的 boolean, integer 等写法
+    pub fn parse(s: &str) -> Option<ConfigType> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "bool" | "boolean" => Some(ConfigType::Bool),
+            "int" | "integer" | "long" | "number" => Some(ConfigType::Int),
+            "string" | "str" | "text" => Some(ConfigType::Str),
+            "enum" => Some(ConfigType::Enum),
+            "url" => Some(ConfigType::Url),
+            "json" => Some(ConfigType::Json),
+            _ => None,
+        }
+    }
+}
+
+/// 每个配置项的约束, 保存在 tb_version_config_mdm45.constraints (json)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Constraints {
+    /// 生成配置时必须有值
+    pub required: bool,
+    /// int 的范围
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    /// string 的正则, 需要匹配整个值时自己加 ^ 和 $
+    pub pattern: Option<String>,
+    /// string / url 的最大字符数
+    pub max_length: Option<usize>,
+    /// enum 可选的值
+    pub values: Vec<String>,
+    /// url 允许的 scheme, 默认 http 和 https
+    pub schemes: Vec<String>,
+}
+
+impl Constraints {
+    /// 没有设置时为默认约束
+    pub fn parse(text: Option<&str>) -> Result<Constraints, FieldError> {
+        match text.map(str::trim) {
+            None | Some("") | Some("null") => Ok(Constraints::default()),
+            Some(t) => serde_json::from_str(t)
+                .map_err(|e| FieldError::new("constraints", format!("格式错误: {}", e))),
+        }
+    }
+}
+
+/// 保存配置项定义时检查类型和约束是否匹配
+pub fn check_definition(config_type: &str, constraints: Option<&str>) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    let ty = match ConfigType::parse(config_type) {
+        Some(ty) => ty,
+        None => {
+            errors.push(FieldError::new(
+                "config_type",
+                format!("不支持 {}, 可选 {:?}", config_type, TYPES),
+            ));
+            return errors;
+        }
+    };
+    let c = match Constraints::parse(constraints) {
+        Ok(c) => c,
+        Err(e) => {
+            errors.push(e);
+            return errors;
+        }
+    };
+
+    let mut only = |field: &str, set: bool, allowed: &[ConfigType]| {
+        if set && !allowed.contains(&ty) {
+            errors.push(FieldError::new(
+                &format!("constraints.{}", field),
+                format!("不能用于 {} 类型", config_type),
+            ));
+        }
+    };
+    only("min", c.min.is_some(), &[ConfigType::Int]);
+    only("max", c.max.is_some(), &[ConfigType::Int]);
+    only("pattern", c.pattern.is_some(), &[ConfigType::Str]);
+    only(
+        "max_length",
+        c.max_length.is_some(),
+        &[ConfigType::Str, ConfigType::Url],
+    );
+    only("values", !c.values.is_empty(), &[ConfigType::Enum]);
+    only("schemes", !c.schemes.is_empty(), &[ConfigType::Url]);
+
+    if let (Some(min), Some(max)) = (c.min, c.max) {
+        if min > max {
+            errors.push(FieldError::new("constraints.min", "不能大于 max"));
+        }
+    }
+    if let Some(p) = &c.pattern {
+        if let Err(e) = RegexBuilder::new(p).size_limit(REGEX_SIZE_LIMIT).build() {
+            errors.push(FieldError::new("constraints.pattern", e.to_string()));
+        }
+    }
+    if ty == ConfigType::Enum {
+        let mut values = c.values.clone();
+        values.sort();
+        values.dedup();
+        if c.values.is_empty() {
+            errors.push(FieldError::new("constraints.values", "enum 需要可选的值"));
+        } else if values.len() != c.values.len() {
+            errors.push(FieldError::new("constraints.values", "有重复的值"));
+        }
+    }
+    errors
+}
+
+fn parse_bool(v: &str) -> Option<bool> {
+    match v.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" => Some(true),
+        "false" | "0" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// 检查一个值, 错误的 field 为 key. 不认识的类型 (旧数据) 不检查
+pub fn check_value(
+    key: &str,
+    config_type: &str,
+    constraints: &Constraints,
+    value: &str,
+) -> Option<FieldError> {
+    let error = |msg: String| Some(FieldError::new(key, msg));
+    let c = constraints;
+    if c.required && value.trim().is_empty() {
+        return error("不能为空".to_string());
+    }
+
+    match ConfigType::parse(config_type)? {
+        ConfigType::Bool => {
+            if parse_bool(value).is_none() {
+                return error(format!("{} 不是 bool", value));
+            }
+        }
+        ConfigType::Int => {
+            let n = match value.trim().parse::<i64>() {
+                Ok(n) => n,
+                Err(_) => return error(format!("{} 不是整数", value)),
+            };
+            if c.min.map_or(false, |min| n < min) || c.max.map_or(false, |max| n > max) {
+                return error(format!(
+                    "{} 超出范围 [{}, {}]",
+                    n,
+                    c.min.map_or("-".to_string(), |v| v.to_string()),
+                    c.max.map_or("-".to_string(), |v| v.to_string())
+                ));
+            }
+        }
+        ConfigType::Str => {
+            if let Some(p) = &c.pattern {
+                let matched = RegexBuilder::new(p)
+                    .size_limit(REGEX_SIZE_LIMIT)
+                    .build()
+                    .map(|re| re.is_match(value))
+                    .unwrap_or(false);
+                if !matched {
+                    return error(format!("不匹配 {}", p));
+                }
+            }
+        }
+        ConfigType::Enum => {
+            if !c.values.iter().any(|v| v == value) {
+                return error(format!("{} 不在 {:?} 中", value, c.values));
+            }
+        }
+        ConfigType::Url => {
+            let url = match url::Url::parse(value.trim()) {
+                Ok(url) => url,
+                Err(e) => return error(format!("不是有效的 url: {}", e)),
+            };
+            let ok = if c.schemes.is_empty() {
+                DEFAULT_SCHEMES.contains(&url.scheme())
+            } else {
+                c.schemes.iter().any(|s| s == url.scheme())
+            };
+            if !ok {
+                return error(format!("不支持 {} 协议", url.scheme()));
+            }
+        }
+        ConfigType::Json => {
+            if serde_json::from_str::<serde_json::Value>(value).is_err() {
+                return error("不是有效的 json".to_string());
+            }
+        }
+    }
+
+    if let Some(max) = c.max_length {
+        if value.chars().count() > max {
+            return error(format!("不能超过 {} 个字符", max));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_definition, check_value, ConfigType, Constraints};
+
+    fn fields(errors: Vec<crate::error::FieldError>) -> Vec<String> {
+        errors.into_iter().map(|e| e.field).collect()
+    }
+
+    #[test]
+    fn test_check_definition() {
+        assert_eq!(Some(ConfigType::Bool), ConfigType::parse("Boolean"));
+        assert!(check_definition("int", Some(r#"{"min": 1, "max": 10}"#)).is_empty());
+        assert!(check_definition("string", None).is_empty());
+        assert_eq!(vec!["config_type"], fields(check_definition("date", None)));
+        assert_eq!(
+            vec!["constraints"],
+            fields(check_definition("int", Some(r#"{"minimum": 1}"#)))
+        );
+        assert_eq!(
+            vec!["constraints.pattern"],
+            fields(check_definition("bool", Some(r#"{"pattern": "a"}"#)))
+        );
+        assert_eq!(
+            vec!["constraints.min"],
+            fields(check_definition("int", Some(r#"{"min": 2, "max": 1}"#)))
+        );
+        assert_eq!(
+            vec!["constraints.pattern"],
+            fields(check_definition("string", Some(r#"{"pattern": "("}"#)))
+        );
+        assert_eq!(
+            vec!["constraints.values"],
+            fields(check_definition("enum", None))
+        );
+        assert_eq!(
+            vec!["constraints.values"],
+            fields(check_definition("enum", Some(r#"{"values": ["a", "a"]}"#)))
+        );
+    }
+
+    #[test]
+    fn test_check_value() {
+        let none = Constraints::default();
+        let c = |text: &str| Constraints::parse(Some(text)).unwrap();
+
+        assert!(check_value("k", "bool", &none, "yes").is_none());
+        assert!(check_value("k", "bool", &none, "maybe").is_some());
+
+        let range = c(r#"{"min": 1, "max": 10}"#);
+        assert!(check_value("k", "int", &range, "10").is_none());
+        assert!(check_value("k", "int", &range, "11").is_some());
+        assert!(check_value("k", "int", &range, "1.5").is_some());
+
+        let pattern = c(r#"{"pattern": "^[a-z]+$", "max_length": 3}"#);
+        assert!(check_value("k", "string", &pattern, "abc").is_none());
+        assert!(check_value("k", "string", &pattern, "abcd").is_some());
+        assert!(check_value("k", "string", &pattern, "A").is_some());
+
+        let values = c(r#"{"values": ["dev", "prod"]}"#);
+        assert!(check_value("k", "enum", &values, "prod").is_none());
+        assert!(check_value("k", "enum", &values, "test").is_some());
+
+        assert!(check_value("k", "url", &none, "https://a.com/x").is_none());
+        assert!(check_value("k", "url", &none, "ftp://a.com").is_some());
+        assert!(check_value("k", "url", &c(r#"{"schemes": ["ftp"]}"#), "ftp://a.com").is_none());
+        assert!(check_value("k", "url", &none, "a.com").is_some());
+
+        assert!(check_value("k", "json", &none, r#"{"a": 1}"#).is_none());
+        assert!(check_value("k", "json", &none, "{").is_some());
+
+        let required = c(r#"{"required": true}"#);
+        let err = check_value("k", "string", &required, " ").unwrap();
+        assert_eq!("k", err.field);
+        // 旧数据中不认识的类型不检查
+        assert!(check_value("k", "text2", &none, "x").is_none());
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    error::{AppError, AppResult},
+    mysql::{insert_args, Tx},
+    mysql_query, sql_args,
+};
+
+/// 值的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Version,
+    Project,
+    Default,
+}
+
+/// 值集的范围, project_id / version_id 为 0 表示没有
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Scope {
+    pub project_id: i64,
+    pub version_id: i64,
+}
+
+impl Scope {
+    pub const DEFAULT: Scope = Scope {
+        project_id: 0,
+        version_id: 0,
+    };
+
+    /// 版本的值集必须属于某个项目
+    pub fn new(project_id: i64, version_id: Option<i64>) -> AppResult<Scope> {
+        let version_id = version_id.unwrap_or(0);
+        if project_id < 0 || version_id < 0 || (project_id == 0 && version_id != 0) {
+            return Err(AppError::Validation("版本的值集需要指定项目".to_string()));
+        }
+        Ok(Scope {
+            project_id,
+            version_id,
+        })
+    }
+
+    pub fn source(&self) -> Source {
+        if self.version_id != 0 {
+            Source::Version
+        } else if self.project_id != 0 {
+            Source::Project
+        } else {
+            Source::Default
+        }
+    }
+
+    /// 自己和继承的范围, 从具体到默认
+    pub fn chain(&self) -> Vec<Scope> {
+        let mut chain = vec![*self];
+        if self.version_id != 0 {
+            chain.push(Scope {
+                project_id: self.project_id,
+                version_id: 0,
+            });
+        }
+        if self.project_id != 0 {
+            chain.push(Scope::DEFAULT);
+        }
+        chain
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize, Clone)]
+pub struct ValueSet {
+    pub id: i64,
+    pub name: String,
+    pub project_id: i64,
+    pub version_id: i64,
+    pub create_user: String,
+    pub create_time: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update_user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update_time: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize, Clone, PartialEq)]
+pub struct ConfigValue {
+    pub config_key: String,
+    pub value: String,
+    pub update_user: String,
+    pub update_time: DateTime<Utc>,
+}
+
+/// 一个值集中覆盖的值
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub source: Source,
+    pub values: BTreeMap<String, ConfigValue>,
+}
+
+/// 配置项的最终值和来源
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Effective {
+    pub key: String,
+    /// 所有值集都没有设置时为空
+    pub value: Option<String>,
+    pub source: Option<Source>,
+    pub update_user: Option<String>,
+    pub update_time: Option<DateTime<Utc>>,
+    /// 当前值集覆盖了继承的值
+    pub overridden: bool,
+    /// 不覆盖时继承的值和来源
+    pub inherited: Option<String>,
+    pub inherited_source: Option<Source>,
+}
+
+/// layers 从具体到默认, 第一个为当前值集
+pub fn resolve(keys: &[String], layers: &[Layer]) -> Vec<Effective> {
+    keys.iter()
+        .map(|key| {
+            let mut found = layers
+                .iter()
+                .filter_map(|l| l.values.get(key).map(|v| (l.source, v)));
+            let current = found.next();
+            let parent = found.next();
+            let overridden = layers.first().map_or(false, |l| l.values.contains_key(key));
+            let inherited = if overridden { parent } else { current };
+
+            Effective {
+                key: key.clone(),
+                value: current.map(|(_, v)| v.value.clone()),
+                source: current.map(|(s, _)| s),
+                update_user: current.map(|(_, v)| v.update_user.clone()),
+                update_time: current.map(|(_, v)| v.update_time),
+                overridden,
+                inherited: inherited.map(|(_, v)| v.value.clone()),
+                inherited_source: inherited.map(|(s, _)| s),
+            }
+        })
+        .collect()
+}
+
+const SELECT_SET: &str = "select id, name, project_id, version_id, create_user, create_time, update_user, update_time from tb_config_value_set";
+
+pub async fn find_set(scope: Scope) -> AppResult<Option<ValueSet>> {
+    let mut data: Vec<ValueSet> = Vec::new();
+    mysql_query!(
+        ValueSet,
+        data,
+        &format!("{} where project_id = ? and version_id = ?", SELECT_SET),
+        &sql_args![scope.project_id, scope.version_id]
+    )?;
+    Ok(data.pop())
+}
+
+/// 没有时创建, 返回值集 id. name 为空时不修改名称
+pub async fn ensure_set(scope: Scope, name: Option<&str>, user: &str) -> AppResult<i64> {
+    let default_name = match scope.source() {
+        Source::Default => "默认".to_string(),
+        Source::Project => format!("项目 {}", scope.project_id),
+        Source::Version => format!("项目 {} 版本 {}", scope.project_id, scope.version_id),
+    };
+    let id = insert_args(
+        r#"insert into tb_config_value_set (name, project_id, version_id, create_user, create_time)
+values (?, ?, ?, ?, NOW())
+on duplicate key update id = LAST_INSERT_ID(id), name = COALESCE(?, name), update_user = ?, update_time = NOW()"#,
+        &sql_args![
+            name.unwrap_or(&default_name),
+            scope.project_id,
+            scope.version_id,
+            user,
+            name,
+            user
+        ],
+    )
+    .await?;
+    Ok(id as i64)
+}
+
+pub async fn values(set_id: i64) -> AppResult<BTreeMap<String, ConfigValue>> {
+    let mut data: Vec<ConfigValue> = Vec::new();
+    mysql_query!(
+        ConfigValue,
+        data,
+        "select config_key, value, update_user, update_time from tb_config_value where set_id = ?",
+        &sql_args![set_id]
+    )?;
+    Ok(data
+        .into_iter()
+        .map(|v| (v.config_key.clone(), v))
+        .collect())
+}
+
+/// 从具体到默认的各级值集, 没有创建的值集为空
+pub async fn layers(scope: Scope) -> AppResult<Vec<Layer>> {
+    let mut layers = Vec::new();
+    for s in scope.chain() {
+        let values = match find_set(s).await? {
+            Some(set) => values(set.id).await?,
+            None => BTreeMap::new(),
+        };
+        layers.push(Layer {
+            source: s.source(),
+            values,
+        });
+    }
+    Ok(layers)
+}
+
+/// 生成配置文件时使用的值, 只包含设置了值的 key
+pub async fn effective_values(scope: Scope) -> AppResult<BTreeMap<String, String>> {
+    let mut map = BTreeMap::new();
+    for layer in layers(scope).await?.into_iter().rev() {
+        map.extend(layer.values.into_iter().map(|(k, v)| (k, v.value)));
+    }
+    Ok(map)
+}
+
+/// 值为 None 时删除覆盖, 恢复继承. 在一个事务中写入, 失败时全部回滚
+pub async fn save(
+    set_id: i64,
+    changes: &BTreeMap<String, Option<String>>,
+    user: &str,
+) -> AppResult<()> {
+    let mut tx = Tx::begin().await?;
+    for (key, value) in changes {
+        match value {
+            Some(value) => {
+                tx.execute(
+                    r#"insert into tb_config_value (set_id, config_key, value, update_user, update_time)
+values (?, ?, ?, ?, NOW())
+on duplicate key update value = values(value), update_user = values(update_user), update_time = values(update_time)"#,
+                    &sql_args![set_id, key, value, user],
+                )
+                .await?;
+            }
+            None => {
+                tx.execute(
+                    "delete from tb_config_value where set_id = ? and config_key = ?",
+                    &sql_args![set_id, key],
+                )
+                .await?;
+            }
+        }
+    }
+    tx.commit().await
+}
+
+/// 复制时要写入的值, overwrite 为 false 时保留目标已经覆盖的 key
+pub fn copy_changes(
+    from: &BTreeMap<String, ConfigValue>,
+    to: &BTreeMap<String, ConfigValue>,
+    keys: &[String],
+    overwrite: bool,
+) -> BTreeMap<String, Option<String>> {
+    from.values()
+        .filter(|v| keys.contains(&v.config_key))
+        .filter(|v| overwrite || !to.contains_key(&v.config_key))
+        .filter(|v| to.get(&v.config_key).map(|t| &t.value) != Some(&v.value))
+        .map(|v| (v.config_key.clone(), Some(v.value.clone())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::Utc;
+
+    use super::{copy_changes, resolve, ConfigValue, Layer, Scope, Source};
+
+    fn values(pairs: &[(&str, &str)]) -> BTreeMap<String, ConfigValue> {
+        pairs
+            .iter()
+            .map(|(k, v)| {
+                (
+                    k.to_string(),
+                    ConfigValue {
+                        config_key: k.to_string(),
+                        value: v.to_string(),
+                        update_user: "u".to_string(),
+                        update_time: Utc::now(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn keys(list: &[&str]) -> Vec<String> {
+        list.iter().map(|k| k.to_string()).collect()
+    }
+
+    #[test]
+    fn test_scope() {
+        assert!(Scope::new(0, Some(2)).is_err());
+        let s = Scope::new(1, Some(2)).unwrap();
+        assert_eq!(Source::Version, s.source());
+        let chain: Vec<Source> = s.chain().iter().map(|s| s.source()).collect();
+        assert_eq!(
+            vec![Source::Version, Source::Project, Source::Default],
+            chain
+        );
+        assert_eq!(vec![Scope::DEFAULT], Scope::DEFAULT.chain());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let layers = vec![
+            Layer {
+                source: Source::Version,
+                values: values(&[("debug", "true")]),
+            },
+            Layer {
+                source: Source::Project,
+                values: values(&[("url", "http://p"), ("debug", "false")]),
+            },
+            Layer {
+                source: Source::Default,
+                values: values(&[("url", "http://d"), ("timeout", "30")]),
+            },
+        ];
+        let list = resolve(&keys(&["debug", "url", "timeout", "other"]), &layers);
+
+        assert_eq!(Some("true".to_string()), list[0].value);
+        assert_eq!(Some(Source::Version), list[0].source);
+        assert!(list[0].overridden);
+        assert_eq!(Some("false".to_string()), list[0].inherited);
+        assert_eq!(Some(Source::Project), list[0].inherited_source);
+
+        assert_eq!(Some("http://p".to_string()), list[1].value);
+        assert_eq!(Some(Source::Project), list[1].source);
+        assert!(!list[1].overridden);
+        assert_eq!(Some("http://p".to_string()), list[1].inherited);
+
+        assert_eq!(Some(Source::Default), list[2].source);
+        assert_eq!(None, list[3].value);
+        assert_eq!(None, list[3].source);
+    }
+
+    #[test]
+    fn test_copy_changes() {
+        let from = values(&[("a", "1"), ("b", "2"), ("c", "3"), ("gone", "x")]);
+        let to = values(&[("a", "9"), ("b", "2")]);
+        let keys = keys(&["a", "b", "c"]);
+
+        let changes = copy_changes(&from, &to, &keys, false);
+        assert_eq!(vec!["c"], changes.keys().collect::<Vec<&String>>());
+
+        let changes = copy_changes(&from, &to, &keys, true);
+        assert_eq!(vec!["a", "c"], changes.keys().collect::<Vec<&String>>());
+        assert_eq!(Some(&Some("1".to_string())), changes.get("a"));
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use log::{info, warn};
+use serde::Serialize;
+use sqlx::mysql::MySqlDatabaseError;
+
+use crate::http_response::response_app_error;
+
+/// mysql 唯一索引冲突的错误号
+const ER_DUP_ENTRY: u16 = 1062;
+
+pub type AppResult<T> = Result<T, AppError>;
+
+/// 某个字段的校验错误
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        FieldError {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    Validation(String),
+    /// 多个字段的校验错误, 响应中带上 fields
+    Fields(Vec<FieldError>),
+    Auth(String),
+    Forbidden(String),
+    Conflict(String),
+    RateLimited(String),
+    Database(sqlx::Error),
+    Internal(String),
+}
+
+impl AppError {
+    pub fn internal<E: fmt::Display>(err: E) -> AppError {
+        AppError::Internal(err.to_string())
+    }
+
+    /// 没有错误时返回 Ok
+    pub fn check_fields(errors: Vec<FieldError>) -> AppResult<()> {
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::Fields(errors))
+        }
+    }
+
+    /// 稳定的错误码, 前端根据它做判断, 不要随意修改
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::Validation(_) | AppError::Fields(_) => "validation_error",
+            AppError::Auth(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::Conflict(_) => "conflict",
+            AppError::RateLimited(_) => "rate_limited",
+            AppError::Database(_) => "database_error",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// 返回给客户端的信息, 数据库和内部错误的细节只写日志
+    pub fn message(&self) -> String {
+        match self {
+            AppError::NotFound(msg)
+            | AppError::Validation(msg)
+            | AppError::Auth(msg)
+            | AppError::Forbidden(msg)
+            | AppError::Conflict(msg)
+            | AppError::RateLimited(msg) => msg.clone(),
+            AppError::Fields(errors) => errors
+                .iter()
+                .map(|e| format!("{}: {}", e.field, e.message))
+                .collect::<Vec<String>>()
+                .join("; "),
+            AppError::Database(_) => "数据库错误".to_string(),
+            AppError::Internal(_) => "服务器内部错误".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Database(err) => write!(f, "{}: {}", self.code(), err),
+            AppError::Internal(msg) => write!(f, "{}: {}", self.code(), msg),
+            _ => write!(f, "{}: {}", self.code(), self.message()),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<sqlx::Error> for AppError {
+    fn from(err: sqlx::Error) -> Self {
+        info!("err = {}", err);
+        match err {
+            sqlx::Error::RowNotFound => AppError::NotFound("记录不存在".to_string()),
+            sqlx::Error::Database(ref e)
+                if e.try_downcast_ref::<MySqlDatabaseError>()
+                    .map(|e| e.number() == ER_DUP_ENTRY)
+                    .unwrap_or(false) =>
+            {
+                AppError::Conflict("记录已存在".to_string())
+            }
+            _ => AppError::Database(err),
+        }
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(err: serde_json::Error) -> Self {
+        AppError::Validation(format!("参数错误: {}", err))
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Validation(_) | AppError::Fields(_) => StatusCode::BAD_REQUEST,
+            AppError::Auth(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Database(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AppError::Database(_) | AppError::Internal(_) => warn!("{}", self),
+            _ => info!("{}", self),
+        }
+        response_app_error(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AppError, FieldError};
+    use actix_web::{http::StatusCode, ResponseError};
+
+    #[test]
+    fn test_status_and_code() {
+        let cases = vec![
+            (
+                AppError::NotFound("x".into()),
+                StatusCode::NOT_FOUND,
+                "not_found",
+            ),
+            (
+                AppError::Validation("x".into()),
+                StatusCode::BAD_REQUEST,
+                "validation_error",
+            ),
+            (
+                AppError::Auth("x".into()),
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+            ),
+            (
+                AppError::Forbidden("x".into()),
+                StatusCode::FORBIDDEN,
+                "forbidden",
+            ),
+            (
+                AppError::Conflict("x".into()),
+                StatusCode::CONFLICT,
+                "conflict",
+            ),
+            (
+                AppError::RateLimited("x".into()),
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limited",
+            ),
+            (
+                AppError::Internal("x".into()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal_error",
+            ),
+        ];
+
+        for (err, status, code) in cases {
+            assert_eq!(status, err.status_code());
+            assert_eq!(code, err.code());
+        }
+    }
+
+    #[test]
+    fn test_sanitized_message() {
+        let err = AppError::from(sqlx::Error::Protocol("secret host 10.0.0.1".to_string()));
+        assert_eq!("database_error", err.code());
+        assert!(!err.message().contains("10.0.0.1"));
+
+        let err = AppError::internal("stack trace");
+        assert!(!err.message().contains("stack"));
+
+        let err = AppError::from(sqlx::Error::RowNotFound);
+        assert_eq!("not_found", err.code());
+    }
+
+    #[test]
+    fn test_fields() {
+        assert!(AppError::check_fields(Vec::new()).is_ok());
+
+        let err = AppError::check_fields(vec![
+            FieldError::new("a", "不能为空"),
+            FieldError::new("b", "太长"),
+        ])
+        .unwrap_err();
+        assert_eq!("validation_error", err.code());
+        assert_eq!(StatusCode::BAD_REQUEST, err.status_code());
+        assert_eq!("a: 不能为空; b: 太长", err.message());
+    }
+}
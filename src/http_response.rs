@@ -1,6 +1,11 @@
-use actix_web::HttpResponse;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use actix_web::{HttpResponse, ResponseError};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+
+use crate::error::AppError;
+
 #[derive(Debug, Serialize, Deserialize)]
 enum MyHttpReponse {
     #[serde(rename = "ok")]
@@ -9,8 +14,24 @@ enum MyHttpReponse {
     Error(Value),
 }
 
+/// 兼容模式: 错误也返回 200, 只有 `{"error":{"msg":..}}`, 登录失效时 code 为 401
+static COMPAT_MODE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_compat_mode(compat: bool) {
+    COMPAT_MODE.store(compat, Ordering::Relaxed);
+}
+
+pub fn is_compat_mode() -> bool {
+    COMPAT_MODE.load(Ordering::Relaxed)
+}
+
+/// 成功响应的内容, 需要自己设置响应头时使用
+pub fn ok_body(value: Value) -> String {
+    serde_json::to_string(&MyHttpReponse::Ok(value)).unwrap()
+}
+
 pub fn response_ok(value: Value) -> HttpResponse {
-    HttpResponse::Ok().body(serde_json::to_string(&MyHttpReponse::Ok(value)).unwrap())
+    HttpResponse::Ok().body(ok_body(value))
 }
 
 pub fn response_success(msg: &str) -> HttpResponse {
@@ -18,11 +39,20 @@ pub fn response_success(msg: &str) -> HttpResponse {
         .body(serde_json::to_string(&MyHttpReponse::Ok(json!({ "msg": msg }))).unwrap())
 }
 
-pub fn response_error(msg: &str) -> HttpResponse {
-    HttpResponse::Ok()
-        .body(serde_json::to_string(&MyHttpReponse::Error(json!({ "msg": msg }))).unwrap())
-}
+pub fn response_app_error(err: &AppError) -> HttpResponse {
+    if is_compat_mode() {
+        let value = match err {
+            AppError::Auth(_) => json!({ "code": 401, "msg": err.message() }),
+            _ => json!({ "msg": err.message() }),
+        };
+        return HttpResponse::Ok()
+            .body(serde_json::to_string(&MyHttpReponse::Error(value)).unwrap());
+    }
 
-pub fn response_error2(value: Value) -> HttpResponse {
-    HttpResponse::Ok().body(serde_json::to_string(&MyHttpReponse::Error(json!(value))).unwrap())
+    let mut value = json!({ "code": err.code(), "msg": err.message() });
+    if let AppError::Fields(errors) = err {
+        value["fields"] = json!(errors);
+    }
+    HttpResponse::build(err.status_code())
+        .body(serde_json::to_string(&MyHttpReponse::Error(value)).unwrap())
 }
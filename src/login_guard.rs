@@ -0,0 +1,501 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use log::info;
+use once_cell::sync::OnceCell;
+
+use crate::{
+    config::{Config, LoginGuardConfig},
+    error::{AppError, AppResult},
+    mysql::execute_args,
+    mysql_query, sql_args,
+};
+
+/// 内存中最多保留的记录数, 超过后清理已失效的记录
+const MAX_ENTRIES: usize = 10_000;
+
+pub const RESULT_SUCCESS: &str = "success";
+pub const RESULT_FAILURE: &str = "failure";
+/// 被锁定或等待期内的请求, 没有校验密码
+pub const RESULT_BLOCKED: &str = "blocked";
+/// 管理员解除锁定, client_ip 不为空时同时解除该 ip
+pub const RESULT_UNLOCK: &str = "unlock";
+
+#[derive(Debug, Clone, Default)]
+struct Attempts {
+    failures: u32,
+    last_failure: Option<DateTime<Utc>>,
+    locked_until: Option<DateTime<Utc>>,
+    /// 已经通过检查, 还在校验密码的请求. 有失败记录时同一时间只允许一个,
+    /// 并发的请求不能绕过等待时间
+    pending: u32,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+struct AttemptRow {
+    username: String,
+    client_ip: Option<String>,
+    result: String,
+    create_time: DateTime<Utc>,
+}
+
+/// 按账号和 ip 分别统计连续失败次数, 失败后等待时间指数增长, 达到上限后锁定
+pub struct LoginGuard {
+    config: LoginGuardConfig,
+    states: Mutex<HashMap<String, Attempts>>,
+}
+
+fn user_key(username: &str) -> String {
+    // sys_user.username 不区分大小写
+    format!("user:{}", username.trim().to_lowercase())
+}
+
+fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+/// 向上取整到秒, 至少 1 秒
+fn ceil_secs(d: Duration) -> i64 {
+    ((d.num_milliseconds() + 999) / 1000).max(1)
+}
+
+impl LoginGuard {
+    pub fn new(config: LoginGuardConfig) -> Self {
+        LoginGuard {
+            config,
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn backoff(&self, failures: u32) -> Duration {
+        let shift = failures.saturating_sub(1).min(30);
+        let secs = self
+            .config
+            .backoff_base_secs
+            .saturating_mul(1i64 << shift)
+            .min(self.config.max_backoff_secs);
+        Duration::seconds(secs)
+    }
+
+    /// 最后一次失败超过 lockout_secs 后重新计数
+    fn expired(&self, a: &Attempts, now: DateTime<Utc>) -> bool {
+        let window = Duration::seconds(self.config.lockout_secs);
+        let locked = a.locked_until.map(|t| t > now).unwrap_or(false);
+        let recent = a.last_failure.map(|t| t + window > now).unwrap_or(false);
+        !locked && !recent && a.pending == 0
+    }
+
+    /// 需要等待的秒数, 0 表示可以尝试
+    fn wait_secs(&self, states: &HashMap<String, Attempts>, key: &str, now: DateTime<Utc>) -> i64 {
+        let a = match states.get(key) {
+            Some(a) if !self.expired(a, now) => a,
+            _ => return 0,
+        };
+
+        if let Some(until) = a.locked_until {
+            if until > now {
+                return ceil_secs(until - now);
+            }
+        }
+
+        match a.last_failure {
+            Some(t) if a.failures > 0 && t + self.backoff(a.failures) > now => {
+                ceil_secs(t + self.backoff(a.failures) - now)
+            }
+            _ => 0,
+        }
+    }
+
+    fn fail(&self, key: String, limit: u32, now: DateTime<Utc>) {
+        let mut states = self.states.lock().unwrap();
+        if states.len() > MAX_ENTRIES {
+            states.retain(|_, a| !self.expired(a, now));
+        }
+
+        let a = states.entry(key).or_default();
+        a.pending = a.pending.saturating_sub(1);
+        if self.expired(a, now) {
+            *a = Attempts::default();
+        }
+        a.failures += 1;
+        a.last_failure = Some(now);
+        if a.failures >= limit {
+            a.locked_until = Some(now + Duration::seconds(self.config.lockout_secs));
+        }
+    }
+
+    fn keys(username: &str, ip: Option<&str>) -> Vec<String> {
+        let mut keys = vec![user_key(username)];
+        keys.extend(ip.map(ip_key));
+        keys
+    }
+
+    fn check_locked(
+        &self,
+        states: &HashMap<String, Attempts>,
+        keys: &[String],
+        now: DateTime<Utc>,
+    ) -> AppResult<()> {
+        let wait = keys
+            .iter()
+            .map(|key| self.wait_secs(states, key, now))
+            .max()
+            .unwrap_or(0);
+        if wait > 0 {
+            Err(AppError::RateLimited(format!(
+                "登录失败次数过多, 请 {} 秒后再试",
+                wait
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// 账号或 ip 处于等待期/锁定期时返回 RateLimited
+    pub fn check(&self, username: &str, ip: Option<&str>, now: DateTime<Utc>) -> AppResult<()> {
+        let states = self.states.lock().unwrap();
+        self.check_locked(&states, &Self::keys(username, ip), now)
+    }
+
+    /// 检查并占用一次尝试, 和检查在同一个锁内.
+    /// 没有失败记录时不限制并发, 有失败记录时前一个请求出结果前拒绝新的请求
+    pub fn reserve(
+        &self,
+        username: &str,
+        ip: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> AppResult<Reservation<'_>> {
+        let mut states = self.states.lock().unwrap();
+        let keys = Self::keys(username, ip);
+        self.check_locked(&states, &keys, now)?;
+        let busy = keys.iter().any(|key| {
+            states
+                .get(key)
+                .map(|a| a.pending > 0 && a.failures > 0 && !self.expired(a, now))
+                .unwrap_or(false)
+        });
+        if busy {
+            return Err(AppError::RateLimited(
+                "上一次登录还在处理, 请稍后再试".to_string(),
+            ));
+        }
+        for key in keys {
+            states.entry(key).or_default().pending += 1;
+        }
+        Ok(Reservation {
+            guard: self,
+            username: username.to_string(),
+            ip: ip.map(str::to_string),
+            done: false,
+        })
+    }
+
+    /// 没有校验出结果 (如数据库错误), 释放占用的尝试
+    pub fn cancel(&self, username: &str, ip: Option<&str>) {
+        let mut states = self.states.lock().unwrap();
+        for key in Self::keys(username, ip) {
+            if let Some(a) = states.get_mut(&key) {
+                a.pending = a.pending.saturating_sub(1);
+            }
+        }
+    }
+
+    pub fn failure(&self, username: &str, ip: Option<&str>, now: DateTime<Utc>) {
+        self.fail(user_key(username), self.config.max_failures, now);
+        if let Some(ip) = ip {
+            self.fail(ip_key(ip), self.config.ip_max_failures, now);
+        }
+    }
+
+    /// 登录成功只清除账号的计数, ip 的计数不受影响,
+    /// 否则攻击者可以用自己的账号不断重置
+    pub fn success(&self, username: &str, ip: Option<&str>) {
+        let mut states = self.states.lock().unwrap();
+        states.remove(&user_key(username));
+        if let Some(a) = ip.and_then(|ip| states.get_mut(&ip_key(ip))) {
+            a.pending = a.pending.saturating_sub(1);
+        }
+    }
+
+    pub fn unlock(&self, username: &str) {
+        self.states.lock().unwrap().remove(&user_key(username));
+    }
+
+    pub fn unlock_ip(&self, ip: &str) {
+        self.states.lock().unwrap().remove(&ip_key(ip));
+    }
+}
+
+/// reserve 占用的尝试, 记录结果时释放.
+/// 没有结果就被丢弃时 (数据库错误, 请求被取消) 也会释放
+pub struct Reservation<'a> {
+    guard: &'a LoginGuard,
+    username: String,
+    ip: Option<String>,
+    done: bool,
+}
+
+impl Reservation<'_> {
+    pub fn failure(mut self, now: DateTime<Utc>) {
+        self.done = true;
+        self.guard.failure(&self.username, self.ip.as_deref(), now);
+    }
+
+    pub fn success(mut self) {
+        self.done = true;
+        self.guard.success(&self.username, self.ip.as_deref());
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.guard.cancel(&self.username, self.ip.as_deref());
+        }
+    }
+}
+
+static GUARD: OnceCell<LoginGuard> = OnceCell::new();
+
+pub fn get() -> &'static LoginGuard {
+    GUARD.get_or_init(|| LoginGuard::new(Config::get().login_guard.clone()))
+}
+
+fn is_persist_enabled() -> bool {
+    Config::get().login_guard.persist
+}
+
+/// 登录前检查并占用一次尝试, 被拒绝的请求也会记录
+pub async fn reserve(username: &str, ip: Option<&str>) -> AppResult<Reservation<'static>> {
+    match get().reserve(username, ip, Utc::now()) {
+        Err(AppError::RateLimited(msg)) => {
+            save(username, ip, RESULT_BLOCKED, Some(&msg)).await?;
+            Err(AppError::RateLimited(msg))
+        }
+        res => res,
+    }
+}
+
+pub async fn record_failure(attempt: Reservation<'_>, reason: &str) -> AppResult<()> {
+    let (username, ip) = (attempt.username.clone(), attempt.ip.clone());
+    attempt.failure(Utc::now());
+    save(&username, ip.as_deref(), RESULT_FAILURE, Some(reason)).await
+}
+
+pub async fn record_success(attempt: Reservation<'_>) -> AppResult<()> {
+    let (username, ip) = (attempt.username.clone(), attempt.ip.clone());
+    attempt.success();
+    save(&username, ip.as_deref(), RESULT_SUCCESS, None).await
+}
+
+/// 管理员解除锁定, 记录下来以免重启后恢复
+pub async fn unlock(username: &str, ip: Option<&str>) -> AppResult<()> {
+    let guard = get();
+    guard.unlock(username);
+    if let Some(ip) = ip {
+        guard.unlock_ip(ip);
+    }
+    save(username, ip, RESULT_UNLOCK, None).await
+}
+
+async fn save(
+    username: &str,
+    ip: Option<&str>,
+    result: &str,
+    reason: Option<&str>,
+) -> AppResult<()> {
+    if !is_persist_enabled() {
+        return Ok(());
+    }
+
+    execute_args(
+        "insert into sys_login_attempt (username, client_ip, result, reason, create_time) values (?, ?, ?, ?, ?)",
+        &sql_args![username, ip, result, reason, Utc::now()],
+    )
+    .await
+}
+
+/// 启动时从 sys_login_attempt 恢复锁定状态, 只需要 lockout_secs 内的记录
+pub async fn restore() -> AppResult<()> {
+    if !is_persist_enabled() {
+        return Ok(());
+    }
+
+    let since = Utc::now() - Duration::seconds(Config::get().login_guard.lockout_secs);
+    let mut rows: Vec<AttemptRow> = Vec::new();
+    mysql_query!(
+        AttemptRow,
+        rows,
+        "select username, client_ip, result, create_time from sys_login_attempt where create_time > ? order by id",
+        &sql_args![since]
+    )?;
+
+    let guard = get();
+    for row in &rows {
+        match row.result.as_str() {
+            RESULT_FAILURE => {
+                guard.failure(&row.username, row.client_ip.as_deref(), row.create_time)
+            }
+            RESULT_SUCCESS => guard.success(&row.username, None),
+            RESULT_UNLOCK => {
+                guard.unlock(&row.username);
+                if let Some(ip) = &row.client_ip {
+                    guard.unlock_ip(ip);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    info!("login guard restored from {} attempts", rows.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, Utc};
+
+    use super::LoginGuard;
+    use crate::{config::LoginGuardConfig, error::AppError};
+
+    fn guard() -> LoginGuard {
+        LoginGuard::new(LoginGuardConfig {
+            max_failures: 3,
+            ip_max_failures: 5,
+            lockout_secs: 600,
+            backoff_base_secs: 2,
+            max_backoff_secs: 5,
+            persist: false,
+        })
+    }
+
+    fn start() -> DateTime<Utc> {
+        "2021-01-01T00:00:00Z".parse().unwrap()
+    }
+
+    fn wait(g: &LoginGuard, username: &str, ip: Option<&str>, now: DateTime<Utc>) -> i64 {
+        match g.check(username, ip, now) {
+            Ok(()) => 0,
+            Err(AppError::RateLimited(msg)) => {
+                msg.split_whitespace().find_map(|s| s.parse().ok()).unwrap()
+            }
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_backoff_and_lockout() {
+        let g = guard();
+        let t0 = start();
+
+        assert_eq!(0, wait(&g, "admin", None, t0));
+
+        g.failure("admin", None, t0);
+        assert_eq!(2, wait(&g, "admin", None, t0));
+        assert_eq!(0, wait(&g, "admin", None, t0 + Duration::seconds(2)));
+
+        let t1 = t0 + Duration::seconds(2);
+        g.failure("Admin", None, t1);
+        assert_eq!(4, wait(&g, "admin", None, t1));
+
+        let t2 = t1 + Duration::seconds(4);
+        g.failure("admin", None, t2);
+        assert_eq!(600, wait(&g, "admin", None, t2));
+        assert_eq!(
+            1,
+            wait(
+                &g,
+                "admin",
+                None,
+                t2 + Duration::seconds(599) + Duration::milliseconds(1)
+            )
+        );
+        assert_eq!(0, wait(&g, "admin", None, t2 + Duration::seconds(600)));
+
+        // 锁定结束后重新计数
+        let t3 = t2 + Duration::seconds(600);
+        g.failure("admin", None, t3);
+        assert_eq!(2, wait(&g, "admin", None, t3));
+
+        // 其他账号不受影响
+        assert_eq!(0, wait(&g, "other", None, t3));
+    }
+
+    #[test]
+    fn test_ip_and_unlock() {
+        let g = guard();
+        let t0 = start();
+        let ip = Some("10.0.0.1");
+
+        for (i, name) in ["a", "b", "c", "d", "e"].iter().enumerate() {
+            g.failure(name, ip, t0 + Duration::seconds(10 * i as i64));
+        }
+        let t1 = t0 + Duration::seconds(40);
+        assert_eq!(600, wait(&g, "f", ip, t1));
+        assert_eq!(0, wait(&g, "f", Some("10.0.0.2"), t1));
+
+        // 成功登录不清除 ip 的计数
+        g.success("a", ip);
+        assert_eq!(600, wait(&g, "a", ip, t1));
+        g.unlock_ip("10.0.0.1");
+        assert_eq!(0, wait(&g, "a", ip, t1));
+
+        g.failure("x", None, t1);
+        g.failure("x", None, t1);
+        g.failure("x", None, t1);
+        assert_eq!(600, wait(&g, "x", None, t1));
+        g.unlock("X");
+        assert_eq!(0, wait(&g, "x", None, t1));
+    }
+
+    #[test]
+    fn test_failures_expire() {
+        let g = guard();
+        let t0 = start();
+
+        g.failure("admin", None, t0);
+        g.failure("admin", None, t0 + Duration::seconds(10));
+        // 超过 lockout_secs 没有失败, 重新计数, 不会锁定
+        let t1 = t0 + Duration::seconds(700);
+        g.failure("admin", None, t1);
+        assert_eq!(2, wait(&g, "admin", None, t1));
+    }
+
+    #[test]
+    fn test_reserve() {
+        let g = guard();
+        let t0 = start();
+        let ip = Some("10.0.0.1");
+
+        // 没有失败记录时, 同一账号或 ip 的并发登录都可以通过
+        let a = g.reserve("admin", ip, t0).unwrap();
+        let b = g.reserve("admin", ip, t0).unwrap();
+        let c = g.reserve("other", ip, t0).unwrap();
+        a.success();
+        b.success();
+        c.success();
+        assert_eq!(0, wait(&g, "admin", ip, t0));
+
+        // 有失败记录时, 前一个请求出结果前不能并发
+        g.reserve("admin", ip, t0).unwrap().failure(t0);
+        assert_eq!(2, wait(&g, "admin", None, t0));
+        assert_eq!(2, wait(&g, "other", ip, t0));
+
+        let t1 = t0 + Duration::seconds(2);
+        let a = g.reserve("admin", ip, t1).unwrap();
+        assert!(g.reserve("admin", None, t1).is_err());
+        assert!(g.reserve("other", ip, t1).is_err());
+        assert!(g.reserve("other", None, t1).is_ok());
+        // 被丢弃 (如请求取消) 时释放占用
+        drop(a);
+        let a = g.reserve("admin", ip, t1).unwrap();
+
+        // 成功后账号清零, ip 只释放占用
+        a.success();
+        assert_eq!(0, wait(&g, "admin", None, t1));
+        let b = g.reserve("other", ip, t1).unwrap();
+        assert!(g.reserve("admin", ip, t1).is_err());
+        drop(b);
+        assert!(g.reserve("admin", ip, t1).is_ok());
+    }
+}
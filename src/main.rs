@@ -2,24 +2,44 @@
 
 use actix_identity::{CookieIdentityPolicy, Identity, IdentityService};
 use actix_web::{
+    cookie::SameSite,
     error::{InternalError, JsonPayloadError, QueryPayloadError},
+    http::header,
     middleware::{self, Logger},
-    post, web, App, Error, HttpRequest, HttpResponse, HttpServer, Responder,
+    post, web, App, Error, HttpRequest, HttpResponse, HttpServer, Responder, ResponseError,
 };
 
-use http_response::{response_error, response_ok, response_success};
+use config::{Config, Opt};
+use error::{AppError, AppResult};
+use http_response::{response_ok, response_success};
 use log::info;
 use params::LoginParams;
-use rand::Rng;
-use serde_json::Value;
+use serde_json::{json, Value};
+use structopt::StructOpt;
 
 mod api;
+mod apk;
+mod artifact;
+mod audit;
+mod cache;
+mod ci_token;
 mod config;
+mod config_io;
+mod config_type;
+mod config_value;
+mod error;
 mod http_response;
+mod login_guard;
 mod mysql;
 mod params;
+mod password;
+mod rbac;
+mod render;
+mod rollout;
+mod session;
 mod sha;
-mod utils;
+mod snapshot;
+mod vcs;
 
 #[post("/test/post")]
 async fn hello(req_body: String) -> impl Responder {
@@ -27,65 +47,207 @@ async fn hello(req_body: String) -> impl Responder {
     response_ok(Value::String("hello world".to_string()))
 }
 
-async fn login(id: Identity, params: web::Json<LoginParams>) -> HttpResponse {
-    let result = mysql::login(&params.username, &params.password).await;
-    match result {
-        Ok(_) => {
-            id.remember(params.username.clone());
-            response_success("登录成功")
+async fn login(
+    id: Identity,
+    req: HttpRequest,
+    params: web::Json<LoginParams>,
+) -> AppResult<HttpResponse> {
+    let client_ip = api::client_ip(&req);
+    let attempt = login_guard::reserve(&params.username, client_ip.as_deref()).await?;
+
+    let user = match mysql::login(&params.username, &params.password).await {
+        Ok(user) => user,
+        Err(AppError::Auth(msg)) => {
+            login_guard::record_failure(attempt, &msg).await?;
+            return Err(AppError::Auth(msg));
         }
-        Err(err) => response_error(&err),
-    }
+        // 没有校验结果, attempt 丢弃时释放占用
+        Err(e) => return Err(e),
+    };
+    login_guard::record_success(attempt).await?;
+
+    let user_agent = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok());
+    let identity = session::create(&params.username, client_ip.as_deref(), user_agent).await?;
+
+    id.remember(identity);
+    Ok(response_ok(json!({
+        "msg": "登录成功",
+        "must_change_password": user.must_change_password.as_deref() == Some("Y"),
+    })))
 }
 
-async fn logout(id: Identity) -> HttpResponse {
+async fn logout(id: Identity) -> AppResult<HttpResponse> {
+    if let Some(identity) = id.identity() {
+        if session::is_store_enabled() {
+            session::revoke(&identity).await?;
+        }
+    }
     id.forget();
-    response_success("退出成功")
+    Ok(response_success("退出成功"))
 }
 
 fn post_error(err: JsonPayloadError, _: &HttpRequest) -> Error {
-    let res = format!("{}", err);
-    InternalError::from_response(err, response_error(&res)).into()
+    let res = AppError::Validation(format!("{}", err));
+    InternalError::from_response(err, res.error_response()).into()
 }
 
 fn query_error(err: QueryPayloadError, _: &HttpRequest) -> Error {
-    let res = format!("{}", err);
-    InternalError::from_response(err, response_error(&res)).into()
+    let res = AppError::Validation(format!("{}", err));
+    InternalError::from_response(err, res.error_response()).into()
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    config::init_config();
+    if let Err(err) = config::init_config(&Opt::from_args()) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+
+    let config = Config::get();
+    http_response::set_compat_mode(config.server.compat_response);
 
     // 数据库初始化
-    let _ = crate::mysql::init(crate::mysql::URL).await;
+    if let Err(err) =
+        crate::mysql::init(&config.database.url, config.database.max_connections).await
+    {
+        eprintln!("数据库连接失败: {}", err);
+        std::process::exit(1);
+    }
 
-    let private_key = rand::thread_rng().gen::<[u8; 32]>();
+    if let Err(err) = login_guard::restore().await {
+        eprintln!("恢复登录记录失败: {}", err);
+        std::process::exit(1);
+    }
+
+    let private_key = match session::load_key(&config.session) {
+        Ok(key) => key,
+        Err(err) => {
+            eprintln!("配置错误: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if config.session.store {
+        config::get_runtime().spawn(async {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+            loop {
+                interval.tick().await;
+                if let Err(err) = session::purge_expired().await {
+                    info!("purge sessions err = {}", err);
+                }
+            }
+        });
+    }
+
+    if config.trash.retention_days > 0 {
+        config::get_runtime().spawn(async {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+            loop {
+                interval.tick().await;
+                if let Err(err) = api::trash::purge_expired().await {
+                    info!("purge trash err = {}", err);
+                }
+            }
+        });
+    }
 
     HttpServer::new(move || {
+        let mut policy = CookieIdentityPolicy::new(&private_key)
+            .name(config.cookie.name.as_str())
+            .path(config.cookie.path.as_str())
+            .secure(config.cookie.secure)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+        if let Some(domain) = &config.cookie.domain {
+            policy = policy.domain(domain.as_str());
+        }
+        if let Some(age) = config.cookie.max_age_secs {
+            policy = policy.max_age_secs(age);
+        }
+
         App::new()
             .wrap(Logger::new("%U %s %D"))
             .wrap(middleware::DefaultHeaders::new().header("Content-Type", "application/json"))
             .app_data(web::JsonConfig::default().error_handler(post_error))
             .app_data(web::QueryConfig::default().error_handler(query_error))
-            .wrap(IdentityService::new(
-                CookieIdentityPolicy::new(&private_key)
-                    .name("JSESSIONID")
-                    .secure(false),
-            ))
+            .wrap(IdentityService::new(policy))
             .service(hello)
             // .service(web::resource("/jpm")
             .service(
                 web::scope("/jpm")
                     .service(web::resource("/login").route(web::post().to(login)))
                     .service(web::resource("/logout").route(web::post().to(logout)))
+                    .service(api::rbac::me)
+                    .service(api::rbac::roles)
+                    .service(api::rbac::user_roles)
+                    .service(api::rbac::assign)
+                    .service(api::rbac::unassign)
+                    .service(api::users::disable)
+                    .service(api::users::enable)
+                    .service(api::users::reset_password)
+                    .service(api::users::unlock)
+                    .service(api::users::change_password)
+                    .service(api::session::logout_all)
+                    .service(api::session::list)
+                    .service(api::session::revoke)
+                    .service(api::session::revoke_user)
+                    .service(api::audit::list)
+                    .service(api::ci::create_token)
+                    .service(api::ci::tokens)
+                    .service(api::ci::revoke_token)
+                    .service(api::ci::create_build)
+                    .service(api::ci::update_build_status)
+                    .service(api::build_diff::diff)
+                    .service(api::changelog::detail)
+                    .service(api::config_io::export)
+                    .service(api::config_io::import)
+                    .service(api::config_value::effective)
+                    .service(api::config_value::update)
+                    .service(api::config_value::copy)
+                    .service(api::config_snapshot::freeze)
+                    .service(api::config_snapshot::by_project)
+                    .service(api::config_snapshot::detail)
+                    .service(api::config_snapshot::update)
+                    .service(api::config_snapshot::remove)
+                    .service(api::render::preview)
+                    .service(api::render::download)
+                    .service(api::artifact::upload)
+                    .service(api::artifact::list)
+                    .service(api::artifact::download)
+                    .service(api::artifact::remove)
+                    .service(api::release::promote_build)
+                    .service(api::release::approve_build)
+                    .service(api::release::demote_build)
+                    .service(api::release::current_release)
+                    .service(api::rollout::detail)
+                    .service(api::rollout::update)
+                    .service(api::rollout::control)
+                    .service(api::stats::builds)
+                    .service(api::stats::results)
+                    .service(api::stats::builders)
+                    .service(api::stats::release_interval)
+                    .service(api::stats::latest)
+                    .service(api::trash::query)
+                    .service(api::trash::restore)
+                    .service(api::trash::purge)
                     .service(api::update)
                     .service(api::delete)
                     .service(api::query),
             )
-            .service(actix_files::Files::new("/", "web").index_file("index.html"))
+            .service(
+                web::scope("/ota")
+                    .service(api::ota::check)
+                    .service(api::ota::download),
+            )
+            .service(
+                actix_files::Files::new("/", config.server.static_dir.as_str())
+                    .index_file("index.html"),
+            )
     })
-    .bind(format!("0.0.0.0:{}", 8080))?
+    .bind(config.server.bind.as_str())?
     .run()
     .await
 }
@@ -0,0 +1,183 @@
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    error::{AppError, AppResult},
+    sha::sha256_encode,
+};
+
+/// 密码的存储方式, 存储的字符串自带前缀, 据此选择校验方式
+pub trait PasswordScheme: Send + Sync {
+    fn name(&self) -> &'static str;
+    /// 该存储格式是否由本方式产生
+    fn recognizes(&self, stored: &str) -> bool;
+    fn hash(&self, password: &str) -> AppResult<String>;
+    /// salt 是 sys_user.salt, 只有旧格式用到
+    fn verify(&self, password: &str, salt: &str, stored: &str) -> bool;
+}
+
+/// 新密码使用的 Argon2id, PHC 格式 `$argon2id$v=19$m=..,t=..,p=..$salt$hash`
+pub struct Argon2idScheme;
+
+impl PasswordScheme for Argon2idScheme {
+    fn name(&self) -> &'static str {
+        "argon2id"
+    }
+
+    fn recognizes(&self, stored: &str) -> bool {
+        stored.starts_with("$argon2id$")
+    }
+
+    fn hash(&self, password: &str) -> AppResult<String> {
+        let salt = SaltString::generate(rand::thread_rng());
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(AppError::internal)?;
+        Ok(hash.to_string())
+    }
+
+    fn verify(&self, password: &str, _salt: &str, stored: &str) -> bool {
+        match PasswordHash::new(stored) {
+            Ok(hash) => Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+/// 旧格式, `sha::sha256_encode` 的 64 位 hex, 没有前缀
+pub struct LegacySha256Scheme;
+
+impl PasswordScheme for LegacySha256Scheme {
+    fn name(&self) -> &'static str {
+        "sha256"
+    }
+
+    fn recognizes(&self, stored: &str) -> bool {
+        stored.len() == 64 && stored.chars().all(|c| c.is_ascii_hexdigit())
+    }
+
+    fn hash(&self, _password: &str) -> AppResult<String> {
+        Err(AppError::Internal("旧格式只用于校验".to_string()))
+    }
+
+    fn verify(&self, password: &str, salt: &str, stored: &str) -> bool {
+        let pd = sha256_encode(password, salt);
+        pd.as_bytes()
+            .ct_eq(stored.to_ascii_lowercase().as_bytes())
+            .into()
+    }
+}
+
+static CURRENT: Argon2idScheme = Argon2idScheme;
+static SCHEMES: [&dyn PasswordScheme; 2] = [&Argon2idScheme, &LegacySha256Scheme];
+
+#[derive(Debug, PartialEq)]
+pub enum Verification {
+    Invalid,
+    Valid,
+    /// 密码正确, 但不是当前的存储方式, 需要重新 hash
+    ValidNeedsRehash,
+}
+
+/// 新密码的最短长度
+pub const MIN_PASSWORD_LEN: usize = 6;
+
+pub fn check_strength(password: &str) -> AppResult<()> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(AppError::Validation(format!(
+            "密码至少需要 {} 位",
+            MIN_PASSWORD_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// 随机字母数字串, 用于 sys_user.salt 和重置后的临时密码
+pub fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+/// 用当前方式 hash 新密码
+pub fn hash(password: &str) -> AppResult<String> {
+    CURRENT.hash(password)
+}
+
+pub fn verify(password: &str, salt: &str, stored: &str) -> Verification {
+    let scheme = match SCHEMES.iter().find(|s| s.recognizes(stored)) {
+        Some(s) => s,
+        None => return Verification::Invalid,
+    };
+
+    if !scheme.verify(password, salt, stored) {
+        Verification::Invalid
+    } else if scheme.name() == CURRENT.name() {
+        Verification::Valid
+    } else {
+        Verification::ValidNeedsRehash
+    }
+}
+
+/// Argon2 计算较慢, 在阻塞线程中执行, 不占用处理请求的线程
+pub async fn hash_blocking(password: &str) -> AppResult<String> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || hash(&password))
+        .await
+        .map_err(AppError::internal)?
+}
+
+pub async fn verify_blocking(password: &str, salt: &str, stored: &str) -> AppResult<Verification> {
+    let (password, salt, stored) = (password.to_string(), salt.to_string(), stored.to_string());
+    tokio::task::spawn_blocking(move || verify(&password, &salt, &stored))
+        .await
+        .map_err(AppError::internal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_strength, hash, random_string, verify, Verification};
+
+    #[test]
+    fn test_argon2id() {
+        let stored = hash("666666").unwrap();
+        assert!(stored.starts_with("$argon2id$"));
+        assert_ne!(stored, hash("666666").unwrap());
+
+        assert_eq!(Verification::Valid, verify("666666", "", &stored));
+        assert_eq!(Verification::Invalid, verify("666667", "", &stored));
+    }
+
+    #[test]
+    fn test_legacy() {
+        let stored = "ce059eb08c180b4ea4233d4c65af00ec57f6b38d90400f2f6d1f6d9f11db2b67";
+
+        assert_eq!(
+            Verification::ValidNeedsRehash,
+            verify("666666", "jmfjNfLUHYWx2Kjj1JFx", stored)
+        );
+        assert_eq!(
+            Verification::Invalid,
+            verify("666666", "other salt", stored)
+        );
+        assert_eq!(Verification::Invalid, verify("666666", "", "plain"));
+    }
+
+    #[test]
+    fn test_helpers() {
+        assert!(check_strength("12345").is_err());
+        assert!(check_strength("123456").is_ok());
+
+        let s = random_string(20);
+        assert_eq!(20, s.len());
+        assert!(s.chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+    api::CurrentUser,
+    config::Config,
+    error::{AppError, AppResult},
+    mysql_query, sql_args,
+};
+
+/// 通配符, 可以用在 sys_role_permission 的 page 和 op 上
+pub const ANY: &str = "*";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Query,
+    Update,
+    Delete,
+    Admin,
+}
+
+impl Op {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Op::Query => "query",
+            Op::Update => "update",
+            Op::Delete => "delete",
+            Op::Admin => "admin",
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize, Clone)]
+pub struct Grant {
+    pub role_code: String,
+    pub project_id: Option<i64>,
+    pub page: String,
+    pub op: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Permissions {
+    /// 配置文件里的管理员, 不受角色限制, 用来分配最初的角色
+    pub superuser: bool,
+    pub grants: Vec<Grant>,
+}
+
+fn matches(pattern: &str, value: &str) -> bool {
+    pattern == ANY || pattern == value
+}
+
+impl Permissions {
+    /// 全局授权对所有项目生效, 项目授权只对该项目生效
+    pub fn allows(&self, page: &str, op: Op, project: Option<i64>) -> bool {
+        if self.superuser {
+            return true;
+        }
+
+        self.grants.iter().any(|g| {
+            matches(&g.page, page)
+                && matches(&g.op, op.as_str())
+                && (g.project_id.is_none() || g.project_id == project)
+        })
+    }
+
+    /// 给前端的权限列表, `{"global": {page: [op]}, "projects": {id: {page: [op]}}}`
+    pub fn to_value(&self) -> Value {
+        let mut global: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        let mut projects: BTreeMap<i64, BTreeMap<String, BTreeSet<String>>> = BTreeMap::new();
+
+        if self.superuser {
+            global
+                .entry(ANY.to_string())
+                .or_default()
+                .insert(ANY.to_string());
+        }
+
+        for g in &self.grants {
+            let pages = match g.project_id {
+                Some(id) => projects.entry(id).or_default(),
+                None => &mut global,
+            };
+            pages
+                .entry(g.page.clone())
+                .or_default()
+                .insert(g.op.clone());
+        }
+
+        let roles: BTreeSet<(String, Option<i64>)> = self
+            .grants
+            .iter()
+            .map(|g| (g.role_code.clone(), g.project_id))
+            .collect();
+
+        json!({
+            "superuser": self.superuser,
+            "roles": roles
+                .into_iter()
+                .map(|(role, project_id)| json!({ "role": role, "project_id": project_id }))
+                .collect::<Vec<Value>>(),
+            "global": global,
+            "projects": projects,
+        })
+    }
+}
+
+pub async fn permissions(user: &CurrentUser) -> AppResult<Permissions> {
+    let mut grants: Vec<Grant> = Vec::new();
+
+    mysql_query!(
+        Grant,
+        grants,
+        r#"select r.code as role_code, ur.project_id, p.page, p.op
+from sys_user_role ur
+join sys_role r on r.id = ur.role_id
+join sys_role_permission p on p.role_id = r.id
+where ur.username = ?"#,
+        &sql_args![&user.username]
+    )?;
+
+    Ok(Permissions {
+        superuser: Config::get().auth.admins.contains(&user.username),
+        grants,
+    })
+}
+
+pub async fn require(
+    user: &CurrentUser,
+    page: &str,
+    op: Op,
+    project: Option<i64>,
+) -> AppResult<()> {
+    if permissions(user).await?.allows(page, op, project) {
+        Ok(())
+    } else {
+        Err(AppError::Forbidden(format!(
+            "没有权限: {} {}",
+            page,
+            op.as_str()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Grant, Op, Permissions};
+
+    fn grant(role: &str, project_id: Option<i64>, page: &str, op: &str) -> Grant {
+        Grant {
+            role_code: role.to_string(),
+            project_id,
+            page: page.to_string(),
+            op: op.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_allows() {
+        let p = Permissions {
+            superuser: false,
+            grants: vec![
+                grant("viewer", None, "*", "query"),
+                grant("developer", Some(3), "versionbuildrecord", "update"),
+                grant("developer", Some(3), "project", "*"),
+            ],
+        };
+
+        assert!(p.allows("project", Op::Query, None));
+        assert!(p.allows("mdm45", Op::Query, Some(7)));
+        assert!(!p.allows("mdm45", Op::Update, None));
+        assert!(p.allows("versionbuildrecord", Op::Update, Some(3)));
+        assert!(!p.allows("versionbuildrecord", Op::Update, Some(4)));
+        assert!(!p.allows("versionbuildrecord", Op::Update, None));
+        assert!(p.allows("project", Op::Delete, Some(3)));
+        assert!(!p.allows("session", Op::Admin, None));
+
+        let admin = Permissions {
+            superuser: true,
+            grants: Vec::new(),
+        };
+        assert!(admin.allows("session", Op::Admin, None));
+    }
+
+    #[test]
+    fn test_to_value() {
+        let p = Permissions {
+            superuser: false,
+            grants: vec![
+                grant("viewer", None, "*", "query"),
+                grant("developer", Some(3), "project", "update"),
+            ],
+        };
+
+        let v = p.to_value();
+        assert_eq!("query", v["global"]["*"][0]);
+        assert_eq!("update", v["projects"]["3"]["project"][0]);
+        assert_eq!(2, v["roles"].as_array().unwrap().len());
+    }
+}
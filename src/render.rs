@@ -0,0 +1,526 @@
+use std::{collections::HashMap, convert::TryFrom, fmt::Write};
+
+use serde_json::{Map, Value};
+
+use crate::{
+    config_type::ConfigType,
+    error::{AppError, AppResult, FieldError},
+};
+
+/// 生成的配置文件格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Java .properties
+    Properties,
+    /// Android res/values
+    Xml,
+    Json,
+    /// build.gradle 中的 buildConfigField
+    Gradle,
+}
+
+pub const FORMATS: [&str; 4] = ["properties", "xml", "json", "gradle"];
+
+impl Format {
+    pub fn parse(s: &str) -> AppResult<Format> {
+        match s {
+            "properties" => Ok(Format::Properties),
+            "xml" => Ok(Format::Xml),
+            "json" => Ok(Format::Json),
+            "gradle" => Ok(Format::Gradle),
+            _ => Err(AppError::Validation(format!(
+                "format {} 不支持, 可选 {:?}",
+                s, FORMATS
+            ))),
+        }
+    }
+
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Format::Properties => "config.properties",
+            Format::Xml => "config.xml",
+            Format::Json => "config.json",
+            Format::Gradle => "config.gradle",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Properties | Format::Gradle => "text/plain; charset=utf-8",
+            Format::Xml => "application/xml; charset=utf-8",
+            Format::Json => "application/json; charset=utf-8",
+        }
+    }
+}
+
+/// 按 config_type 决定输出的类型, 不认识的类型按字符串处理
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Bool,
+    Int,
+    Json,
+    Str,
+}
+
+impl Kind {
+    /// enum, url 和不认识的类型按字符串输出
+    pub fn of(config_type: &str) -> Kind {
+        match ConfigType::parse(config_type) {
+            Some(ConfigType::Bool) => Kind::Bool,
+            Some(ConfigType::Int) => Kind::Int,
+            Some(ConfigType::Json) => Kind::Json,
+            _ => Kind::Str,
+        }
+    }
+}
+
+/// 一个配置项和选择的值
+#[derive(Debug, Clone)]
+pub struct Item {
+    pub key: String,
+    pub name: Option<String>,
+    pub kind: Kind,
+    pub module: String,
+    pub category: String,
+    pub sort: i64,
+    pub value: String,
+}
+
+enum Typed {
+    Bool(bool),
+    Int(i64),
+    Json(Value),
+    Str(String),
+}
+
+fn typed(item: &Item) -> Result<Typed, String> {
+    let v = item.value.trim();
+    match item.kind {
+        Kind::Bool => match v.to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" => Ok(Typed::Bool(true)),
+            "false" | "0" | "no" => Ok(Typed::Bool(false)),
+            _ => Err(format!("{} 不是 bool: {}", item.key, item.value)),
+        },
+        Kind::Int => v
+            .parse::<i64>()
+            .map(Typed::Int)
+            .map_err(|_| format!("{} 不是整数: {}", item.key, item.value)),
+        Kind::Json => serde_json::from_str(v)
+            .map(Typed::Json)
+            .map_err(|_| format!("{} 不是 json: {}", item.key, item.value)),
+        Kind::Str => Ok(Typed::Str(item.value.clone())),
+    }
+}
+
+/// 按 module, category, sort 排序后生成
+pub fn render(format: Format, items: &[Item]) -> AppResult<String> {
+    let mut items: Vec<&Item> = items.iter().collect();
+    items.sort_by(|a, b| {
+        (&a.module, &a.category, a.sort, &a.key).cmp(&(&b.module, &b.category, b.sort, &b.key))
+    });
+
+    let mut values = Vec::with_capacity(items.len());
+    let mut errors = Vec::new();
+    for item in &items {
+        match typed(item) {
+            Ok(v) => values.push(v),
+            Err(e) => errors.push(e),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(AppError::Validation(errors.join("; ")));
+    }
+
+    check_names(format, &items)?;
+
+    let pairs: Vec<(&Item, Typed)> = items.into_iter().zip(values).collect();
+    Ok(match format {
+        Format::Properties => properties(&pairs),
+        Format::Xml => android_xml(&pairs),
+        Format::Json => json(&pairs),
+        Format::Gradle => gradle(&pairs),
+    })
+}
+
+/// xml 和 gradle 用 resource_name 作为名字, 不同的 key 不能生成同一个名字
+fn check_names(format: Format, items: &[&Item]) -> AppResult<()> {
+    let name_of = match format {
+        Format::Xml => resource_name,
+        Format::Gradle => |key: &str| resource_name(key).to_ascii_uppercase(),
+        Format::Properties | Format::Json => return Ok(()),
+    };
+
+    let mut names: HashMap<String, &str> = HashMap::new();
+    let mut errors = Vec::new();
+    for item in items {
+        let name = name_of(&item.key);
+        match names.get(&name) {
+            Some(other) if *other != item.key => errors.push(FieldError::new(
+                &item.key,
+                format!("和 {} 生成的名字 {} 重复", other, name),
+            )),
+            Some(_) => {}
+            None => {
+                names.insert(name, &item.key);
+            }
+        }
+    }
+    AppError::check_fields(errors)
+}
+
+fn group_of(item: &Item) -> String {
+    format!("{} / {}", item.module, item.category)
+}
+
+/// 每个 module / category 之前输出一次 comment 生成的注释
+fn grouped(
+    out: &mut String,
+    pairs: &[(&Item, Typed)],
+    comment: impl Fn(&mut String, &str),
+    line: impl Fn(&mut String, &Item, &Typed),
+) {
+    let mut last: Option<String> = None;
+    for (item, v) in pairs {
+        let group = group_of(item);
+        if last.as_ref() != Some(&group) {
+            comment(out, &group);
+            last = Some(group);
+        }
+        line(out, item, v);
+    }
+}
+
+fn display(v: &Typed) -> String {
+    match v {
+        Typed::Bool(b) => b.to_string(),
+        Typed::Int(i) => i.to_string(),
+        Typed::Json(j) => j.to_string(),
+        Typed::Str(s) => s.clone(),
+    }
+}
+
+/// properties 文件按 ISO-8859-1 读取, 非 ascii 字符写成 \uXXXX
+fn escape_properties(s: &str, is_key: bool) -> String {
+    let mut out = String::new();
+    for (i, c) in s.chars().enumerate() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{c}' => out.push_str("\\f"),
+            ' ' if is_key || i == 0 => out.push_str("\\ "),
+            '=' | ':' | '#' | '!' if is_key => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_ascii() && !c.is_ascii_control() => out.push(c),
+            c => {
+                let mut buf = [0u16; 2];
+                for u in c.encode_utf16(&mut buf) {
+                    let _ = write!(out, "\\u{:04x}", u);
+                }
+            }
+        }
+    }
+    out
+}
+
+fn properties(pairs: &[(&Item, Typed)]) -> String {
+    let mut out = String::new();
+    grouped(
+        &mut out,
+        pairs,
+        |out, group| {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            let _ = writeln!(out, "# {}", escape_properties(group, false));
+        },
+        |out, item, v| {
+            let _ = writeln!(
+                out,
+                "{}={}",
+                escape_properties(&item.key, true),
+                escape_properties(&display(v), false)
+            );
+        },
+    );
+    out
+}
+
+/// 资源名只能包含字母, 数字和下划线, 不能以数字开头
+pub fn resource_name(key: &str) -> String {
+    let mut name: String = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+/// xml 注释中不能出现 --, 连续的 - 合并成一个
+fn xml_comment(s: &str) -> String {
+    let mut out = String::new();
+    for c in escape_xml(s).chars() {
+        if c != '-' || !out.ends_with('-') {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// android 字符串资源还需要转义引号, 反斜杠和开头的 @ ?
+fn escape_android_string(s: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in s.chars().enumerate() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\'' => out.push_str("\\'"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '@' | '?' if i == 0 => {
+                out.push('\\');
+                out.push(c);
+            }
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn android_xml(pairs: &[(&Item, Typed)]) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<resources>\n");
+    grouped(
+        &mut out,
+        pairs,
+        |out, group| {
+            let _ = writeln!(out, "    <!-- {} -->", xml_comment(group));
+        },
+        |out, item, v| {
+            let (tag, text) = match v {
+                Typed::Bool(b) => ("bool", b.to_string()),
+                Typed::Int(i) if i32::try_from(*i).is_ok() => ("integer", i.to_string()),
+                _ => ("string", escape_android_string(&display(v))),
+            };
+            let _ = writeln!(
+                out,
+                "    <{} name=\"{}\">{}</{}>",
+                tag,
+                resource_name(&item.key),
+                text,
+                tag
+            );
+        },
+    );
+    out.push_str("</resources>\n");
+    out
+}
+
+/// { module: { category: { key: value } } }
+fn json(pairs: &[(&Item, Typed)]) -> String {
+    let mut root = Map::new();
+    for (item, v) in pairs {
+        let value = match v {
+            Typed::Bool(b) => Value::Bool(*b),
+            Typed::Int(i) => Value::from(*i),
+            Typed::Json(j) => j.clone(),
+            Typed::Str(s) => Value::String(s.clone()),
+        };
+        let module = root
+            .entry(item.module.clone())
+            .or_insert_with(|| Value::Object(Map::new()));
+        if let Value::Object(m) = module {
+            let category = m
+                .entry(item.category.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(c) = category {
+                c.insert(item.key.clone(), value);
+            }
+        }
+    }
+    let mut out = serde_json::to_string_pretty(&Value::Object(root)).unwrap_or_default();
+    out.push('\n');
+    out
+}
+
+/// java 字符串字面量
+fn java_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// groovy 单引号字符串, 不会替换 $
+fn groovy_string(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn gradle(pairs: &[(&Item, Typed)]) -> String {
+    let mut out = String::new();
+    grouped(
+        &mut out,
+        pairs,
+        |out, group| {
+            let _ = writeln!(out, "// {}", group.replace(&['\r', '\n'][..], " "));
+        },
+        |out, item, v| {
+            let (ty, literal) = match v {
+                Typed::Bool(b) => ("boolean", b.to_string()),
+                Typed::Int(i) if i32::try_from(*i).is_ok() => ("int", i.to_string()),
+                Typed::Int(i) => ("long", format!("{}L", i)),
+                _ => ("String", java_string(&display(v))),
+            };
+            let _ = writeln!(
+                out,
+                "buildConfigField {}, {}, {}",
+                groovy_string(ty),
+                groovy_string(&resource_name(&item.key).to_ascii_uppercase()),
+                groovy_string(&literal)
+            );
+        },
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, resource_name, Format, Item, Kind};
+    use crate::error::AppError;
+
+    fn item(key: &str, kind: Kind, module: &str, sort: i64, value: &str) -> Item {
+        Item {
+            key: key.to_string(),
+            name: None,
+            kind,
+            module: module.to_string(),
+            category: "c".to_string(),
+            sort,
+            value: value.to_string(),
+        }
+    }
+
+    fn items() -> Vec<Item> {
+        vec![
+            item("server.url", Kind::Str, "net", 2, "http://a.com/?q='x'"),
+            item("debug", Kind::Bool, "app", 1, "1"),
+            item("timeout", Kind::Int, "net", 1, "30"),
+            item("title", Kind::Str, "app", 2, "你好 @a"),
+        ]
+    }
+
+    #[test]
+    fn test_kind() {
+        assert_eq!(Kind::Bool, Kind::of("Boolean"));
+        assert_eq!(Kind::Int, Kind::of("int"));
+        assert_eq!(Kind::Str, Kind::of("text"));
+        assert!(Format::parse("yaml").is_err());
+        assert_eq!("_1a_b", resource_name("1a.b"));
+    }
+
+    #[test]
+    fn test_properties() {
+        let text = render(Format::Properties, &items()).unwrap();
+        assert_eq!(
+            "# app / c\ndebug=true\ntitle=\\u4f60\\u597d @a\n\n# net / c\ntimeout=30\nserver.url=http://a.com/?q='x'\n",
+            text
+        );
+    }
+
+    #[test]
+    fn test_xml() {
+        let text = render(Format::Xml, &items()).unwrap();
+        assert!(text.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<resources>\n    <!-- app / c -->\n    <bool name=\"debug\">true</bool>\n"));
+        assert!(text.contains("<string name=\"title\">你好 @a</string>"));
+        assert!(text.contains("<integer name=\"timeout\">30</integer>"));
+        assert!(text.contains("<string name=\"server_url\">http://a.com/?q=\\'x\\'</string>"));
+        assert!(text.ends_with("</resources>\n"));
+    }
+
+    #[test]
+    fn test_json() {
+        let text = render(Format::Json, &items()).unwrap();
+        let v: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(true, v["app"]["c"]["debug"]);
+        assert_eq!(30, v["net"]["c"]["timeout"]);
+    }
+
+    #[test]
+    fn test_gradle() {
+        let text = render(Format::Gradle, &items()).unwrap();
+        assert!(text.contains("buildConfigField 'boolean', 'DEBUG', 'true'\n"));
+        assert!(text.contains("buildConfigField 'int', 'TIMEOUT', '30'\n"));
+        assert!(text
+            .contains("buildConfigField 'String', 'SERVER_URL', '\"http://a.com/?q=\\'x\\'\"'\n"));
+    }
+
+    #[test]
+    fn test_invalid_value() {
+        let mut v = items();
+        v.push(item("retry", Kind::Int, "net", 3, "x"));
+        let err = render(Format::Json, &v).unwrap_err();
+        assert!(err.message().contains("retry"));
+    }
+
+    #[test]
+    fn test_comment() {
+        let mut v = items();
+        v[0].module = "a---b-".to_string();
+        let text = render(Format::Xml, &v).unwrap();
+        assert!(text.contains("    <!-- a-b- / c -->\n"));
+        assert!(!text.contains("--->"));
+
+        v[0].module = "a\r\n// b".to_string();
+        let text = render(Format::Gradle, &v).unwrap();
+        assert!(text.contains("// a  // b / c\n"));
+    }
+
+    #[test]
+    fn test_name_collision() {
+        let mut v = items();
+        v.push(item("server_url", Kind::Str, "app", 3, "x"));
+        assert!(render(Format::Properties, &v).is_ok());
+        match render(Format::Xml, &v).unwrap_err() {
+            AppError::Fields(errors) => {
+                assert_eq!(1, errors.len());
+                assert!(errors[0].message.contains("server_url"));
+            }
+            e => panic!("{:?}", e),
+        }
+
+        // gradle 的名字转成大写, 只有大小写不同也会重复
+        let mut v = items();
+        v.push(item("Debug", Kind::Bool, "net", 3, "0"));
+        assert!(render(Format::Xml, &v).is_ok());
+        assert!(render(Format::Gradle, &v).is_err());
+    }
+}
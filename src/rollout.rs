@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{AppError, AppResult},
+    mysql::{execute_args, Arg},
+    mysql_query,
+    sha::sha256_hex,
+    sql_args,
+};
+
+pub const ACTION_UPDATE: &str = "update";
+pub const ACTION_PAUSE: &str = "pause";
+pub const ACTION_RESUME: &str = "resume";
+pub const ACTION_HALT: &str = "halt";
+
+/// paused 可以恢复, halted 不能恢复, 需要发布新的构建
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RolloutState {
+    Active,
+    Paused,
+    Halted,
+}
+
+impl RolloutState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RolloutState::Active => "active",
+            RolloutState::Paused => "paused",
+            RolloutState::Halted => "halted",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<RolloutState> {
+        match s {
+            "active" => Some(RolloutState::Active),
+            "paused" => Some(RolloutState::Paused),
+            "halted" => Some(RolloutState::Halted),
+            _ => None,
+        }
+    }
+
+    /// pause/resume/halt 之后的状态
+    pub fn apply(&self, action: &str) -> AppResult<RolloutState> {
+        let next = match (self, action) {
+            (RolloutState::Active, ACTION_PAUSE) => RolloutState::Paused,
+            (RolloutState::Paused, ACTION_RESUME) => RolloutState::Active,
+            (RolloutState::Active, ACTION_HALT) | (RolloutState::Paused, ACTION_HALT) => {
+                RolloutState::Halted
+            }
+            _ => {
+                return Err(AppError::Conflict(format!(
+                    "灰度状态为 {}, 不能 {}",
+                    self.as_str(),
+                    action
+                )))
+            }
+        };
+        Ok(next)
+    }
+}
+
+/// 发布的灰度规则, 没有设置时全量发布
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rollout {
+    /// 0 - 100
+    pub percentage: u8,
+    pub state: RolloutState,
+    /// 白名单设备总是可以收到, 黑名单设备总是收不到
+    pub allow_devices: Vec<String>,
+    pub deny_devices: Vec<String>,
+}
+
+impl Default for Rollout {
+    fn default() -> Self {
+        Rollout {
+            percentage: 100,
+            state: RolloutState::Active,
+            allow_devices: Vec::new(),
+            deny_devices: Vec::new(),
+        }
+    }
+}
+
+/// 设备在某个构建中的分桶, 0 - 99, 同一设备在同一构建中不变, 调高比例时已收到的设备不会被排除
+pub fn bucket(build_uuid: &str, device_id: &str) -> u8 {
+    let h = sha256_hex(&format!("{}:{}", build_uuid, device_id));
+    (u32::from_str_radix(&h[..8], 16).unwrap_or(0) % 100) as u8
+}
+
+impl Rollout {
+    /// 暂停和终止时只有白名单可以收到, 没有设备 id 的客户端只能收到全量发布
+    pub fn allows(&self, build_uuid: &str, device_id: Option<&str>) -> bool {
+        if self.state == RolloutState::Halted {
+            return false;
+        }
+
+        let device_id = match device_id {
+            Some(d) => d,
+            None => return self.state == RolloutState::Active && self.percentage >= 100,
+        };
+        if self.deny_devices.iter().any(|d| d == device_id) {
+            return false;
+        }
+        if self.allow_devices.iter().any(|d| d == device_id) {
+            return true;
+        }
+        self.state == RolloutState::Active && bucket(build_uuid, device_id) < self.percentage
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RolloutParams {
+    pub percentage: Option<u8>,
+    pub allow_devices: Option<Vec<String>>,
+    pub deny_devices: Option<Vec<String>>,
+}
+
+fn clean_devices(devices: &[String]) -> AppResult<Vec<String>> {
+    let mut list: Vec<String> = devices
+        .iter()
+        .map(|d| d.trim().to_string())
+        .filter(|d| !d.is_empty())
+        .collect();
+    if let Some(d) = list.iter().find(|d| d.chars().count() > 128) {
+        return Err(AppError::Validation(format!("设备 id {} 太长", d)));
+    }
+    list.sort();
+    list.dedup();
+    Ok(list)
+}
+
+impl RolloutParams {
+    /// 没有传的字段保持不变
+    pub fn apply(&self, current: &Rollout) -> AppResult<Rollout> {
+        if current.state == RolloutState::Halted {
+            return Err(AppError::Conflict("灰度已终止, 不能修改".to_string()));
+        }
+
+        let mut next = current.clone();
+        if let Some(p) = self.percentage {
+            if p > 100 {
+                return Err(AppError::Validation(
+                    "percentage 只能是 0 - 100".to_string(),
+                ));
+            }
+            next.percentage = p;
+        }
+        if let Some(list) = &self.allow_devices {
+            next.allow_devices = clean_devices(list)?;
+        }
+        if let Some(list) = &self.deny_devices {
+            next.deny_devices = clean_devices(list)?;
+        }
+        Ok(next)
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+struct RolloutRow {
+    build_id: i64,
+    percentage: i64,
+    state: String,
+    allow_devices: Option<String>,
+    deny_devices: Option<String>,
+}
+
+fn parse_devices(s: &Option<String>) -> Vec<String> {
+    s.as_deref()
+        .and_then(|s| serde_json::from_str(s).ok())
+        .unwrap_or_default()
+}
+
+impl From<&RolloutRow> for Rollout {
+    fn from(row: &RolloutRow) -> Self {
+        Rollout {
+            percentage: row.percentage.clamp(0, 100) as u8,
+            state: RolloutState::parse(&row.state).unwrap_or(RolloutState::Active),
+            allow_devices: parse_devices(&row.allow_devices),
+            deny_devices: parse_devices(&row.deny_devices),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize, Clone)]
+pub struct RolloutHistory {
+    pub id: i64,
+    pub build_id: i64,
+    pub action: String,
+    pub percentage: i64,
+    pub state: String,
+    pub allow_devices: Option<String>,
+    pub deny_devices: Option<String>,
+    pub username: String,
+    pub create_time: DateTime<Utc>,
+}
+
+const SELECT_ROLLOUT: &str =
+    "select build_id, percentage, state, allow_devices, deny_devices from tb_release_rollout";
+
+/// 按构建 id 读取, 没有设置的构建不在结果中
+pub async fn list(build_ids: &[i64]) -> AppResult<HashMap<i64, Rollout>> {
+    if build_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let marks = vec!["?"; build_ids.len()].join(", ");
+    let args: Vec<Arg> = build_ids.iter().map(|id| Arg::from(*id)).collect();
+
+    let mut data: Vec<RolloutRow> = Vec::new();
+    mysql_query!(
+        RolloutRow,
+        data,
+        &format!("{} where build_id in ({})", SELECT_ROLLOUT, marks),
+        &args
+    )?;
+    Ok(data
+        .iter()
+        .map(|r| (r.build_id, Rollout::from(r)))
+        .collect())
+}
+
+pub async fn find(build_id: i64) -> AppResult<Rollout> {
+    Ok(list(&[build_id])
+        .await?
+        .remove(&build_id)
+        .unwrap_or_default())
+}
+
+pub async fn history(build_id: i64) -> AppResult<Vec<RolloutHistory>> {
+    let mut data: Vec<RolloutHistory> = Vec::new();
+    mysql_query!(
+        RolloutHistory,
+        data,
+        r#"select id, build_id, action, percentage, state, allow_devices, deny_devices, username, create_time
+from tb_release_rollout_history where build_id = ? order by id desc"#,
+        &sql_args![build_id]
+    )?;
+    Ok(data)
+}
+
+fn devices_text(devices: &[String]) -> String {
+    serde_json::to_string(devices).unwrap_or_else(|_| "[]".to_string())
+}
+
+/// 保存规则并记录历史
+pub async fn save(build_id: i64, rollout: &Rollout, action: &str, user: &str) -> AppResult<()> {
+    let allow = devices_text(&rollout.allow_devices);
+    let deny = devices_text(&rollout.deny_devices);
+
+    execute_args(
+        r#"insert into tb_release_rollout (build_id, percentage, state, allow_devices, deny_devices, update_user, update_time)
+values (?, ?, ?, ?, ?, ?, NOW())
+on duplicate key update percentage = values(percentage), state = values(state), allow_devices = values(allow_devices),
+deny_devices = values(deny_devices), update_user = values(update_user), update_time = values(update_time)"#,
+        &sql_args![
+            build_id,
+            rollout.percentage as i64,
+            rollout.state.as_str(),
+            &allow,
+            &deny,
+            user
+        ],
+    )
+    .await?;
+
+    execute_args(
+        r#"insert into tb_release_rollout_history (build_id, action, percentage, state, allow_devices, deny_devices, username, create_time)
+values (?, ?, ?, ?, ?, ?, ?, NOW())"#,
+        &sql_args![
+            build_id,
+            action,
+            rollout.percentage as i64,
+            rollout.state.as_str(),
+            &allow,
+            &deny,
+            user
+        ],
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        bucket, Rollout, RolloutParams, RolloutState, ACTION_HALT, ACTION_PAUSE, ACTION_RESUME,
+    };
+
+    #[test]
+    fn test_bucket() {
+        assert_eq!(bucket("b1", "d1"), bucket("b1", "d1"));
+
+        // 分布大致均匀
+        let n = (0..1000)
+            .filter(|i| bucket("b1", &format!("device-{}", i)) < 30)
+            .count();
+        assert!(n > 200 && n < 400, "{}", n);
+    }
+
+    #[test]
+    fn test_allows() {
+        let mut r = Rollout::default();
+        assert!(r.allows("b1", None));
+        assert!(r.allows("b1", Some("d1")));
+
+        r.percentage = 0;
+        r.allow_devices = vec!["tester".to_string()];
+        r.deny_devices = vec!["bad".to_string()];
+        assert!(!r.allows("b1", None));
+        assert!(!r.allows("b1", Some("d1")));
+        assert!(r.allows("b1", Some("tester")));
+
+        r.percentage = 100;
+        assert!(!r.allows("b1", Some("bad")));
+        assert!(r.allows("b1", Some("d1")));
+
+        // 调高比例时已经收到的设备仍然可以收到
+        r.percentage = 10;
+        let included: Vec<String> = (0..200)
+            .map(|i| format!("device-{}", i))
+            .filter(|d| r.allows("b1", Some(d)))
+            .collect();
+        r.percentage = 50;
+        assert!(included.iter().all(|d| r.allows("b1", Some(d))));
+
+        r.state = RolloutState::Paused;
+        assert!(!r.allows("b1", Some(&included[0])));
+        assert!(r.allows("b1", Some("tester")));
+
+        r.state = RolloutState::Halted;
+        assert!(!r.allows("b1", Some("tester")));
+    }
+
+    #[test]
+    fn test_state() {
+        use RolloutState::*;
+
+        assert_eq!(Paused, Active.apply(ACTION_PAUSE).unwrap());
+        assert_eq!(Active, Paused.apply(ACTION_RESUME).unwrap());
+        assert_eq!(Halted, Paused.apply(ACTION_HALT).unwrap());
+        assert!(Active.apply(ACTION_RESUME).is_err());
+        assert!(Halted.apply(ACTION_RESUME).is_err());
+        assert!(Halted.apply(ACTION_HALT).is_err());
+    }
+
+    #[test]
+    fn test_params() {
+        let params = RolloutParams {
+            percentage: Some(20),
+            allow_devices: Some(vec![" a ".to_string(), "".to_string(), "a".to_string()]),
+            deny_devices: None,
+        };
+        let r = params.apply(&Rollout::default()).unwrap();
+        assert_eq!(20, r.percentage);
+        assert_eq!(vec!["a".to_string()], r.allow_devices);
+
+        let halted = Rollout {
+            state: RolloutState::Halted,
+            ..Rollout::default()
+        };
+        assert!(params.apply(&halted).is_err());
+
+        let params = RolloutParams {
+            percentage: Some(101),
+            allow_devices: None,
+            deny_devices: None,
+        };
+        assert!(params.apply(&Rollout::default()).is_err());
+    }
+}
@@ -0,0 +1,215 @@
+use std::{fs, path::Path};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use rand::Rng;
+use serde::Serialize;
+
+use crate::{
+    config::{Config, SessionConfig},
+    error::{AppError, AppResult},
+    mysql::{execute_args, SqlBuilder},
+    mysql_find_one, mysql_query, sql_args,
+};
+
+/// cookie 签名密钥最短长度
+const MIN_KEY_LEN: usize = 32;
+
+#[derive(sqlx::FromRow, Debug, Serialize, Clone)]
+pub struct Session {
+    pub session_id: String,
+    pub username: String,
+    pub create_time: DateTime<Utc>,
+    pub last_seen_time: DateTime<Utc>,
+    pub expire_time: DateTime<Utc>,
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+pub fn decode_key(key: &str) -> Result<Vec<u8>, String> {
+    let bytes = hex::decode(key.trim()).map_err(|e| format!("不是有效的 hex: {}", e))?;
+    if bytes.len() < MIN_KEY_LEN {
+        return Err(format!("至少需要 {} 字节", MIN_KEY_LEN));
+    }
+    Ok(bytes)
+}
+
+fn generate_key() -> Vec<u8> {
+    let mut key = vec![0u8; 64];
+    rand::thread_rng().fill(&mut key[..]);
+    key
+}
+
+/// 读取 cookie 签名密钥, 配置的 key 优先, 其次 key_file, 文件不存在时生成并保存
+pub fn load_key(config: &SessionConfig) -> Result<Vec<u8>, String> {
+    if let Some(key) = &config.key {
+        return decode_key(key).map_err(|e| format!("session.key {}", e));
+    }
+
+    let path = match &config.key_file {
+        Some(p) => p,
+        None => {
+            warn!("没有配置 session.key 或 session.key_file, 重启后所有用户需要重新登录");
+            return Ok(generate_key());
+        }
+    };
+
+    if path.exists() {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+        return decode_key(&text).map_err(|e| format!("{} {}", path.display(), e));
+    }
+
+    let key = generate_key();
+    write_key(path, &key).map_err(|e| format!("写入 {} 失败: {}", path.display(), e))?;
+    info!("session key generated at {}", path.display());
+    Ok(key)
+}
+
+fn write_key(path: &Path, key: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, hex::encode(key))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+
+    Ok(())
+}
+
+pub fn is_store_enabled() -> bool {
+    Config::get().session.store
+}
+
+/// 登录成功后生成 cookie 中保存的值, 开启服务端会话时是 session_id, 否则是用户名
+pub async fn create(
+    username: &str,
+    client_ip: Option<&str>,
+    user_agent: Option<&str>,
+) -> AppResult<String> {
+    if !is_store_enabled() {
+        return Ok(username.to_string());
+    }
+
+    let session_id = hex::encode(rand::thread_rng().gen::<[u8; 32]>());
+    execute_args(
+        "insert into sys_session (session_id, username, create_time, last_seen_time, expire_time, client_ip, user_agent)
+values (?, ?, NOW(), NOW(), DATE_ADD(NOW(), INTERVAL ? SECOND), ?, ?)",
+        &sql_args![
+            &session_id,
+            username,
+            Config::get().session.ttl_secs,
+            client_ip,
+            user_agent
+        ],
+    )
+    .await?;
+
+    Ok(session_id)
+}
+
+/// 从 cookie 中的值得到用户名, 会话过期或被注销时返回 Auth 错误
+pub async fn resolve(identity: &str) -> AppResult<String> {
+    if !is_store_enabled() {
+        return Ok(identity.to_string());
+    }
+
+    let username = mysql_find_one!(
+        String,
+        "select username from sys_session where session_id = ? and revoked is null
+and expire_time > NOW() and last_seen_time > DATE_SUB(NOW(), INTERVAL ? SECOND)",
+        &sql_args![identity, Config::get().session.idle_timeout_secs]
+    )
+    .map_err(|err| match err {
+        AppError::NotFound(_) => AppError::Auth("登录已过期, 请重新登录".to_string()),
+        e => e,
+    })?;
+
+    execute_args(
+        "UPDATE sys_session SET last_seen_time = NOW() where session_id = ?",
+        &sql_args![identity],
+    )
+    .await?;
+
+    Ok(username)
+}
+
+pub async fn revoke(session_id: &str) -> AppResult<()> {
+    execute_args(
+        "UPDATE sys_session SET revoked = 'Y' where session_id = ?",
+        &sql_args![session_id],
+    )
+    .await
+}
+
+pub async fn revoke_user(username: &str) -> AppResult<()> {
+    execute_args(
+        "UPDATE sys_session SET revoked = 'Y' where username = ? and revoked is null",
+        &sql_args![username],
+    )
+    .await
+}
+
+/// 未过期也未注销的会话
+pub async fn list(username: Option<&str>) -> AppResult<Vec<Session>> {
+    let mut w = SqlBuilder::new(
+        "revoked is null and expire_time > NOW() and last_seen_time > DATE_SUB(NOW(), INTERVAL ? SECOND)",
+    );
+    w.bind(Config::get().session.idle_timeout_secs);
+
+    if let Some(username) = username {
+        w.push(" and username = ?").bind(username);
+    }
+
+    let sql = format!(
+        r#"select session_id, username, create_time, last_seen_time, expire_time, client_ip, user_agent
+from sys_session where {} order by last_seen_time desc"#,
+        w.sql()
+    );
+
+    let mut data: Vec<Session> = Vec::new();
+    mysql_query!(Session, data, &sql, w.args())?;
+    Ok(data)
+}
+
+/// 清理已过期和已注销的会话
+pub async fn purge_expired() -> AppResult<()> {
+    execute_args(
+        "DELETE FROM sys_session where revoked = 'Y' or expire_time < NOW() or last_seen_time < DATE_SUB(NOW(), INTERVAL ? SECOND)",
+        &sql_args![Config::get().session.idle_timeout_secs],
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::SessionConfig;
+
+    #[test]
+    fn test_decode_key() {
+        assert!(super::decode_key("zz").is_err());
+        assert!(super::decode_key(&"ab".repeat(16)).is_err());
+        assert_eq!(32, super::decode_key(&"ab".repeat(32)).unwrap().len());
+    }
+
+    #[test]
+    fn test_load_key_file() {
+        let path = std::env::temp_dir().join(format!("session-{}.key", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let config = SessionConfig {
+            key_file: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let first = super::load_key(&config).unwrap();
+        let second = super::load_key(&config).unwrap();
+        assert_eq!(first, second);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
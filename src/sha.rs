@@ -32,6 +32,15 @@ pub fn sha256_encode(password: &str, salt: &str) -> String {
     to_hex_string((&buf).to_vec())
 }
 
+/// 一次 sha256, 用于保存 api token 等随机串
+pub fn sha256_hex(data: &str) -> String {
+    sha256_bytes_hex(data.as_bytes())
+}
+
+pub fn sha256_bytes_hex(data: &[u8]) -> String {
+    to_hex_string(Sha256::digest(data).to_vec())
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -41,5 +50,9 @@ mod tests {
             "ce059eb08c180b4ea4233d4c65af00ec57f6b38d90400f2f6d1f6d9f11db2b67",
             super::sha256_encode("666666", "jmfjNfLUHYWx2Kjj1JFx")
         );
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            super::sha256_hex("abc")
+        );
     }
 }
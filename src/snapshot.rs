@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// 构建时保存的配置文件 (config_detail_file), 按 key 展开后的值
+pub type ConfigValues = BTreeMap<String, String>;
+
+/// json 对象按 `a.b.c` 展开, 否则按 properties (`key=value`) 解析
+pub fn parse(text: &str) -> ConfigValues {
+    let text = text.trim_start_matches('\u{feff}');
+    let mut values = ConfigValues::new();
+    match serde_json::from_str::<Value>(text) {
+        Ok(v @ Value::Object(_)) => flatten("", &v, &mut values),
+        _ => parse_properties(text, &mut values),
+    }
+    values
+}
+
+fn flatten(prefix: &str, v: &Value, out: &mut ConfigValues) {
+    match v {
+        Value::Object(map) => {
+            for (k, v) in map {
+                let key = if prefix.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", prefix, k)
+                };
+                flatten(&key, v, out);
+            }
+        }
+        Value::String(s) => {
+            out.insert(prefix.to_string(), s.clone());
+        }
+        Value::Null => {
+            out.insert(prefix.to_string(), String::new());
+        }
+        _ => {
+            out.insert(prefix.to_string(), v.to_string());
+        }
+    }
+}
+
+/// 只支持单行的 `key=value` 和 `key: value`, `#` 和 `!` 开头为注释
+fn parse_properties(text: &str, out: &mut ConfigValues) {
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+            continue;
+        }
+        let (k, v) = match line.find(['=', ':']) {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => (line, ""),
+        };
+        out.insert(k.trim().to_string(), v.trim().to_string());
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Entry {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Change {
+    pub key: String,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Serialize, PartialEq, Default)]
+pub struct ConfigDiff {
+    pub added: Vec<Entry>,
+    pub removed: Vec<Entry>,
+    pub changed: Vec<Change>,
+    pub unchanged: usize,
+}
+
+/// 按 key 比较, 结果按 key 排序
+pub fn diff(from: &ConfigValues, to: &ConfigValues) -> ConfigDiff {
+    let mut d = ConfigDiff::default();
+    for (k, v) in from {
+        match to.get(k) {
+            None => d.removed.push(Entry {
+                key: k.clone(),
+                value: v.clone(),
+            }),
+            Some(t) if t != v => d.changed.push(Change {
+                key: k.clone(),
+                from: v.clone(),
+                to: t.clone(),
+            }),
+            Some(_) => d.unchanged += 1,
+        }
+    }
+    for (k, v) in to {
+        if !from.contains_key(k) {
+            d.added.push(Entry {
+                key: k.clone(),
+                value: v.clone(),
+            });
+        }
+    }
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, parse, Change, Entry};
+
+    #[test]
+    fn test_parse() {
+        let v = parse(r#"{"app": {"name": "a", "debug": false}, "url": null}"#);
+        assert_eq!("a", v["app.name"]);
+        assert_eq!("false", v["app.debug"]);
+        assert_eq!("", v["url"]);
+
+        let v = parse("# comment\n! comment\nserver.url = http://a:80\nname: b\nflag\n");
+        assert_eq!(3, v.len());
+        assert_eq!("http://a:80", v["server.url"]);
+        assert_eq!("b", v["name"]);
+        assert_eq!("", v["flag"]);
+    }
+
+    #[test]
+    fn test_diff() {
+        let from = parse("a=1\nb=2\nc=3");
+        let to = parse("b=2\nc=4\nd=5");
+        let d = diff(&from, &to);
+        assert_eq!(
+            vec![Entry {
+                key: "d".to_string(),
+                value: "5".to_string()
+            }],
+            d.added
+        );
+        assert_eq!(
+            vec![Entry {
+                key: "a".to_string(),
+                value: "1".to_string()
+            }],
+            d.removed
+        );
+        assert_eq!(
+            vec![Change {
+                key: "c".to_string(),
+                from: "3".to_string(),
+                to: "4".to_string()
+            }],
+            d.changed
+        );
+        assert_eq!(1, d.unchanged);
+    }
+}
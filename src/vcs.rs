@@ -0,0 +1,431 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::OnceCell;
+use quick_xml::{events::Event, Reader};
+use serde::{Deserialize, Serialize};
+use tokio::{process::Command, sync::Mutex};
+
+use crate::{
+    cache::TtlCache,
+    config::{ChangelogConfig, Config},
+    error::{AppError, AppResult},
+    sha::sha256_hex,
+};
+
+/// 缓存的不同区间的个数上限
+const CACHE_ENTRIES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Vcs {
+    Svn,
+    Git,
+}
+
+/// 地址像 git 仓库, 或者是本地的 git 仓库时用 git, 否则用 svn
+pub fn detect(url: &str) -> Vcs {
+    let url = url.trim().trim_end_matches('/');
+    if url.ends_with(".git") || url.starts_with("git@") || url.starts_with("git://") {
+        return Vcs::Git;
+    }
+    if let Some(path) = url.strip_prefix("file://") {
+        let path = Path::new(path);
+        if path.join("HEAD").is_file() || path.join(".git").exists() {
+            return Vcs::Git;
+        }
+    }
+    Vcs::Svn
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub revision: String,
+    pub author: String,
+    pub date: Option<DateTime<Utc>>,
+    pub message: String,
+}
+
+/// 版本号会作为命令行参数, 只允许常见的字符
+fn check_revision(rev: &str) -> AppResult<()> {
+    let ok = !rev.is_empty()
+        && rev.len() <= 64
+        && !rev.starts_with('-')
+        && rev
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-' || c == '/');
+    if ok {
+        Ok(())
+    } else {
+        Err(AppError::Validation(format!("版本号 {} 无效", rev)))
+    }
+}
+
+fn check_url(url: &str) -> AppResult<()> {
+    if url.trim().is_empty() || url.starts_with('-') {
+        return Err(AppError::Validation(format!("版本库地址 {} 无效", url)));
+    }
+    Ok(())
+}
+
+async fn run(cmd: &mut Command, timeout: Duration) -> AppResult<String> {
+    cmd.stdin(Stdio::null()).kill_on_drop(true);
+    let out = tokio::time::timeout(timeout, cmd.output())
+        .await
+        .map_err(|_| AppError::internal(format!("{:?} timeout", cmd)))?
+        .map_err(AppError::internal)?;
+    if !out.status.success() {
+        return Err(AppError::internal(format!(
+            "{:?} failed: {}",
+            cmd,
+            String::from_utf8_lossy(&out.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// 解析 `svn log --xml` 的输出
+pub fn parse_svn_xml(xml: &str) -> AppResult<Vec<LogEntry>> {
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+
+    let mut entries = Vec::new();
+    let mut current: Option<LogEntry> = None;
+    let mut field: Option<Vec<u8>> = None;
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf).map_err(AppError::internal)? {
+            Event::Start(e) => match e.name() {
+                b"logentry" => {
+                    let mut revision = String::new();
+                    for a in e.attributes() {
+                        let a = a.map_err(AppError::internal)?;
+                        if a.key == b"revision" {
+                            revision = a
+                                .unescape_and_decode_value(&reader)
+                                .map_err(AppError::internal)?;
+                        }
+                    }
+                    current = Some(LogEntry {
+                        revision,
+                        author: String::new(),
+                        date: None,
+                        message: String::new(),
+                    });
+                }
+                name => field = Some(name.to_vec()),
+            },
+            Event::Text(t) => {
+                if let (Some(entry), Some(f)) = (current.as_mut(), field.as_deref()) {
+                    let text = t.unescape_and_decode(&reader).map_err(AppError::internal)?;
+                    match f {
+                        b"author" => entry.author = text,
+                        b"date" => entry.date = text.parse::<DateTime<Utc>>().ok(),
+                        b"msg" => entry.message = text,
+                        _ => {}
+                    }
+                }
+            }
+            Event::End(e) => {
+                if e.name() == b"logentry" {
+                    entries.extend(current.take());
+                }
+                field = None;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(entries)
+}
+
+const GIT_FORMAT: &str = "--format=%H%x1f%an%x1f%aI%x1f%B%x1e";
+
+/// 解析 GIT_FORMAT 格式的 `git log` 输出
+pub fn parse_git_log(text: &str) -> Vec<LogEntry> {
+    text.split('\u{1e}')
+        .filter_map(|record| {
+            let mut fields = record.trim_start_matches('\n').splitn(4, '\u{1f}');
+            let revision = fields.next().filter(|s| !s.is_empty())?;
+            Some(LogEntry {
+                revision: revision.to_string(),
+                author: fields.next().unwrap_or_default().to_string(),
+                date: fields
+                    .next()
+                    .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+                    .map(|d| d.with_timezone(&Utc)),
+                message: fields.next().unwrap_or_default().trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// svn 的 (from, to] 区间, 新的在前
+async fn svn_log(
+    config: &ChangelogConfig,
+    url: &str,
+    from: &str,
+    to: &str,
+) -> AppResult<Vec<LogEntry>> {
+    let parse = |r: &str| {
+        r.trim_start_matches('r')
+            .parse::<u64>()
+            .map_err(|_| AppError::Validation(format!("svn 版本号 {} 无效", r)))
+    };
+    let (from, to) = (parse(from)?, parse(to)?);
+    if from >= to {
+        return Ok(Vec::new());
+    }
+
+    let xml = run(
+        Command::new("svn")
+            .arg("log")
+            .arg("--xml")
+            .arg("--non-interactive")
+            .arg("--limit")
+            .arg(config.max_entries.to_string())
+            .arg("-r")
+            .arg(format!("{}:{}", to, from + 1))
+            .arg("--")
+            .arg(url),
+        Duration::from_secs(config.timeout_secs),
+    )
+    .await?;
+    parse_svn_xml(&xml)
+}
+
+/// 同时只有一个 git 命令更新镜像
+static GIT_LOCK: OnceCell<Mutex<()>> = OnceCell::new();
+
+/// git 需要本地的镜像仓库, 没有时 clone, 缺少提交时 fetch
+async fn git_mirror(config: &ChangelogConfig, url: &str, revs: &[&str]) -> AppResult<PathBuf> {
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let dir = config.dir.join(&sha256_hex(url)[..16]);
+    let _guard = GIT_LOCK.get_or_init(|| Mutex::new(())).lock().await;
+
+    if !dir.join("HEAD").is_file() {
+        tokio::fs::create_dir_all(&config.dir)
+            .await
+            .map_err(AppError::internal)?;
+        run(
+            Command::new("git")
+                .arg("clone")
+                .arg("--mirror")
+                .arg("--quiet")
+                .arg("--")
+                .arg(url)
+                .arg(&dir),
+            timeout,
+        )
+        .await?;
+        return Ok(dir);
+    }
+
+    for rev in revs {
+        let found = run(
+            Command::new("git")
+                .arg("--git-dir")
+                .arg(&dir)
+                .arg("cat-file")
+                .arg("-e")
+                .arg(format!("{}^{{commit}}", rev)),
+            timeout,
+        )
+        .await
+        .is_ok();
+        if !found {
+            run(
+                Command::new("git")
+                    .arg("--git-dir")
+                    .arg(&dir)
+                    .arg("remote")
+                    .arg("update")
+                    .arg("--prune"),
+                timeout,
+            )
+            .await?;
+            break;
+        }
+    }
+    Ok(dir)
+}
+
+/// git 的 from..to, 新的在前
+async fn git_log(
+    config: &ChangelogConfig,
+    url: &str,
+    from: &str,
+    to: &str,
+) -> AppResult<Vec<LogEntry>> {
+    let dir = git_mirror(config, url, &[from, to]).await?;
+    let text = run(
+        Command::new("git")
+            .arg("--git-dir")
+            .arg(&dir)
+            .arg("log")
+            .arg(GIT_FORMAT)
+            .arg("-n")
+            .arg(config.max_entries.to_string())
+            .arg(format!("{}..{}", from, to)),
+        Duration::from_secs(config.timeout_secs),
+    )
+    .await?;
+    Ok(parse_git_log(&text))
+}
+
+/// 不含 from, 包含 to 的提交记录
+pub async fn log_with(
+    config: &ChangelogConfig,
+    url: &str,
+    from: &str,
+    to: &str,
+) -> AppResult<Vec<LogEntry>> {
+    check_url(url)?;
+    check_revision(from)?;
+    check_revision(to)?;
+    match detect(url) {
+        Vcs::Svn => svn_log(config, url, from, to).await,
+        Vcs::Git => git_log(config, url, from, to).await,
+    }
+}
+
+static CACHE: OnceCell<TtlCache<Vec<LogEntry>>> = OnceCell::new();
+
+/// 同一区间的结果不会变, 按 [changelog] cache_secs 缓存
+pub async fn log(url: &str, from: &str, to: &str) -> AppResult<Vec<LogEntry>> {
+    let config = &Config::get().changelog;
+    let cache =
+        CACHE.get_or_init(|| TtlCache::new(Duration::from_secs(config.cache_secs), CACHE_ENTRIES));
+
+    let key = format!("{}\n{}\n{}", url, from, to);
+    if let Some(v) = cache.get(&key) {
+        return Ok(v);
+    }
+    let v = log_with(config, url, from, to).await?;
+    cache.insert(key, v.clone());
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, process::Command};
+
+    use super::{detect, log_with, parse_git_log, parse_svn_xml, Vcs};
+    use crate::config::ChangelogConfig;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Vcs::Git, detect("https://example.com/app.git"));
+        assert_eq!(Vcs::Git, detect("git@example.com:app.git"));
+        assert_eq!(Vcs::Svn, detect("svn://example.com/app/trunk"));
+        assert_eq!(Vcs::Svn, detect("https://example.com/svn/app/trunk"));
+    }
+
+    #[test]
+    fn test_parse_svn_xml() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<log>
+<logentry revision="12">
+<author>alice</author>
+<date>2021-03-01T08:00:00.123456Z</date>
+<msg>fix &lt;crash&gt;
+second line</msg>
+</logentry>
+<logentry revision="11">
+<author>bob</author>
+<date>2021-02-28T08:00:00.000000Z</date>
+<msg></msg>
+</logentry>
+</log>"#;
+        let entries = parse_svn_xml(xml).unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!("12", entries[0].revision);
+        assert_eq!("alice", entries[0].author);
+        assert_eq!("fix <crash>\nsecond line", entries[0].message);
+        assert!(entries[0].date.is_some());
+        assert_eq!("", entries[1].message);
+    }
+
+    #[test]
+    fn test_parse_git_log() {
+        let text = "abc\u{1f}alice\u{1f}2021-03-01T08:00:00+08:00\u{1f}subject\n\nbody\n\u{1e}\ndef\u{1f}bob\u{1f}bad\u{1f}x\n\u{1e}\n";
+        let entries = parse_git_log(text);
+        assert_eq!(2, entries.len());
+        assert_eq!("abc", entries[0].revision);
+        assert_eq!("subject\n\nbody", entries[0].message);
+        assert_eq!(
+            "2021-03-01T00:00:00Z",
+            entries[0]
+                .date
+                .unwrap()
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        );
+        assert_eq!(None, entries[1].date);
+    }
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let out = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(args)
+            .env("GIT_AUTHOR_NAME", "tester")
+            .env("GIT_AUTHOR_EMAIL", "tester@example.com")
+            .env("GIT_COMMITTER_NAME", "tester")
+            .env("GIT_COMMITTER_EMAIL", "tester@example.com")
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{:?}", out);
+        String::from_utf8(out.stdout).unwrap().trim().to_string()
+    }
+
+    #[actix_rt::test]
+    async fn test_git_log() {
+        if Command::new("git").arg("--version").output().is_err() {
+            return;
+        }
+
+        let root = std::env::temp_dir().join(format!("vcs_test_{}", std::process::id()));
+        let repo = root.join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        git(&repo, &["init", "-q"]);
+        let mut revs = Vec::new();
+        for i in 0..3 {
+            git(
+                &repo,
+                &[
+                    "commit",
+                    "-q",
+                    "--allow-empty",
+                    "-m",
+                    &format!("change {}", i),
+                ],
+            );
+            revs.push(git(&repo, &["rev-parse", "HEAD"]));
+        }
+
+        let config = ChangelogConfig {
+            dir: root.join("mirror"),
+            ..ChangelogConfig::default()
+        };
+        let url = format!("file://{}", repo.display());
+        assert_eq!(Vcs::Git, detect(&url));
+
+        let entries = log_with(&config, &url, &revs[0], &revs[2]).await.unwrap();
+        let messages: Vec<&str> = entries.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(vec!["change 2", "change 1"], messages);
+        assert_eq!("tester", entries[0].author);
+
+        // 镜像中没有的提交会先 fetch
+        git(&repo, &["commit", "-q", "--allow-empty", "-m", "change 3"]);
+        let head = git(&repo, &["rev-parse", "HEAD"]);
+        let entries = log_with(&config, &url, &revs[2], &head).await.unwrap();
+        assert_eq!(1, entries.len());
+
+        assert!(log_with(&config, &url, "--all", &head).await.is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}